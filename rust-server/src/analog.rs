@@ -0,0 +1,253 @@
+use std::{collections::HashMap, io, time::Duration};
+
+use log::{debug, error, info};
+use tokio::{
+    sync::mpsc::Sender,
+    time::{interval, Instant},
+};
+
+use crate::channels::ChannelsUIEndpoint;
+use crate::metrics::METRICS;
+use crate::supervisor::Supervisor;
+use crate::xpc_types::UICommand;
+
+mod adc;
+mod config;
+mod filter;
+mod linux;
+
+use adc::{open_adc, Adc};
+use config::{read_analog_config, AnalogInput};
+use filter::AnalogFilter;
+
+pub fn run_analog(
+    supervisor: &Supervisor,
+    channels: &[ChannelsUIEndpoint],
+    config_file: &str,
+) -> Result<(), io::Error> {
+    let config = read_analog_config(config_file)?;
+    if let Some(input) = config.inputs.iter().find(|input| {
+        input
+            .connection
+            .as_ref()
+            .is_some_and(|c| !channels.iter().any(|e| &e.connection == c))
+    }) {
+        return Err(io::Error::other(format!(
+            "Unknown connection {} for dataref {}",
+            input.connection.as_deref().unwrap_or_default(),
+            input.dataref
+        )));
+    }
+
+    let endpoints = channels.to_vec();
+    let cf = config_file.to_string();
+    supervisor.spawn("analog", move || analog_main(endpoints.clone(), cf.clone()));
+
+    Ok(())
+}
+
+/// How often a failing ADC read is logged again
+const READ_ERROR_LOG_INTERVAL: Duration = Duration::from_secs(10);
+
+/// One analog input: where it is read from, how it is filtered, and
+/// where the value is sent.
+struct AnalogChannel {
+    input: AnalogInput,
+    filter: AnalogFilter,
+    ui_cmds: Sender<UICommand>,
+    /// When the read failure was last logged, while the reads fail
+    error_logged_at: Option<Duration>,
+}
+
+impl AnalogChannel {
+    fn new(input: AnalogInput, ui_cmds: Sender<UICommand>) -> AnalogChannel {
+        AnalogChannel {
+            filter: AnalogFilter::new(&input.filter),
+            input,
+            ui_cmds,
+            error_logged_at: None,
+        }
+    }
+
+    /// Reads the ADC, and returns the dataref write if the value should
+    /// be sent.
+    fn poll(&mut self, adc: &mut dyn Adc, now: Duration) -> Result<Option<UICommand>, io::Error> {
+        let raw = adc.read(self.input.channel)?;
+        Ok(self
+            .filter
+            .update(raw, now)
+            .map(|value| UICommand::SetDataref {
+                dataref: self.input.dataref.clone(),
+                value,
+            }))
+    }
+
+    /// Returns true if the read failure at `now` is to be logged: the
+    /// first one, and then once per `READ_ERROR_LOG_INTERVAL`.
+    fn read_failed(&mut self, now: Duration) -> bool {
+        match self.error_logged_at {
+            Some(logged_at) if now - logged_at < READ_ERROR_LOG_INTERVAL => false,
+            _ => {
+                self.error_logged_at = Some(now);
+                true
+            }
+        }
+    }
+
+    /// Returns true if the reads had been failing
+    fn read_succeeded(&mut self) -> bool {
+        self.error_logged_at.take().is_some()
+    }
+}
+
+async fn analog_main(
+    endpoints: Vec<ChannelsUIEndpoint>,
+    config_file: String,
+) -> Result<(), io::Error> {
+    let config = read_analog_config(&config_file)?;
+
+    let mut adcs = HashMap::<String, Box<dyn Adc>>::new();
+    for adc in config.adcs.iter() {
+        adcs.insert(adc.name.clone(), open_adc(&adc.chip)?);
+    }
+
+    let mut channels = config
+        .inputs
+        .iter()
+        .map(|input| {
+            let endpoint = match &input.connection {
+                Some(name) => endpoints.iter().find(|e| &e.connection == name),
+                None => endpoints.first(),
+            }
+            .ok_or_else(|| {
+                io::Error::other(format!(
+                    "Unknown connection {} for dataref {}",
+                    input.connection.as_deref().unwrap_or_default(),
+                    input.dataref
+                ))
+            })?;
+
+            Ok(AnalogChannel::new(input.clone(), endpoint.ui_cmds.clone()))
+        })
+        .collect::<Result<Vec<AnalogChannel>, io::Error>>()?;
+
+    info!("Reading {} analog inputs", channels.len());
+
+    let start = Instant::now();
+    let mut timer = interval(Duration::from_millis(config.poll_interval_ms));
+
+    loop {
+        timer.tick().await;
+        let now = start.elapsed();
+
+        for channel in channels.iter_mut() {
+            let adc = adcs.get_mut(&channel.input.adc).unwrap();
+            let command = match tokio::task::block_in_place(|| channel.poll(adc.as_mut(), now)) {
+                Ok(command) => {
+                    if channel.read_succeeded() {
+                        info!(
+                            "Reading ADC {} channel {} works again",
+                            channel.input.adc, channel.input.channel
+                        );
+                    }
+                    command
+                }
+                Err(e) => {
+                    if channel.read_failed(now) {
+                        error!(
+                            "Reading ADC {} channel {} failed: {:?}",
+                            channel.input.adc, channel.input.channel, e
+                        );
+                    }
+                    continue;
+                }
+            };
+
+            if let Some(command) = command {
+                debug!("Analog input: {:?}", command);
+                METRICS.commands_sent.inc(&["analog"]);
+                channel.ui_cmds.send(command).await.ok();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod analog_tests {
+    use std::time::Duration;
+
+    use tokio::sync::mpsc;
+
+    use super::adc::MockAdc;
+    use super::config::{AnalogInput, FilterConfig};
+    use super::AnalogChannel;
+    use crate::xpc_types::UICommand;
+
+    fn input() -> AnalogInput {
+        AnalogInput {
+            adc: String::from("levers"),
+            channel: 2,
+            dataref: String::from("sim/cockpit2/engine/actuators/mixture_ratio_all"),
+            connection: None,
+            filter: FilterConfig {
+                raw_min: 0,
+                raw_max: 1000,
+                output_min: 0.0,
+                output_max: 1.0,
+                deadzone: 0.0,
+                center_deadzone: 0.0,
+                smoothing: 1.0,
+                hysteresis: 0.01,
+                max_rate_hz: 10.0,
+            },
+        }
+    }
+
+    #[test]
+    fn poll_mock_adc() {
+        let (ui_cmds, _) = mpsc::channel(1);
+        let mut channel = AnalogChannel::new(input(), ui_cmds);
+
+        let adc = MockAdc::default();
+        let mut reader = adc.clone();
+        let poll = |reader: &mut MockAdc, channel: &mut AnalogChannel, ms| {
+            channel
+                .poll(reader, Duration::from_millis(ms))
+                .unwrap()
+                .map(|command| match command {
+                    UICommand::SetDataref { value, .. } => value,
+                    _ => panic!("Got unexpected command {:?}", command),
+                })
+        };
+
+        adc.set(2, 250);
+        assert_eq!(poll(&mut reader, &mut channel, 0), Some(0.25));
+        adc.set(2, 255);
+        assert_eq!(poll(&mut reader, &mut channel, 20), None);
+        adc.set(2, 750);
+        assert_eq!(poll(&mut reader, &mut channel, 50), None);
+        assert_eq!(poll(&mut reader, &mut channel, 100), Some(0.75));
+
+        adc.set(1, 0);
+        let mut other = channel.input.clone();
+        other.channel = 3;
+        assert!(AnalogChannel::new(other, channel.ui_cmds.clone())
+            .poll(&mut reader, Duration::ZERO)
+            .is_err());
+    }
+
+    #[test]
+    fn read_failures_logged_once_per_interval() {
+        let (ui_cmds, _) = mpsc::channel(1);
+        let mut channel = AnalogChannel::new(input(), ui_cmds);
+        let failed = |channel: &mut AnalogChannel, s| channel.read_failed(Duration::from_secs(s));
+
+        assert!(failed(&mut channel, 0));
+        assert!(!failed(&mut channel, 1));
+        assert!(!failed(&mut channel, 9));
+        assert!(failed(&mut channel, 10));
+        assert!(channel.read_succeeded());
+        assert!(!channel.read_succeeded());
+        assert!(failed(&mut channel, 11));
+    }
+}
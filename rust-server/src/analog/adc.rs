@@ -0,0 +1,64 @@
+use std::{
+    io,
+    sync::{Arc, Mutex},
+};
+
+use super::config::AdcChip;
+
+/// An analog-to-digital converter with several channels.
+pub trait Adc: Send {
+    fn read(&mut self, channel: u8) -> Result<u16, io::Error>;
+}
+
+/// ADC whose values are set by the caller. Used for testing the analog
+/// inputs without hardware.
+#[derive(Debug, Clone, Default)]
+pub struct MockAdc {
+    values: Arc<Mutex<Vec<u16>>>,
+}
+
+impl MockAdc {
+    pub fn new(values: &[u16]) -> MockAdc {
+        MockAdc {
+            values: Arc::new(Mutex::new(values.to_vec())),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn set(&self, channel: u8, value: u16) {
+        let mut values = self.values.lock().unwrap();
+        if values.len() <= channel as usize {
+            values.resize(channel as usize + 1, 0);
+        }
+        values[channel as usize] = value;
+    }
+}
+
+impl Adc for MockAdc {
+    fn read(&mut self, channel: u8) -> Result<u16, io::Error> {
+        self.values
+            .lock()
+            .unwrap()
+            .get(channel as usize)
+            .copied()
+            .ok_or_else(|| io::Error::other(format!("Mock ADC has no channel {}", channel)))
+    }
+}
+
+pub fn open_adc(chip: &AdcChip) -> Result<Box<dyn Adc>, io::Error> {
+    match chip {
+        AdcChip::Mock { values, .. } => Ok(Box::new(MockAdc::new(values))),
+        #[cfg(target_os = "linux")]
+        AdcChip::Mcp3008 { device, speed_hz } => {
+            Ok(Box::new(super::linux::Mcp3008::open(device, *speed_hz)?))
+        }
+        #[cfg(target_os = "linux")]
+        AdcChip::Ads1115 { device, address } => {
+            Ok(Box::new(super::linux::Ads1115::open(device, *address)?))
+        }
+        #[cfg(not(target_os = "linux"))]
+        _ => Err(io::Error::other(
+            "SPI and I2C ADCs are supported only on Linux",
+        )),
+    }
+}
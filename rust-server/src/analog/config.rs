@@ -0,0 +1,330 @@
+use std::{collections::HashMap, fs::File, io::BufReader};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalogConfig {
+    /// How often the ADCs are read
+    #[serde(default = "default_poll_interval")]
+    pub poll_interval_ms: u64,
+    pub adcs: Vec<AdcConfig>,
+    pub inputs: Vec<AnalogInput>,
+}
+
+fn default_poll_interval() -> u64 {
+    20
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AdcConfig {
+    pub name: String,
+    #[serde(flatten)]
+    pub chip: AdcChip,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum AdcChip {
+    /// 10-bit SPI ADC with 8 channels, e.g., device "/dev/spidev0.0"
+    #[serde(rename = "mcp3008")]
+    Mcp3008 {
+        device: String,
+        #[serde(default = "default_spi_speed")]
+        speed_hz: u32,
+    },
+    /// 16-bit I2C ADC with 4 channels, e.g., device "/dev/i2c-1"
+    #[serde(rename = "ads1115")]
+    Ads1115 {
+        device: String,
+        #[serde(default = "default_ads1115_address")]
+        address: u16,
+    },
+    /// Returns fixed values; for testing without hardware
+    #[serde(rename = "mock")]
+    Mock {
+        #[serde(default)]
+        values: Vec<u16>,
+    },
+}
+
+impl AdcChip {
+    /// The number of channels of the ADC, if it is limited
+    pub fn channels(&self) -> Option<u8> {
+        match self {
+            AdcChip::Mcp3008 { .. } => Some(8),
+            AdcChip::Ads1115 { .. } => Some(4),
+            AdcChip::Mock { .. } => None,
+        }
+    }
+}
+
+fn default_spi_speed() -> u32 {
+    1_000_000
+}
+
+fn default_ads1115_address() -> u16 {
+    0x48
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AnalogInput {
+    pub adc: String,
+    pub channel: u8,
+    /// Dataref that receives the value
+    pub dataref: String,
+    /// X-Plane connection of the dataref; the first connection by default
+    #[serde(default)]
+    pub connection: Option<String>,
+    #[serde(flatten)]
+    pub filter: FilterConfig,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct FilterConfig {
+    /// Raw ADC readings at the ends of the travel. `raw_min` can be
+    /// larger than `raw_max` if the potentiometer is wired backwards.
+    pub raw_min: u16,
+    pub raw_max: u16,
+    /// Dataref values at the ends of the travel
+    #[serde(default)]
+    pub output_min: f32,
+    #[serde(default = "default_output_max")]
+    pub output_max: f32,
+    /// Fraction of the travel at both ends that gives the end value
+    #[serde(default)]
+    pub deadzone: f32,
+    /// Fraction of the travel around the center that gives the center
+    /// value, e.g., for a trim wheel
+    #[serde(default)]
+    pub center_deadzone: f32,
+    /// Weight of a new reading in the exponential moving average, from
+    /// 0 (never changes) to 1 (no smoothing)
+    #[serde(default = "default_smoothing")]
+    pub smoothing: f32,
+    /// Smallest change of the travel fraction that is sent
+    #[serde(default)]
+    pub hysteresis: f32,
+    /// Most dataref writes per second
+    #[serde(default = "default_max_rate")]
+    pub max_rate_hz: f32,
+}
+
+fn default_output_max() -> f32 {
+    1.0
+}
+
+fn default_smoothing() -> f32 {
+    1.0
+}
+
+fn default_max_rate() -> f32 {
+    10.0
+}
+
+pub fn read_analog_config(config_file: &str) -> Result<AnalogConfig, std::io::Error> {
+    let input_file = File::open(config_file).map_err(|e| {
+        error!(
+            "Reading analog configuration file {} failed: {:?}",
+            config_file, e
+        );
+        e
+    })?;
+    let config: AnalogConfig =
+        serde_json::from_reader(BufReader::new(input_file)).map_err(|e| {
+            let s = e.to_string();
+            error!(
+                "Reading analog configuration file {} failed: {:?}",
+                config_file, s
+            );
+            std::io::Error::other(s)
+        })?;
+
+    validate_analog_config(&config)?;
+
+    Ok(config)
+}
+
+fn validate_analog_config(config: &AnalogConfig) -> Result<(), std::io::Error> {
+    let adcs = config
+        .adcs
+        .iter()
+        .map(|adc| (adc.name.as_str(), &adc.chip))
+        .collect::<HashMap<&str, &AdcChip>>();
+
+    if config.poll_interval_ms == 0 {
+        return Err(std::io::Error::other(
+            "poll_interval_ms must be larger than 0",
+        ));
+    }
+
+    for input in config.inputs.iter() {
+        let Some(chip) = adcs.get(input.adc.as_str()) else {
+            return Err(std::io::Error::other(format!(
+                "Unknown ADC {} for dataref {}",
+                input.adc, input.dataref
+            )));
+        };
+        if let Some(channels) = chip.channels().filter(|n| input.channel >= *n) {
+            return Err(std::io::Error::other(format!(
+                "ADC {} of dataref {} has channels 0 to {}, not {}",
+                input.adc,
+                input.dataref,
+                channels - 1,
+                input.channel
+            )));
+        }
+        if input.filter.raw_min == input.filter.raw_max {
+            return Err(std::io::Error::other(format!(
+                "The calibration of dataref {} has the same raw_min and raw_max",
+                input.dataref
+            )));
+        }
+        if !(0.0..0.5).contains(&input.filter.deadzone) {
+            return Err(std::io::Error::other(format!(
+                "The deadzone of dataref {} must be at least 0 and less than 0.5",
+                input.dataref
+            )));
+        }
+        if !(0.0..1.0).contains(&input.filter.center_deadzone) {
+            return Err(std::io::Error::other(format!(
+                "The center_deadzone of dataref {} must be at least 0 and less than 1",
+                input.dataref
+            )));
+        }
+        if !(0.0..0.5).contains(&input.filter.hysteresis) {
+            return Err(std::io::Error::other(format!(
+                "The hysteresis of dataref {} must be at least 0 and less than 0.5",
+                input.dataref
+            )));
+        }
+        if input.filter.max_rate_hz <= 0.0 || input.filter.max_rate_hz.is_nan() {
+            return Err(std::io::Error::other(format!(
+                "The max_rate_hz of dataref {} must be larger than 0",
+                input.dataref
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod analog_config_tests {
+    use super::{validate_analog_config, AdcChip, AnalogConfig};
+
+    fn config() -> AnalogConfig {
+        serde_json::from_str(
+            r#"{
+                "adcs": [
+                    { "name": "levers", "type": "mcp3008", "device": "/dev/spidev0.0" },
+                    { "name": "trim", "type": "ads1115", "device": "/dev/i2c-1", "address": 73 }
+                ],
+                "inputs": [
+                    {
+                        "adc": "levers", "channel": 0,
+                        "dataref": "sim/cockpit2/engine/actuators/throttle_ratio_all",
+                        "raw_min": 20, "raw_max": 1000, "deadzone": 0.02,
+                        "smoothing": 0.3, "hysteresis": 0.005, "max_rate_hz": 20
+                    },
+                    {
+                        "adc": "trim", "channel": 1,
+                        "dataref": "sim/cockpit2/controls/elevator_trim",
+                        "raw_min": 0, "raw_max": 26000, "output_min": -1, "output_max": 1,
+                        "center_deadzone": 0.04
+                    }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn deserialize_config() {
+        let config = config();
+        assert_eq!(config.poll_interval_ms, 20);
+        assert!(matches!(
+            config.adcs[0].chip,
+            AdcChip::Mcp3008 {
+                speed_hz: 1_000_000,
+                ..
+            }
+        ));
+        assert!(matches!(
+            config.adcs[1].chip,
+            AdcChip::Ads1115 { address: 73, .. }
+        ));
+        assert_eq!(config.inputs[0].filter.smoothing, 0.3);
+        assert_eq!(config.inputs[1].filter.smoothing, 1.0);
+        assert_eq!(config.inputs[1].filter.output_min, -1.0);
+        assert!(validate_analog_config(&config).is_ok());
+    }
+
+    #[test]
+    fn validation() {
+        let mut unknown_adc = config();
+        unknown_adc.inputs[0].adc = String::from("throttle");
+        assert!(validate_analog_config(&unknown_adc).is_err());
+
+        let mut no_travel = config();
+        no_travel.inputs[1].filter.raw_max = 0;
+        assert!(validate_analog_config(&no_travel).is_err());
+
+        let mut no_interval = config();
+        no_interval.poll_interval_ms = 0;
+        assert!(validate_analog_config(&no_interval).is_err());
+
+        let mut no_rate = config();
+        no_rate.inputs[0].filter.max_rate_hz = 0.0;
+        assert!(validate_analog_config(&no_rate).is_err());
+    }
+
+    #[test]
+    fn channel_validation() {
+        let with_channels = |levers, trim| {
+            let mut config = config();
+            config.inputs[0].channel = levers;
+            config.inputs[1].channel = trim;
+            validate_analog_config(&config)
+        };
+
+        assert!(with_channels(7, 3).is_ok());
+        assert!(with_channels(8, 0).is_err());
+        assert!(with_channels(0, 4).is_err());
+
+        let mut mock = config();
+        mock.adcs[0].chip = AdcChip::Mock { values: Vec::new() };
+        mock.inputs[0].channel = 12;
+        assert!(validate_analog_config(&mock).is_ok());
+    }
+
+    #[test]
+    fn deadzone_validation() {
+        let with_deadzones = |deadzone, center_deadzone| {
+            let mut config = config();
+            config.inputs[0].filter.deadzone = deadzone;
+            config.inputs[0].filter.center_deadzone = center_deadzone;
+            validate_analog_config(&config)
+        };
+
+        assert!(with_deadzones(0.49, 0.99).is_ok());
+        assert!(with_deadzones(0.5, 0.0).is_err());
+        assert!(with_deadzones(-0.1, 0.0).is_err());
+        assert!(with_deadzones(0.0, 1.0).is_err());
+        assert!(with_deadzones(0.0, -0.5).is_err());
+    }
+
+    #[test]
+    fn hysteresis_validation() {
+        let with_hysteresis = |hysteresis| {
+            let mut config = config();
+            config.inputs[0].filter.hysteresis = hysteresis;
+            validate_analog_config(&config)
+        };
+
+        assert!(with_hysteresis(0.0).is_ok());
+        assert!(with_hysteresis(0.49).is_ok());
+        assert!(with_hysteresis(0.5).is_err());
+        assert!(with_hysteresis(-0.01).is_err());
+    }
+}
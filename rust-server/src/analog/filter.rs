@@ -0,0 +1,209 @@
+use std::time::Duration;
+
+use super::config::FilterConfig;
+
+/// Turns raw ADC readings into dataref values: calibration, deadzones,
+/// smoothing, hysteresis and a limit on the rate of the writes.
+#[derive(Debug, Clone)]
+pub struct AnalogFilter {
+    config: FilterConfig,
+    /// Smoothed travel fraction
+    smoothed: Option<f32>,
+    /// Travel fraction of the latest output
+    output: Option<f32>,
+    /// Time and value of the latest write
+    last_sent: Option<(Duration, f32)>,
+}
+
+impl AnalogFilter {
+    pub fn new(config: &FilterConfig) -> AnalogFilter {
+        AnalogFilter {
+            config: config.clone(),
+            smoothed: None,
+            output: None,
+            last_sent: None,
+        }
+    }
+
+    /// Processes a reading made at `now`, and returns the value to
+    /// write to the dataref, if there is a new value to write.
+    pub fn update(&mut self, raw: u16, now: Duration) -> Option<f32> {
+        let fraction = self.apply_deadzones(self.calibrate(raw));
+        let smoothed = self.smooth(fraction);
+        let output = self.apply_hysteresis(smoothed);
+
+        let value =
+            self.config.output_min + output * (self.config.output_max - self.config.output_min);
+        self.limit_rate(value, now)
+    }
+
+    /// Converts the reading to the fraction of the travel, from 0 to 1.
+    fn calibrate(&self, raw: u16) -> f32 {
+        let min = self.config.raw_min as f32;
+        let max = self.config.raw_max as f32;
+        ((raw as f32 - min) / (max - min)).clamp(0.0, 1.0)
+    }
+
+    fn apply_deadzones(&self, fraction: f32) -> f32 {
+        let deadzone = self.config.deadzone;
+        let fraction = if deadzone > 0.0 {
+            ((fraction - deadzone) / (1.0 - 2.0 * deadzone)).clamp(0.0, 1.0)
+        } else {
+            fraction
+        };
+
+        let half_center = self.config.center_deadzone / 2.0;
+        if half_center > 0.0 {
+            let from_center = fraction - 0.5;
+            if from_center.abs() <= half_center {
+                0.5
+            } else {
+                let scaled = (from_center.abs() - half_center) / (0.5 - half_center) * 0.5;
+                0.5 + scaled.copysign(from_center)
+            }
+        } else {
+            fraction
+        }
+    }
+
+    fn smooth(&mut self, fraction: f32) -> f32 {
+        let alpha = self.config.smoothing.clamp(0.0, 1.0);
+        let smoothed = match self.smoothed {
+            Some(previous) => previous + alpha * (fraction - previous),
+            None => fraction,
+        };
+        self.smoothed = Some(smoothed);
+        smoothed
+    }
+
+    /// Keeps the previous output until the value has moved more than the
+    /// hysteresis. Values within the hysteresis of the ends of the travel
+    /// snap to the ends, so that a smoothed value reaches them.
+    fn apply_hysteresis(&mut self, fraction: f32) -> f32 {
+        let hysteresis = self.config.hysteresis;
+        let fraction = if fraction < hysteresis {
+            0.0
+        } else if fraction > 1.0 - hysteresis {
+            1.0
+        } else {
+            fraction
+        };
+
+        let output = match self.output {
+            Some(previous) if (fraction - previous).abs() < hysteresis => previous,
+            _ => fraction,
+        };
+        self.output = Some(output);
+        output
+    }
+
+    fn limit_rate(&mut self, value: f32, now: Duration) -> Option<f32> {
+        let min_interval = Duration::from_secs_f64(1.0 / self.config.max_rate_hz.max(0.001) as f64);
+
+        match self.last_sent {
+            Some((_, sent)) if sent == value => None,
+            Some((time, _)) if now < time + min_interval => None,
+            _ => {
+                self.last_sent = Some((now, value));
+                Some(value)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod analog_filter_tests {
+    use std::time::Duration;
+
+    use super::AnalogFilter;
+    use crate::analog::config::FilterConfig;
+
+    fn config() -> FilterConfig {
+        FilterConfig {
+            raw_min: 100,
+            raw_max: 900,
+            output_min: 0.0,
+            output_max: 1.0,
+            deadzone: 0.0,
+            center_deadzone: 0.0,
+            smoothing: 1.0,
+            hysteresis: 0.0,
+            max_rate_hz: 1000.0,
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn calibration() {
+        let mut filter = AnalogFilter::new(&FilterConfig {
+            output_min: -1.0,
+            ..config()
+        });
+        assert_eq!(filter.update(50, ms(0)), Some(-1.0));
+        assert_eq!(filter.update(500, ms(10)), Some(0.0));
+        assert_eq!(filter.update(1023, ms(20)), Some(1.0));
+
+        let mut reversed = AnalogFilter::new(&FilterConfig {
+            raw_min: 900,
+            raw_max: 100,
+            ..config()
+        });
+        assert_eq!(reversed.update(900, ms(0)), Some(0.0));
+        assert_eq!(reversed.update(300, ms(10)), Some(0.75));
+    }
+
+    #[test]
+    fn deadzones() {
+        let mut filter = AnalogFilter::new(&FilterConfig {
+            deadzone: 0.1,
+            ..config()
+        });
+        assert_eq!(filter.update(150, ms(0)), Some(0.0));
+        assert_eq!(filter.update(500, ms(10)), Some(0.5));
+        assert_eq!(filter.update(850, ms(20)), Some(1.0));
+
+        let mut trim = AnalogFilter::new(&FilterConfig {
+            center_deadzone: 0.2,
+            output_min: -1.0,
+            ..config()
+        });
+        assert_eq!(trim.update(560, ms(0)), Some(0.0));
+        assert_eq!(trim.update(900, ms(10)), Some(1.0));
+        assert_eq!(trim.update(440, ms(20)), Some(0.0));
+    }
+
+    #[test]
+    fn smoothing_and_hysteresis() {
+        let mut filter = AnalogFilter::new(&FilterConfig {
+            smoothing: 0.5,
+            hysteresis: 0.05,
+            ..config()
+        });
+        assert_eq!(filter.update(500, ms(0)), Some(0.5));
+        // The average moves half way to 1.0, 0.75
+        assert_eq!(filter.update(900, ms(10)), Some(0.75));
+        // Noise below the hysteresis is not sent.
+        assert_eq!(filter.update(740, ms(20)), None);
+        assert_eq!(filter.update(740, ms(30)), None);
+        // The smoothed value reaches the end of the travel.
+        let sent = (4..20)
+            .filter_map(|t| filter.update(900, ms(t * 10)))
+            .collect::<Vec<f32>>();
+        assert_eq!(sent.last(), Some(&1.0));
+    }
+
+    #[test]
+    fn rate_limit() {
+        let mut filter = AnalogFilter::new(&FilterConfig {
+            max_rate_hz: 10.0,
+            ..config()
+        });
+        assert_eq!(filter.update(100, ms(0)), Some(0.0));
+        assert_eq!(filter.update(500, ms(50)), None);
+        assert_eq!(filter.update(500, ms(100)), Some(0.5));
+        assert_eq!(filter.update(500, ms(300)), None);
+    }
+}
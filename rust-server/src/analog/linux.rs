@@ -0,0 +1,129 @@
+#![cfg(target_os = "linux")]
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{self, Read, Write},
+    os::fd::AsRawFd,
+    thread::sleep,
+    time::Duration,
+};
+
+use log::info;
+
+use super::adc::Adc;
+
+/// SPI_IOC_MESSAGE(1) of linux/spi/spidev.h
+const SPI_IOC_MESSAGE_1: u32 = 0x4020_6b00;
+/// I2C_SLAVE of linux/i2c-dev.h
+const I2C_SLAVE: u32 = 0x0703;
+
+/// struct spi_ioc_transfer of linux/spi/spidev.h
+#[repr(C)]
+#[derive(Default)]
+struct SpiIocTransfer {
+    tx_buf: u64,
+    rx_buf: u64,
+    len: u32,
+    speed_hz: u32,
+    delay_usecs: u16,
+    bits_per_word: u8,
+    cs_change: u8,
+    tx_nbits: u8,
+    rx_nbits: u8,
+    word_delay_usecs: u8,
+    pad: u8,
+}
+
+/// MCP3008 through spidev: 10 bits, channels 0 to 7.
+pub struct Mcp3008 {
+    device: File,
+    speed_hz: u32,
+}
+
+impl Mcp3008 {
+    pub fn open(device: &str, speed_hz: u32) -> Result<Mcp3008, io::Error> {
+        let device = OpenOptions::new().read(true).write(true).open(device)?;
+        info!("Opened MCP3008 ADC at {:?}", device);
+        Ok(Mcp3008 { device, speed_hz })
+    }
+}
+
+impl Adc for Mcp3008 {
+    fn read(&mut self, channel: u8) -> Result<u16, io::Error> {
+        if channel > 7 {
+            return Err(io::Error::other(format!(
+                "MCP3008 has no channel {}",
+                channel
+            )));
+        }
+
+        // Start bit, single-ended mode and the channel
+        let tx = [0x01, (0x08 | channel) << 4, 0x00];
+        let mut rx = [0u8; 3];
+        let mut transfer = SpiIocTransfer {
+            tx_buf: tx.as_ptr() as u64,
+            rx_buf: rx.as_mut_ptr() as u64,
+            len: tx.len() as u32,
+            speed_hz: self.speed_hz,
+            bits_per_word: 8,
+            ..Default::default()
+        };
+
+        let result = unsafe {
+            libc::ioctl(
+                self.device.as_raw_fd(),
+                SPI_IOC_MESSAGE_1 as _,
+                &mut transfer as *mut SpiIocTransfer,
+            )
+        };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        Ok((((rx[1] & 0x03) as u16) << 8) | rx[2] as u16)
+    }
+}
+
+/// ADS1115 through i2c-dev: 15 bits for single-ended input, channels 0
+/// to 3, range ±4.096 V.
+pub struct Ads1115 {
+    device: File,
+}
+
+impl Ads1115 {
+    pub fn open(device: &str, address: u16) -> Result<Ads1115, io::Error> {
+        let device = OpenOptions::new().read(true).write(true).open(device)?;
+        let result =
+            unsafe { libc::ioctl(device.as_raw_fd(), I2C_SLAVE as _, address as libc::c_ulong) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        info!("Opened ADS1115 ADC at {:?}, address {:#x}", device, address);
+        Ok(Ads1115 { device })
+    }
+}
+
+impl Adc for Ads1115 {
+    fn read(&mut self, channel: u8) -> Result<u16, io::Error> {
+        if channel > 3 {
+            return Err(io::Error::other(format!(
+                "ADS1115 has no channel {}",
+                channel
+            )));
+        }
+
+        // Config register: start a single conversion of AINx against GND,
+        // ±4.096 V, 860 samples per second, comparator disabled.
+        let config_high = 0x80 | ((0x04 | channel) << 4) | (0x01 << 1) | 0x01;
+        let config_low = 0xe3;
+        self.device.write_all(&[0x01, config_high, config_low])?;
+
+        sleep(Duration::from_millis(2));
+
+        self.device.write_all(&[0x00])?;
+        let mut conversion = [0u8; 2];
+        self.device.read_exact(&mut conversion)?;
+
+        Ok(i16::from_be_bytes(conversion).max(0) as u16)
+    }
+}
@@ -0,0 +1,251 @@
+use serde::{Deserialize, Serialize};
+
+use crate::xpc_types::UICommand;
+
+/// The autopilot panel, sent to the web clients as the `autopilot` object.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Autopilot {
+    #[serde(rename = "flight-director")]
+    pub flight_director: FlightDirectorMode,
+
+    pub source: AutopilotSource,
+
+    /// The selected heading in degrees
+    pub heading: f32,
+
+    /// The selected altitude in feet
+    pub altitude: f32,
+
+    /// The selected vertical speed in feet per minute
+    #[serde(rename = "vertical-speed")]
+    pub vertical_speed: f32,
+
+    /// The selected airspeed, in knots or Mach
+    pub airspeed: f32,
+
+    #[serde(rename = "airspeed-is-mach")]
+    pub airspeed_is_mach: bool,
+
+    /// The selected course of the HSI in degrees
+    pub course: f32,
+
+    pub modes: AutopilotModes,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct AutopilotModes {
+    pub nav: ModeState,
+    pub approach: ModeState,
+    pub glideslope: ModeState,
+    pub vs: ModeState,
+    pub alt: ModeState,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum FlightDirectorMode {
+    #[default]
+    Off,
+    On,
+    /// The flight director drives the servos
+    Autopilot,
+}
+
+impl FlightDirectorMode {
+    /// The mode of the value of `sim/cockpit2/autopilot/flight_director_mode`
+    pub fn from_dataref(value: f32) -> FlightDirectorMode {
+        match value.round() as i32 {
+            i32::MIN..=0 => FlightDirectorMode::Off,
+            1 => FlightDirectorMode::On,
+            _ => FlightDirectorMode::Autopilot,
+        }
+    }
+}
+
+/// The state of an autopilot mode, from the `*_status` datarefs
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum ModeState {
+    #[default]
+    Off,
+    Armed,
+    Captured,
+}
+
+impl ModeState {
+    pub fn from_dataref(value: f32) -> ModeState {
+        match value.round() as i32 {
+            i32::MIN..=0 => ModeState::Off,
+            1 => ModeState::Armed,
+            _ => ModeState::Captured,
+        }
+    }
+}
+
+/// The side whose instruments the autopilot follows
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum AutopilotSource {
+    #[default]
+    Pilot,
+    Copilot,
+}
+
+impl AutopilotSource {
+    /// The source of the value of `sim/cockpit2/autopilot/autopilot_source`
+    pub fn from_dataref(value: f32) -> AutopilotSource {
+        if value >= 0.5 {
+            AutopilotSource::Copilot
+        } else {
+            AutopilotSource::Pilot
+        }
+    }
+}
+
+/// An autopilot command from the web UI, translated to dataref writes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "autopilot-command")]
+pub enum AutopilotCommand {
+    #[serde(rename = "set-heading")]
+    Heading { degrees: f32 },
+    /// Down to -2000 ft for the fields below sea level
+    #[serde(rename = "set-altitude")]
+    Altitude { feet: f32 },
+    #[serde(rename = "set-vertical-speed")]
+    VerticalSpeed { fpm: f32 },
+    /// Knots, or Mach when the airspeed is in Mach
+    #[serde(rename = "set-airspeed")]
+    Airspeed { value: f32 },
+    #[serde(rename = "set-course")]
+    Course { degrees: f32 },
+    #[serde(rename = "set-source")]
+    Source { source: AutopilotSource },
+}
+
+impl AutopilotCommand {
+    /// The dataref writes for this command, or an error if the value is
+    /// not valid.
+    pub fn xplane_commands(&self) -> Result<Vec<UICommand>, String> {
+        let (dataref, value) = match *self {
+            AutopilotCommand::Heading { degrees } => {
+                ("heading_dial_deg_mag_pilot", degrees.rem_euclid(360.0))
+            }
+            AutopilotCommand::Altitude { feet } => {
+                if !(-2_000.0..=50_000.0).contains(&feet) {
+                    return Err(format!("Invalid altitude {} ft", feet));
+                }
+                ("altitude_dial_ft", feet)
+            }
+            AutopilotCommand::VerticalSpeed { fpm } => {
+                if fpm.abs() > 10_000.0 {
+                    return Err(format!("Invalid vertical speed {} fpm", fpm));
+                }
+                ("vvi_dial_fpm", fpm)
+            }
+            AutopilotCommand::Airspeed { value } => {
+                if value <= 0.0 {
+                    return Err(format!("Invalid airspeed {}", value));
+                }
+                ("airspeed_dial_kts_mach", value)
+            }
+            AutopilotCommand::Course { degrees } => {
+                return Ok(vec![UICommand::SetDataref {
+                    dataref: String::from("sim/cockpit2/radios/actuators/hsi_obs_deg_mag_pilot"),
+                    value: degrees.rem_euclid(360.0),
+                }]);
+            }
+            AutopilotCommand::Source { source } => (
+                "autopilot_source",
+                match source {
+                    AutopilotSource::Pilot => 0.0,
+                    AutopilotSource::Copilot => 1.0,
+                },
+            ),
+        };
+
+        Ok(vec![UICommand::SetDataref {
+            dataref: format!("sim/cockpit2/autopilot/{}", dataref),
+            value,
+        }])
+    }
+}
+
+#[cfg(test)]
+mod autopilot_tests {
+    use super::{Autopilot, AutopilotCommand, FlightDirectorMode, ModeState};
+    use crate::xpc_types::UICommand;
+
+    #[test]
+    fn autopilot_json() {
+        let mut autopilot = Autopilot {
+            flight_director: FlightDirectorMode::from_dataref(2.0),
+            altitude: 10_000.0,
+            ..Default::default()
+        };
+        autopilot.modes.nav = ModeState::from_dataref(1.0);
+        autopilot.modes.glideslope = ModeState::from_dataref(2.0);
+        let json = serde_json::to_value(&autopilot).unwrap();
+
+        assert_eq!(json["flight-director"], "autopilot");
+        assert_eq!(json["source"], "pilot");
+        assert_eq!(json["altitude"], 10_000.0);
+        assert_eq!(
+            json["modes"],
+            serde_json::json!({
+                "nav": "armed", "approach": "off", "glideslope": "captured", "vs": "off", "alt": "off"
+            })
+        );
+    }
+
+    #[test]
+    fn typed_commands() {
+        let command = |json: &str| match serde_json::from_str::<UICommand>(json).unwrap() {
+            UICommand::Autopilot(autopilot) => autopilot,
+            other => panic!("Not an autopilot command: {:?}", other),
+        };
+        let dref = |dataref: &str, value| {
+            Ok(vec![UICommand::SetDataref {
+                dataref: String::from(dataref),
+                value,
+            }])
+        };
+
+        assert_eq!(
+            command(r#"{"autopilot-command": "set-heading", "degrees": 360}"#).xplane_commands(),
+            dref("sim/cockpit2/autopilot/heading_dial_deg_mag_pilot", 0.0)
+        );
+        assert_eq!(
+            command(r#"{"autopilot-command": "set-vertical-speed", "fpm": -700}"#)
+                .xplane_commands(),
+            dref("sim/cockpit2/autopilot/vvi_dial_fpm", -700.0)
+        );
+        assert_eq!(
+            command(r#"{"autopilot-command": "set-course", "degrees": -90}"#).xplane_commands(),
+            dref("sim/cockpit2/radios/actuators/hsi_obs_deg_mag_pilot", 270.0)
+        );
+        assert_eq!(
+            command(r#"{"autopilot-command": "set-source", "source": "copilot"}"#)
+                .xplane_commands(),
+            dref("sim/cockpit2/autopilot/autopilot_source", 1.0)
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        assert!(AutopilotCommand::Altitude { feet: 35_000.0 }
+            .xplane_commands()
+            .is_ok());
+        assert!(AutopilotCommand::Altitude { feet: -100.0 }
+            .xplane_commands()
+            .is_ok());
+        assert!(AutopilotCommand::Altitude { feet: -2_500.0 }
+            .xplane_commands()
+            .is_err());
+        assert!(AutopilotCommand::VerticalSpeed { fpm: 12_000.0 }
+            .xplane_commands()
+            .is_err());
+        assert!(AutopilotCommand::Airspeed { value: 0.0 }
+            .xplane_commands()
+            .is_err());
+    }
+}
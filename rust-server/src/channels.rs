@@ -1,19 +1,36 @@
-use log::error;
-use tokio::sync::mpsc::{self, Receiver as MPSCReceiver, Sender as MPSCSender};
+use std::time::Instant;
 
+use log::{error, warn};
+use tokio::sync::mpsc::{
+    self, error::TrySendError, Receiver as MPSCReceiver, Sender as MPSCSender,
+};
+use tokio::sync::watch;
+
+use crate::connections::ConnectionConfig;
 use crate::control_msgs::ControlMessages;
 use crate::xpc_types::UICommand;
 use crate::xplane_comms::ReceivedDatarefs;
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChannelsController {
-    control: MPSCSender<ControlMessages>,
+    control: Vec<MPSCSender<ControlMessages>>,
 }
 
 impl ChannelsController {
-    pub async fn send_control(&self, msg: ControlMessages) {
-        if let Err(e) = self.control.send(msg).await {
-            error!("Failed to send control msg: {:?}", e);
+    /// Sends the message to all X-Plane connections.
+    /// A connection that hasn't handled its earlier messages yet drops
+    /// the message instead of holding up the others.
+    pub fn send_control(&self, msg: ControlMessages) {
+        for control in &self.control {
+            match control.try_send(msg.clone()) {
+                Ok(()) => {}
+                Err(TrySendError::Full(msg)) => {
+                    warn!("Control channel is full, dropping control msg: {:?}", msg)
+                }
+                Err(TrySendError::Closed(msg)) => {
+                    error!("Failed to send control msg: {:?}", msg)
+                }
+            }
         }
     }
 }
@@ -21,35 +38,53 @@ impl ChannelsController {
 #[derive(Debug)]
 pub struct ChannelsXPlaneCommEndpoint {
     pub control: MPSCReceiver<ControlMessages>,
-    pub datarefs: MPSCSender<ReceivedDatarefs>,
+    pub datarefs: watch::Sender<ReceivedDatarefs>,
     pub ui_cmds: MPSCReceiver<UICommand>,
+    /// When the latest packet was received from X-Plane
+    pub last_packet: watch::Sender<Option<Instant>>,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct ChannelsUIEndpoint {
-    pub data: MPSCReceiver<ReceivedDatarefs>,
+    pub connection: String,
+    pub data: watch::Receiver<ReceivedDatarefs>,
     pub ui_cmds: MPSCSender<UICommand>,
+    pub last_packet: watch::Receiver<Option<Instant>>,
 }
 
-pub fn create_channels() -> (
+pub fn create_channels(
+    connections: &[ConnectionConfig],
+) -> (
     ChannelsController,
-    ChannelsXPlaneCommEndpoint,
-    ChannelsUIEndpoint,
+    Vec<ChannelsXPlaneCommEndpoint>,
+    Vec<ChannelsUIEndpoint>,
 ) {
-    let (ctrl_tx, ctrl_rx) = mpsc::channel::<ControlMessages>(2);
-    let (data_tx, data_rx) = mpsc::channel::<ReceivedDatarefs>(2);
-    let (ui_cmds_tx, ui_cmds_rx) = mpsc::channel::<UICommand>(20);
-
-    let xp_comm_endpoint = ChannelsXPlaneCommEndpoint {
-        control: ctrl_rx,
-        datarefs: data_tx,
-        ui_cmds: ui_cmds_rx,
-    };
-    let ui_endpoint = ChannelsUIEndpoint {
-        data: data_rx,
-        ui_cmds: ui_cmds_tx,
+    let mut controller = ChannelsController {
+        control: Vec::new(),
     };
-    let controller = ChannelsController { control: ctrl_tx };
+    let mut xp_comm_endpoints = Vec::new();
+    let mut ui_endpoints = Vec::new();
+
+    for connection in connections {
+        let (ctrl_tx, ctrl_rx) = mpsc::channel::<ControlMessages>(2);
+        let (data_tx, data_rx) = watch::channel(ReceivedDatarefs::default());
+        let (ui_cmds_tx, ui_cmds_rx) = mpsc::channel::<UICommand>(20);
+        let (last_packet_tx, last_packet_rx) = watch::channel(None);
+
+        controller.control.push(ctrl_tx);
+        xp_comm_endpoints.push(ChannelsXPlaneCommEndpoint {
+            control: ctrl_rx,
+            datarefs: data_tx,
+            ui_cmds: ui_cmds_rx,
+            last_packet: last_packet_tx,
+        });
+        ui_endpoints.push(ChannelsUIEndpoint {
+            connection: connection.name.clone(),
+            data: data_rx,
+            ui_cmds: ui_cmds_tx,
+            last_packet: last_packet_rx,
+        });
+    }
 
-    (controller, xp_comm_endpoint, ui_endpoint)
+    (controller, xp_comm_endpoints, ui_endpoints)
 }
@@ -0,0 +1,73 @@
+use serde::{Deserialize, Serialize};
+
+use crate::xpc_types::DatarefValues;
+
+/// A condition on the received datarefs. The keys are the names used in
+/// the JSON sent to the web clients, e.g., "is-gear-unsafe" or "ias".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum Condition {
+    /// The value is true (non-zero), or false if `value` is false
+    #[serde(rename = "bool")]
+    Bool {
+        key: String,
+        #[serde(default = "default_true")]
+        value: bool,
+    },
+    #[serde(rename = "above")]
+    Above { key: String, value: f64 },
+    #[serde(rename = "below")]
+    Below { key: String, value: f64 },
+}
+
+fn default_true() -> bool {
+    true
+}
+
+impl Condition {
+    pub fn key(&self) -> &str {
+        match self {
+            Condition::Bool { key, .. }
+            | Condition::Above { key, .. }
+            | Condition::Below { key, .. } => key,
+        }
+    }
+
+    /// Unknown keys and missing values never match.
+    pub fn evaluate(&self, datarefs: &DatarefValues) -> bool {
+        match self {
+            Condition::Bool { key, value } => {
+                datarefs.value_of(key).is_some_and(|v| (v != 0.0) == *value)
+            }
+            Condition::Above { key, value } => datarefs.value_of(key).is_some_and(|v| v > *value),
+            Condition::Below { key, value } => datarefs.value_of(key).is_some_and(|v| v < *value),
+        }
+    }
+}
+
+#[cfg(test)]
+mod conditions_tests {
+    use super::Condition;
+    use crate::xpc_types::ReceivedDatarefs;
+
+    #[test]
+    fn evaluate() {
+        let datarefs = ReceivedDatarefs {
+            is_gear_unsafe: true,
+            ias: 65.0,
+            ..Default::default()
+        }
+        .values();
+        let condition = |json: &str| serde_json::from_str::<Condition>(json).unwrap();
+
+        assert!(condition(r#"{"type": "bool", "key": "is-gear-unsafe"}"#).evaluate(&datarefs));
+        assert!(!condition(r#"{"type": "bool", "key": "beacon"}"#).evaluate(&datarefs));
+        assert!(
+            condition(r#"{"type": "bool", "key": "beacon", "value": false}"#).evaluate(&datarefs)
+        );
+        assert!(condition(r#"{"type": "above", "key": "ias", "value": 60}"#).evaluate(&datarefs));
+        assert!(!condition(r#"{"type": "below", "key": "ias", "value": 60}"#).evaluate(&datarefs));
+        assert!(!condition(r#"{"type": "below", "key": "lat", "value": 60}"#).evaluate(&datarefs));
+        assert!(!condition(r#"{"type": "bool", "key": "unknown"}"#).evaluate(&datarefs));
+    }
+}
@@ -0,0 +1,143 @@
+use std::{fs::File, io::BufReader, net::SocketAddr};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_CONNECTION: &str = "default";
+
+/// Configuration of one X-Plane instance that the server follows.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ConnectionConfig {
+    pub name: String,
+
+    /// Local UDP port for receiving data from this X-Plane
+    pub udp_port: u16,
+
+    /// Only follow the beacons of the computer with this name
+    #[serde(default)]
+    pub xplane_host: Option<String>,
+
+    /// Fixed address of X-Plane. The beacons are ignored if this is set.
+    #[serde(default)]
+    pub xplane_address: Option<SocketAddr>,
+}
+
+impl ConnectionConfig {
+    /// The connection used when no configuration file has been given:
+    /// it follows any X-Plane that sends beacons.
+    pub fn default_connection(udp_port: u16) -> ConnectionConfig {
+        ConnectionConfig {
+            name: String::from(DEFAULT_CONNECTION),
+            udp_port,
+            xplane_host: None,
+            xplane_address: None,
+        }
+    }
+
+    pub fn accepts_beacon_from(&self, computer_name: &str) -> bool {
+        match (&self.xplane_address, &self.xplane_host) {
+            (Some(_), _) => false,
+            (None, Some(host)) => host == computer_name,
+            (None, None) => true,
+        }
+    }
+}
+
+pub fn read_connections_config(config_file: &str) -> Result<Vec<ConnectionConfig>, std::io::Error> {
+    let input_file = File::open(config_file).map_err(|e| {
+        error!("Reading connections file {} failed: {:?}", config_file, e);
+        e
+    })?;
+    let connections: Vec<ConnectionConfig> = serde_json::from_reader(BufReader::new(input_file))
+        .map_err(|e| {
+            let s = e.to_string();
+            error!("Reading connections file {} failed: {:?}", config_file, s);
+            std::io::Error::other(s)
+        })?;
+
+    validate_connections(&connections)?;
+
+    Ok(connections)
+}
+
+fn validate_connections(connections: &[ConnectionConfig]) -> Result<(), std::io::Error> {
+    if connections.is_empty() {
+        return Err(std::io::Error::other("No connections configured"));
+    }
+
+    for (i, c) in connections.iter().enumerate() {
+        if connections[..i].iter().any(|other| other.name == c.name) {
+            return Err(std::io::Error::other(format!(
+                "Duplicate connection name {}",
+                c.name
+            )));
+        }
+        if connections[..i]
+            .iter()
+            .any(|other| other.udp_port == c.udp_port)
+        {
+            return Err(std::io::Error::other(format!(
+                "Duplicate UDP port {} in connection {}",
+                c.udp_port, c.name
+            )));
+        }
+        if follows_any(c) && connections[..i].iter().any(follows_any) {
+            return Err(std::io::Error::other(format!(
+                "Connection {} needs xplane_host or xplane_address, another connection already follows any X-Plane",
+                c.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+fn follows_any(connection: &ConnectionConfig) -> bool {
+    connection.xplane_host.is_none() && connection.xplane_address.is_none()
+}
+
+#[cfg(test)]
+mod connections_tests {
+    use super::{validate_connections, ConnectionConfig};
+
+    fn connections() -> Vec<ConnectionConfig> {
+        serde_json::from_str(
+            r#"[
+                { "name": "captain", "udp_port": 49007, "xplane_host": "sim-left" },
+                { "name": "fo", "udp_port": 49008, "xplane_address": "192.168.1.11:49000" }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn beacon_matching() {
+        let c = connections();
+        assert!(c[0].accepts_beacon_from("sim-left"));
+        assert!(!c[0].accepts_beacon_from("sim-right"));
+        assert!(!c[1].accepts_beacon_from("sim-right"));
+        assert!(ConnectionConfig::default_connection(49007).accepts_beacon_from("anything"));
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate_connections(&connections()).is_ok());
+        assert!(validate_connections(&[]).is_err());
+
+        let mut duplicate_name = connections();
+        duplicate_name[1].name = String::from("captain");
+        assert!(validate_connections(&duplicate_name).is_err());
+
+        let mut duplicate_port = connections();
+        duplicate_port[1].udp_port = 49007;
+        assert!(validate_connections(&duplicate_port).is_err());
+
+        let mut one_follows_any = connections();
+        one_follows_any[0].xplane_host = None;
+        assert!(validate_connections(&one_follows_any).is_ok());
+
+        let mut two_follow_any = one_follows_any.clone();
+        two_follow_any[1].xplane_address = None;
+        assert!(validate_connections(&two_follow_any).is_err());
+    }
+}
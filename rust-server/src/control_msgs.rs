@@ -2,5 +2,9 @@ use std::net::IpAddr;
 
 #[derive(Debug, Clone)]
 pub enum ControlMessages {
-    XPlaneAddr { addr: IpAddr, port: u16 },
+    XPlaneAddr {
+        addr: IpAddr,
+        port: u16,
+        computer_name: String,
+    },
 }
@@ -1,18 +1,230 @@
+use std::{future::Future, io, sync::Arc};
+
+use log::info;
+use tokio::sync::broadcast;
+
 use crate::channels::ChannelsUIEndpoint;
+use crate::profiles::AircraftProfiles;
+use crate::rules::RuleEvents;
+use crate::supervisor::Supervisor;
 
 mod event_detect;
+mod expander;
 mod input_config;
+mod layers;
 mod linux;
+mod matrix;
+mod outputs;
+mod processing;
+mod source;
+mod switch_sync;
 mod types;
 
+pub use layers::Layers;
+pub use outputs::STALE_DATA;
+pub use source::VirtualEdge;
+
+/// Where the edges of the GPIO inputs come from.
+#[derive(Debug, Clone)]
+pub enum InputBackend {
+    /// The GPIO chips and expanders
+    Gpiod,
+    /// A script file of timed edges
+    Script(String),
+    /// Edges injected through the web server
+    Injected(broadcast::Sender<VirtualEdge>),
+}
+
+/// Runs the GPIO inputs with `config_file`, or with the configuration
+/// file of the aircraft profile. Fails if one of the configuration files
+/// is not valid.
+pub fn run_gpio(
+    supervisor: &Supervisor,
+    channels: &[ChannelsUIEndpoint],
+    config_file: Option<&str>,
+    profiles: Arc<AircraftProfiles>,
+    backend: InputBackend,
+    layers: Layers,
+    rules: RuleEvents,
+) -> io::Result<()> {
+    check_config(channels, config_file, &profiles, &backend)?;
+
+    if let InputBackend::Gpiod = backend {
+        run_gpiod(supervisor, channels, config_file, profiles, layers, rules);
+        return Ok(());
+    }
+
+    let endpoints = channels.to_vec();
+    let cf = config_file.map(String::from);
+    supervisor.spawn("gpio", move || {
+        let (endpoints, backend, layers) = (endpoints.clone(), backend.clone(), layers.clone());
+        follow_aircraft(endpoints.clone(), cf.clone(), profiles.clone(), move |cf| {
+            processing::virtual_main(endpoints.clone(), cf, backend.clone(), layers.clone())
+        })
+    });
+
+    Ok(())
+}
+
+/// Reads the configuration files of the inputs and of the profiles, and
+/// the edge script, so that their errors stop the server at startup
+/// instead of restarting the inputs.
+fn check_config(
+    channels: &[ChannelsUIEndpoint],
+    config_file: Option<&str>,
+    profiles: &AircraftProfiles,
+    backend: &InputBackend,
+) -> io::Result<()> {
+    for file in config_file.into_iter().chain(profiles.gpio_confs()) {
+        let config = input_config::read_input_config(file)?;
+        processing::inputs_by_chip(config.groups, channels, &Layers::default())?;
+    }
+    if let InputBackend::Script(script_file) = backend {
+        source::read_edge_script(script_file)?;
+    }
+
+    Ok(())
+}
+
 #[cfg(not(target_os = "linux"))]
-pub fn run_gpio(_: &ChannelsUIEndpoint, _: &String) {
+fn run_gpiod(
+    _: &Supervisor,
+    _: &[ChannelsUIEndpoint],
+    _: Option<&str>,
+    _: Arc<AircraftProfiles>,
+    _: Layers,
+    _: RuleEvents,
+) {
     log::info!("Not a linux platform, not initializing the GPIO.");
 }
 
 #[cfg(target_os = "linux")]
-pub fn run_gpio(channels: &ChannelsUIEndpoint, config_file: &String) {
-    let uic = channels.ui_cmds.clone();
-    let cf = config_file.clone();
-    tokio::spawn(async move { linux::gpio_main(uic, cf).await });
+fn run_gpiod(
+    supervisor: &Supervisor,
+    channels: &[ChannelsUIEndpoint],
+    config_file: Option<&str>,
+    profiles: Arc<AircraftProfiles>,
+    layers: Layers,
+    rules: RuleEvents,
+) {
+    let endpoints = channels.to_vec();
+    let cf = config_file.map(String::from);
+    supervisor.spawn("gpio", move || {
+        let (endpoints, layers, rules) = (endpoints.clone(), layers.clone(), rules.clone());
+        follow_aircraft(endpoints.clone(), cf.clone(), profiles.clone(), move |cf| {
+            linux::gpio_main(endpoints.clone(), cf, layers.clone(), rules.clone())
+        })
+    });
+}
+
+/// Runs the inputs with the configuration file for the aircraft loaded
+/// in the first connection, and restarts them with another file when
+/// another aircraft is loaded.
+async fn follow_aircraft<F, Fut>(
+    endpoints: Vec<ChannelsUIEndpoint>,
+    default_conf: Option<String>,
+    profiles: Arc<AircraftProfiles>,
+    mut run: F,
+) -> io::Result<()>
+where
+    F: FnMut(String) -> Fut,
+    Fut: Future<Output = io::Result<()>>,
+{
+    let mut datarefs = endpoints
+        .first()
+        .ok_or_else(|| io::Error::other("No connections for the GPIO inputs"))?
+        .data
+        .clone();
+
+    loop {
+        let conf = profiles.gpio_conf(&datarefs.borrow_and_update(), default_conf.as_deref());
+        let changed = async {
+            loop {
+                if datarefs.changed().await.is_err() {
+                    std::future::pending::<()>().await;
+                }
+                let new_conf = profiles.gpio_conf(&datarefs.borrow(), default_conf.as_deref());
+                if new_conf != conf {
+                    break;
+                }
+            }
+        };
+
+        match &conf {
+            Some(config_file) => {
+                info!("Using GPIO configuration {}", config_file);
+                tokio::select! {
+                    result = run(config_file.clone()) => return result,
+                    _ = changed => {}
+                }
+            }
+            None => {
+                info!("No GPIO configuration for the loaded aircraft");
+                changed.await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod gpio_tests {
+    use std::{sync::Arc, time::Duration};
+
+    use tokio::{
+        sync::{mpsc, watch},
+        time::timeout,
+    };
+
+    use super::follow_aircraft;
+    use crate::{
+        channels::ChannelsUIEndpoint,
+        profiles::{AircraftProfile, AircraftProfiles},
+        xplane_comms::ReceivedDatarefs,
+    };
+
+    #[tokio::test]
+    async fn restart_with_aircraft_configuration() {
+        let (datarefs_tx, data) = watch::channel(ReceivedDatarefs::default());
+        let endpoint = ChannelsUIEndpoint {
+            connection: String::from("default"),
+            data,
+            ui_cmds: mpsc::channel(1).0,
+            last_packet: watch::channel(None).1,
+        };
+        let profiles = Arc::new(AircraftProfiles::new(vec![AircraftProfile {
+            name: String::from("zibo"),
+            icao: vec![String::from("B738")],
+            gpio_conf: Some(String::from("zibo.json")),
+            ..Default::default()
+        }]));
+
+        let (started_tx, mut started) = mpsc::unbounded_channel();
+        let run = tokio::spawn(follow_aircraft(
+            vec![endpoint],
+            Some(String::from("default.json")),
+            profiles,
+            move |config_file| {
+                started_tx.send(config_file).unwrap();
+                std::future::pending()
+            },
+        ));
+        let wait = Duration::from_secs(1);
+
+        let first = timeout(wait, started.recv()).await.unwrap();
+        assert_eq!(first.as_deref(), Some("default.json"));
+        datarefs_tx.send_modify(|d| d.aircraft_icao = String::from("B738"));
+        // Only the profile selected by the connection restarts the inputs
+        assert!(timeout(Duration::from_millis(50), started.recv())
+            .await
+            .is_err());
+        datarefs_tx.send_modify(|d| d.aircraft_profile = Some(String::from("zibo")));
+        let zibo = timeout(wait, started.recv()).await.unwrap();
+        assert_eq!(zibo.as_deref(), Some("zibo.json"));
+        datarefs_tx.send_modify(|d| d.altitude = 1000.0);
+        datarefs_tx.send_modify(|d| d.aircraft_icao = String::from("C172"));
+        datarefs_tx.send_modify(|d| d.aircraft_profile = None);
+        let default = timeout(wait, started.recv()).await.unwrap();
+        assert_eq!(default.as_deref(), Some("default.json"));
+        run.abort();
+    }
 }
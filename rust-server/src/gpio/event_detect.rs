@@ -1,28 +1,75 @@
 #![cfg_attr(not(target_os = "linux"), allow(dead_code))]
 
-use std::time::Duration;
+use std::{collections::HashMap, time::Duration};
+
+use crate::metrics::METRICS;
 
 use super::types::{
-    ButtonEvent, ButtonInput, Edge, EncoderEvent, EncoderEventType, EncoderInput, GpioEvent,
-    GpioInput, PendingEvent, ResolvedPendingEvent, SwitchInput, SwitchPendingEvent,
+    ButtonClickEvent, ButtonEvent, ButtonHeldEvent, ButtonInput, Edge, EncoderEvent,
+    EncoderEventType, EncoderInput, GpioEvent, GpioInput, PendingEvent, ResolvedPendingEvent,
+    ResolvedSelectorEvent, SelectorInput, SelectorPendingEvent, SwitchInput, SwitchPendingEvent,
 };
 
+/// Detects the input events of one GPIO chip. The pins are the line
+/// offsets of the chip.
 pub struct GpioEventDetect {
-    pin_states: [u8; MAX_PINS],
-    input_slots: Vec<InputSlot>,
-    last_event_times: [Duration; MAX_PINS],
+    pin_states: HashMap<usize, u8>,
+    input_slots: HashMap<usize, InputSlot>,
+    last_event_times: HashMap<usize, Duration>,
+    button_states: HashMap<usize, ButtonState>,
+    /// Decoder state of each encoder, by its first pin
+    encoder_states: HashMap<usize, EncoderState>,
+    /// Selector positions and pending checks, by the first pin
+    selector_states: HashMap<usize, SelectorState>,
+}
+
+#[derive(Debug, Clone, Default)]
+struct SelectorState {
+    /// Index of the current position, unknown until the first check
+    position: Option<usize>,
+    pending: bool,
 }
 
 #[derive(Debug, Clone)]
-struct EncoderWithPins {
-    encoder: EncoderInput,
-    pin1: usize,
-    pin2: usize,
+struct EncoderState {
+    /// Line values of the encoder as (pin1 << 1) | pin2
+    ab: u8,
+    /// Transitions since the latest detent, positive to the right
+    position: i8,
+    /// Time and direction of the latest detent
+    last_detent: Option<(Duration, EncoderEventType)>,
+}
+
+impl Default for EncoderState {
+    fn default() -> Self {
+        EncoderState {
+            ab: ENCODER_REST,
+            position: 0,
+            last_detent: None,
+        }
+    }
+}
+
+/// Press and release tracking for the buttons with long press, double
+/// press or hold-repeat actions.
+#[derive(Debug, Clone, Default)]
+struct ButtonState {
+    pressed_at: Option<Duration>,
+    press: u64,
+    /// The long press or the hold-repeat has already sent its command
+    /// for the current press, so the release sends nothing.
+    consumed: bool,
+    click: u64,
+    waiting_double: Option<(u64, Duration)>,
 }
 
-const MAX_PINS: usize = 100;
 const DEBOUNCE: Duration = Duration::from_millis(50);
 
+/// Both lines are pulled up when the encoder rests in a detent, and turning
+/// it to the right goes through the states 11 → 01 → 00 → 10 → 11.
+const ENCODER_REST: u8 = 0b11;
+const ENCODER_RIGHT_SEQUENCE: [u8; 4] = [0b11, 0b01, 0b00, 0b10];
+
 const PINSTATE_UNUSED: u8 = 0xff;
 const PINSTATE_SWITCH_LOW: u8 = 0;
 const PINSTATE_SWITCH_HIGH: u8 = 1;
@@ -32,86 +79,192 @@ const PINSTATE_SWITCH_PENDING_WAS_UNUSED: u8 = 0x12;
 
 #[derive(Debug, Clone)]
 enum InputSlot {
-    Unused,
-    AssignedToEncoder { encoder: EncoderWithPins },
-    AssignedToButton { button: ButtonInput },
-    AssignedToSwitch { switch: SwitchInput },
+    Encoder { encoder: EncoderInput },
+    Button { button: ButtonInput },
+    Switch { switch: SwitchInput },
+    Selector { selector: SelectorInput },
+}
+
+impl InputSlot {
+    fn label(&self) -> String {
+        match self {
+            InputSlot::Encoder { encoder } => encoder.command.encoder_name.clone(),
+            InputSlot::Button { button } => format!("button gpio{}", button.gpio),
+            InputSlot::Switch { switch } => format!("switch gpio{}", switch.gpio),
+            InputSlot::Selector { selector } => selector.name.clone(),
+        }
+    }
 }
 
 impl GpioEventDetect {
     pub fn new(inputs: &[GpioInput]) -> GpioEventDetect {
-        let mut input_slots = Vec::new();
-        input_slots.resize(MAX_PINS, InputSlot::Unused);
-
         let mut event_detect = GpioEventDetect {
-            pin_states: [PINSTATE_UNUSED; MAX_PINS],
-            input_slots,
-            last_event_times: [Duration::ZERO; MAX_PINS],
+            pin_states: HashMap::new(),
+            input_slots: HashMap::new(),
+            last_event_times: HashMap::new(),
+            button_states: HashMap::new(),
+            encoder_states: HashMap::new(),
+            selector_states: HashMap::new(),
         };
 
-        let mut idx = 0;
         for input in inputs.iter() {
-            idx = match input {
-                GpioInput::Encoder(encoder) => event_detect.register_encoder(idx, encoder),
-                GpioInput::Button(button) => event_detect.register_button(idx, button),
-                GpioInput::Switch(switch) => event_detect.register_switch(idx, switch),
+            match input {
+                GpioInput::Encoder(encoder) => event_detect.register_encoder(encoder),
+                GpioInput::Button(button) => event_detect.register_button(button),
+                GpioInput::Switch(switch) => event_detect.register_switch(switch),
+                GpioInput::ToggleSwitch(switch) => {
+                    event_detect.register_switch(&switch.to_switch())
+                }
+                GpioInput::Selector(selector) => event_detect.register_selector(selector),
             };
         }
 
         event_detect
     }
 
-    pub fn register_encoder(&mut self, pin: usize, encoder: &EncoderInput) -> usize {
-        let pin1 = pin;
-        let pin2 = pin + 1;
-        let slot = InputSlot::AssignedToEncoder {
-            encoder: EncoderWithPins {
-                encoder: encoder.clone(),
-                pin1,
-                pin2,
-            },
-        };
-
-        self.input_slots[pin1] = slot.clone();
-        self.input_slots[pin2] = slot;
-
-        pin + 2
+    /// Replaces the commands of the inputs, e.g., when the layer changes.
+    /// The states of the pins are kept.
+    pub fn update_inputs(&mut self, inputs: &[GpioInput]) {
+        for input in inputs.iter() {
+            let slot = match input {
+                GpioInput::Encoder(encoder) => InputSlot::Encoder {
+                    encoder: encoder.clone(),
+                },
+                GpioInput::Button(button) => InputSlot::Button {
+                    button: button.clone(),
+                },
+                GpioInput::Switch(switch) => InputSlot::Switch {
+                    switch: switch.clone(),
+                },
+                GpioInput::ToggleSwitch(switch) => InputSlot::Switch {
+                    switch: switch.to_switch(),
+                },
+                GpioInput::Selector(selector) => InputSlot::Selector {
+                    selector: selector.clone(),
+                },
+            };
+            for pin in input.pins() {
+                self.input_slots.insert(pin, slot.clone());
+            }
+        }
     }
 
-    pub fn register_button(&mut self, pin: usize, button: &ButtonInput) -> usize {
-        self.input_slots[pin] = InputSlot::AssignedToButton {
-            button: button.clone(),
+    pub fn register_encoder(&mut self, encoder: &EncoderInput) {
+        let slot = InputSlot::Encoder {
+            encoder: encoder.clone(),
         };
 
-        pin + 1
+        self.input_slots.insert(encoder.gpio1, slot.clone());
+        self.input_slots.insert(encoder.gpio2, slot);
+        self.encoder_states
+            .insert(encoder.gpio1, EncoderState::default());
     }
 
-    pub fn register_switch(&mut self, pin: usize, switch: &SwitchInput) -> usize {
-        self.input_slots[pin] = InputSlot::AssignedToSwitch {
-            switch: switch.clone(),
-        };
+    pub fn register_button(&mut self, button: &ButtonInput) {
+        self.input_slots.insert(
+            button.gpio,
+            InputSlot::Button {
+                button: button.clone(),
+            },
+        );
+    }
+
+    pub fn register_switch(&mut self, switch: &SwitchInput) {
+        self.input_slots.insert(
+            switch.gpio,
+            InputSlot::Switch {
+                switch: switch.clone(),
+            },
+        );
+    }
 
-        pin + 1
+    pub fn register_selector(&mut self, selector: &SelectorInput) {
+        for pin in selector.gpios.iter() {
+            self.input_slots.insert(
+                *pin,
+                InputSlot::Selector {
+                    selector: selector.clone(),
+                },
+            );
+        }
+        if let Some(first) = selector.gpios.first() {
+            self.selector_states
+                .insert(*first, SelectorState::default());
+        }
     }
 
     pub fn on_event(&mut self, pin: usize, edge: Edge, time: &Duration) -> Option<GpioEvent> {
-        match &self.input_slots[pin] {
-            InputSlot::AssignedToEncoder { encoder } => {
-                on_encoder_event(pin, edge, encoder, &mut self.pin_states)
-                    .map(|ee| GpioEvent::Encoder(ee))
+        let slot = self.input_slots.get(&pin)?;
+        METRICS.gpio_events.inc(&[&slot.label()]);
+
+        match slot {
+            InputSlot::Encoder { encoder } => {
+                let state = self.encoder_states.entry(encoder.gpio1).or_default();
+                on_encoder_event(pin, edge, encoder, state)
+                    .map(|ee| accelerate(ee, time, encoder, &mut state.last_detent))
+                    .map(GpioEvent::Encoder)
             }
-            InputSlot::AssignedToButton { button } => on_button_event(
-                pin,
-                edge,
-                time,
+            InputSlot::Button { button } => on_button_event(
+                ButtonEdge {
+                    pin,
+                    edge,
+                    event_time: time,
+                    slot,
+                },
                 button,
-                &mut self.pin_states,
-                &mut self.last_event_times,
-            )
-            .map(|be| GpioEvent::Button(be)),
-            InputSlot::AssignedToSwitch { .. } => on_switch_event(pin, &mut self.pin_states),
+                self.pin_states.entry(pin).or_insert(PINSTATE_UNUSED),
+                self.last_event_times.entry(pin).or_default(),
+                self.button_states.entry(pin).or_default(),
+            ),
+            InputSlot::Switch { .. } => on_switch_event(
+                pin,
+                slot,
+                self.pin_states.entry(pin).or_insert(PINSTATE_UNUSED),
+            ),
+            InputSlot::Selector { selector } => {
+                let first = selector.gpios[0];
+                on_selector_event(
+                    selector,
+                    slot,
+                    self.selector_states.entry(first).or_default(),
+                )
+            }
+        }
+    }
+
+    /// Called when the debounce time has passed after a selector pin
+    /// changed. `line_values` has the values of all the selector pins.
+    pub fn on_pending_selector_event(
+        &mut self,
+        pending_event: &SelectorPendingEvent,
+        line_values: &HashMap<usize, bool>,
+    ) -> Option<ResolvedSelectorEvent> {
+        let selector = match self.input_slots.get(&pending_event.pin) {
+            Some(InputSlot::Selector { selector }) => Some(selector),
             _ => None,
+        }?;
+        let state = self.selector_states.entry(pending_event.pin).or_default();
+        state.pending = false;
+
+        let is_low = |pin: &usize| line_values.get(pin) == Some(&false);
+        let position = selector.positions.iter().position(|position| {
+            selector
+                .gpios
+                .iter()
+                .all(|pin| position.low.contains(pin) == is_low(pin))
+        })?;
+
+        if state.position == Some(position) {
+            return None;
         }
+        state.position = Some(position);
+
+        let position = &selector.positions[position];
+        Some(ResolvedSelectorEvent {
+            selector_name: selector.name.clone(),
+            position: position.name.clone(),
+            command: position.action.clone(),
+        })
     }
 
     pub fn on_pending_event(
@@ -119,10 +272,44 @@ impl GpioEventDetect {
         pending_event: &PendingEvent,
         line_value: bool,
     ) -> Option<ResolvedPendingEvent> {
-        let pin_state = self.pin_states[pending_event.pin()];
+        match pending_event {
+            PendingEvent::SelectorPending(_) => None,
+            PendingEvent::SwitchPending(_) => {
+                self.on_pending_switch_event(pending_event, line_value)
+            }
+            PendingEvent::ButtonHeld(held) => {
+                let button = match self.input_slots.get(&held.pin) {
+                    Some(InputSlot::Button { button }) => Some(button),
+                    _ => None,
+                }?;
+                let state = self.button_states.entry(held.pin).or_default();
+                on_button_held(held, line_value, button, state)
+            }
+            PendingEvent::ButtonClick(click) => {
+                let button = match self.input_slots.get(&click.pin) {
+                    Some(InputSlot::Button { button }) => Some(button),
+                    _ => None,
+                }?;
+                let state = self.button_states.entry(click.pin).or_default();
+                on_button_click_timeout(click, button, state)
+            }
+        }
+    }
 
-        let switch = match &self.input_slots[pending_event.pin()] {
-            InputSlot::AssignedToSwitch { switch } => Some(switch),
+    fn on_pending_switch_event(
+        &mut self,
+        pending_event: &PendingEvent,
+        line_value: bool,
+    ) -> Option<ResolvedPendingEvent> {
+        let pin = pending_event.pin();
+        let pin_state = self
+            .pin_states
+            .get(&pin)
+            .copied()
+            .unwrap_or(PINSTATE_UNUSED);
+
+        let switch = match self.input_slots.get(&pin) {
+            Some(InputSlot::Switch { switch }) => Some(switch),
             _ => None,
         }?;
 
@@ -141,119 +328,343 @@ impl GpioEventDetect {
             _ => (None, pin_state),
         };
 
-        self.pin_states[pending_event.pin()] = next_line_value;
+        self.pin_states.insert(pin, next_line_value);
 
-        cmd.and_then(|command| Some(ResolvedPendingEvent { command }))
+        cmd.map(|command| ResolvedPendingEvent {
+            command,
+            next: None,
+        })
     }
 }
 
-fn on_button_event(
+/// An edge on the line of a button.
+struct ButtonEdge<'a> {
     pin: usize,
     edge: Edge,
-    event_time: &Duration,
+    event_time: &'a Duration,
+    slot: &'a InputSlot,
+}
+
+/// The buttons pull the line low when pressed: a falling edge is a press
+/// and a rising edge is a release.
+fn on_button_event(
+    button_edge: ButtonEdge,
     button: &ButtonInput,
-    pin_states: &mut [u8; MAX_PINS],
-    last_event_times: &mut [Duration; MAX_PINS],
-) -> Option<ButtonEvent> {
+    pin_state: &mut u8,
+    last_event_time: &mut Duration,
+    state: &mut ButtonState,
+) -> Option<GpioEvent> {
+    let ButtonEdge {
+        pin,
+        edge,
+        event_time,
+        slot,
+    } = button_edge;
     let value = edge as u8;
-    let prev_value = pin_states[pin] & 0x1;
+    let prev_value = *pin_state & 0x1;
 
-    pin_states[pin] = value;
+    *pin_state = value;
 
-    let last_ev_time = last_event_times[pin];
+    let bounce = !last_event_time.is_zero() && *event_time < *last_event_time + DEBOUNCE;
 
-    if prev_value == 0
-        && value == 1
-        && (last_ev_time.is_zero() || event_time >= &(last_ev_time + DEBOUNCE))
-    {
-        last_event_times[pin] = event_time.clone();
+    if prev_value == 1 && value == 0 {
+        if bounce {
+            METRICS.debounce_rejections.inc(&[&slot.label()]);
+            return None;
+        }
+        return on_button_press(pin, event_time, button, state);
+    }
 
-        Some(ButtonEvent {
-            command: button.command.clone(),
-        })
-    } else {
-        None
+    if prev_value != 0 || value != 1 {
+        return None;
+    }
+
+    let pressed_at = state.pressed_at.take();
+    if state.consumed {
+        state.consumed = false;
+        *last_event_time = *event_time;
+        return None;
+    }
+
+    if bounce {
+        METRICS.debounce_rejections.inc(&[&slot.label()]);
+        return None;
+    }
+
+    *last_event_time = *event_time;
+
+    let button_event = |command: &str| {
+        Some(GpioEvent::Button(ButtonEvent {
+            command: command.to_string(),
+        }))
+    };
+
+    if let (Some(long_press), Some(pressed_at)) = (&button.long_press, pressed_at) {
+        if *event_time >= pressed_at + Duration::from_millis(long_press.threshold_ms) {
+            return button_event(&long_press.command);
+        }
+    }
+
+    if let Some(double_press) = &button.double_press {
+        let window = Duration::from_millis(double_press.window_ms);
+
+        if let Some((_, first_release)) = state.waiting_double {
+            if *event_time <= first_release + window {
+                state.waiting_double = None;
+                return button_event(&double_press.command);
+            }
+        }
+
+        state.click += 1;
+        state.waiting_double = Some((state.click, *event_time));
+        return Some(GpioEvent::Pending {
+            debounce: window,
+            event: PendingEvent::ButtonClick(ButtonClickEvent {
+                pin,
+                click: state.click,
+            }),
+        });
     }
+
+    button_event(&button.command)
+}
+
+fn on_button_press(
+    pin: usize,
+    event_time: &Duration,
+    button: &ButtonInput,
+    state: &mut ButtonState,
+) -> Option<GpioEvent> {
+    state.press += 1;
+    state.pressed_at = Some(*event_time);
+    state.consumed = false;
+
+    let delay_ms = match (&button.hold_repeat, &button.long_press) {
+        (Some(hold_repeat), _) => hold_repeat.delay_ms,
+        (None, Some(long_press)) => long_press.threshold_ms,
+        (None, None) => return None,
+    };
+
+    Some(GpioEvent::Pending {
+        debounce: Duration::from_millis(delay_ms),
+        event: PendingEvent::ButtonHeld(ButtonHeldEvent {
+            pin,
+            press: state.press,
+        }),
+    })
 }
 
+/// Called when the hold delay has passed. The line is low if the button
+/// is still pressed.
+fn on_button_held(
+    held: &ButtonHeldEvent,
+    line_value: bool,
+    button: &ButtonInput,
+    state: &mut ButtonState,
+) -> Option<ResolvedPendingEvent> {
+    if line_value || state.pressed_at.is_none() || state.press != held.press {
+        return None;
+    }
+
+    state.consumed = true;
+
+    match (&button.hold_repeat, &button.long_press) {
+        (Some(hold_repeat), _) => Some(ResolvedPendingEvent {
+            command: hold_repeat
+                .command
+                .clone()
+                .unwrap_or_else(|| button.command.clone()),
+            next: Some((
+                Duration::from_millis(hold_repeat.rate_ms),
+                PendingEvent::ButtonHeld(held.clone()),
+            )),
+        }),
+        (None, Some(long_press)) => Some(ResolvedPendingEvent {
+            command: long_press.command.clone(),
+            next: None,
+        }),
+        (None, None) => None,
+    }
+}
+
+/// Called when the double press window has passed after a release.
+fn on_button_click_timeout(
+    click: &ButtonClickEvent,
+    button: &ButtonInput,
+    state: &mut ButtonState,
+) -> Option<ResolvedPendingEvent> {
+    match state.waiting_double {
+        Some((waiting, _)) if waiting == click.click => {
+            state.waiting_double = None;
+            Some(ResolvedPendingEvent {
+                command: button.command.clone(),
+                next: None,
+            })
+        }
+        _ => None,
+    }
+}
+
+/// Decodes the quadrature (Gray code) signal of an encoder. A bouncing
+/// contact moves the position back and forth, so it does not produce
+/// detents; the position is checked and reset only in the detent states
+/// of the encoder resolution.
 fn on_encoder_event(
     pin: usize,
     edge: Edge,
-    encoder: &EncoderWithPins,
-    pin_states: &mut [u8; MAX_PINS],
+    encoder: &EncoderInput,
+    state: &mut EncoderState,
 ) -> Option<EncoderEvent> {
     let value = edge as u8;
+    let bit = if pin == encoder.gpio1 { 0b10 } else { 0b01 };
+    let ab = if value == 1 {
+        state.ab | bit
+    } else {
+        state.ab & !bit
+    };
 
-    let other_pin = get_other_pin(pin, encoder);
-    let pinstate = pin_states[pin];
-    let other_pinstate = pin_states[other_pin];
+    if ab == state.ab {
+        return None;
+    }
 
-    if (pinstate & 0x1) != value {
-        pin_states[pin] = (pinstate << 1) | value;
-        pin_states[other_pin] = (other_pinstate << 1) | (other_pinstate & 0x1);
-        get_encoder_event_for_pin(encoder, pin_states)
+    state.position += gray_code_step(state.ab, ab);
+    state.ab = ab;
+
+    let resolution = encoder.resolution;
+    if !resolution.is_detent(ab) {
+        return None;
+    }
+
+    let transitions = resolution.transitions_per_detent();
+    let position = std::mem::take(&mut state.position);
+    let commands = &encoder.command;
+
+    if position >= transitions {
+        Some(EncoderEvent {
+            event_type: EncoderEventType::Right,
+            encoder_name: commands.encoder_name.clone(),
+            command: commands.cmd_right.clone(),
+            count: 1,
+        })
+    } else if position <= -transitions {
+        Some(EncoderEvent {
+            event_type: EncoderEventType::Left,
+            encoder_name: commands.encoder_name.clone(),
+            command: commands.cmd_left.clone(),
+            count: 1,
+        })
     } else {
         None
     }
 }
 
-fn get_encoder_event_for_pin(
-    enc: &EncoderWithPins,
-    pin_states: &[u8; MAX_PINS],
-) -> Option<EncoderEvent> {
-    let pin_state_1 = pin_states[enc.pin1];
-    let pin_state_2 = pin_states[enc.pin2];
+/// +1 for a step to the right, -1 for a step to the left, and 0 if both
+/// lines changed at once and the direction is unknown.
+fn gray_code_step(from: u8, to: u8) -> i8 {
+    let index = |ab| {
+        ENCODER_RIGHT_SEQUENCE
+            .iter()
+            .position(|s| *s == ab)
+            .unwrap()
+    };
+    match (index(to) + 4 - index(from)) % 4 {
+        1 => 1,
+        3 => -1,
+        _ => 0,
+    }
+}
 
-    let pattern = ((pin_state_1 & 0xf) << 4) | (pin_state_2 & 0xf);
-    match pattern {
-        0x39 => Some(EncoderEvent {
-            event_type: EncoderEventType::Right,
-            encoder_name: enc.encoder.command.encoder_name.clone(),
-            command: enc.encoder.command.cmd_right.clone(),
-        }),
-        0x93 => Some(EncoderEvent {
-            event_type: EncoderEventType::Left,
-            encoder_name: enc.encoder.command.encoder_name.clone(),
-            command: enc.encoder.command.cmd_left.clone(),
-        }),
-        _ => None,
+/// Applies the acceleration step that matches the time since the
+/// previous detent in the same direction.
+fn accelerate(
+    event: EncoderEvent,
+    event_time: &Duration,
+    encoder: &EncoderInput,
+    last_detent: &mut Option<(Duration, EncoderEventType)>,
+) -> EncoderEvent {
+    let previous = last_detent.replace((*event_time, event.event_type));
+
+    let interval = match previous {
+        Some((time, direction)) if direction == event.event_type && *event_time >= time => {
+            *event_time - time
+        }
+        _ => return event,
+    };
+
+    let step = encoder
+        .acceleration
+        .iter()
+        .filter(|step| interval <= Duration::from_millis(step.within_ms))
+        .min_by_key(|step| step.within_ms);
+
+    match step {
+        Some(step) => {
+            let coarse_command = match event.event_type {
+                EncoderEventType::Right => &step.cmd_right,
+                EncoderEventType::Left => &step.cmd_left,
+            };
+            EncoderEvent {
+                command: coarse_command.clone().unwrap_or(event.command),
+                count: step.multiplier,
+                ..event
+            }
+        }
+        None => event,
     }
 }
 
-fn get_other_pin(pin: usize, encoder: &EncoderWithPins) -> usize {
-    if pin == encoder.pin1 {
-        encoder.pin2
-    } else {
-        encoder.pin1
+/// Waits for the debounce time after the first edge, and then checks
+/// all the selector pins at once.
+fn on_selector_event(
+    selector: &SelectorInput,
+    slot: &InputSlot,
+    state: &mut SelectorState,
+) -> Option<GpioEvent> {
+    if state.pending {
+        METRICS.debounce_rejections.inc(&[&slot.label()]);
+        return None;
     }
+
+    state.pending = true;
+    Some(GpioEvent::Pending {
+        debounce: DEBOUNCE,
+        event: PendingEvent::SelectorPending(SelectorPendingEvent {
+            pin: selector.gpios[0],
+            pins: selector.gpios.clone(),
+        }),
+    })
 }
 
-fn on_switch_event(pin: usize, pin_states: &mut [u8; MAX_PINS]) -> Option<GpioEvent> {
+fn on_switch_event(pin: usize, slot: &InputSlot, pin_state: &mut u8) -> Option<GpioEvent> {
     let pending = Some(GpioEvent::Pending {
         debounce: DEBOUNCE,
         event: PendingEvent::SwitchPending(SwitchPendingEvent { pin }),
     });
 
-    match pin_states[pin] {
+    match *pin_state {
         PINSTATE_UNUSED => {
-            pin_states[pin] = PINSTATE_SWITCH_PENDING_WAS_UNUSED;
+            *pin_state = PINSTATE_SWITCH_PENDING_WAS_UNUSED;
             pending
         }
         PINSTATE_SWITCH_HIGH => {
-            pin_states[pin] = PINSTATE_SWITCH_PENDING_WAS_HIGH;
+            *pin_state = PINSTATE_SWITCH_PENDING_WAS_HIGH;
             pending
         }
         PINSTATE_SWITCH_LOW => {
-            pin_states[pin] = PINSTATE_SWITCH_PENDING_WAS_LOW;
+            *pin_state = PINSTATE_SWITCH_PENDING_WAS_LOW;
             pending
         }
-        _ => None,
+        _ => {
+            METRICS.debounce_rejections.inc(&[&slot.label()]);
+            None
+        }
     }
 }
 
 #[cfg(test)]
 mod gpio_tests {
 
+    use std::collections::BTreeMap;
     use std::time::Duration;
 
     use crate::gpio::types::PendingEvent;
@@ -267,7 +678,13 @@ mod gpio_tests {
     use super::GpioEventDetect;
     use super::GpioInput;
 
-    use super::super::types::EncoderCommands;
+    use std::collections::HashMap;
+
+    use super::super::types::{
+        AccelerationStep, DoublePress, EncoderCommands, EncoderResolution, HoldRepeat, LongPress,
+        SelectorInput, SelectorPendingEvent, SelectorPosition,
+    };
+    use crate::xpc_types::UICommand;
 
     fn test_inputs() -> Vec<GpioInput> {
         [
@@ -279,20 +696,28 @@ mod gpio_tests {
                     cmd_right: String::from("right"),
                     cmd_left: String::from("left"),
                 },
+                resolution: EncoderResolution::Full,
+                acceleration: Vec::new(),
+                layers: BTreeMap::new(),
             }),
             GpioInput::Button(ButtonInput {
                 gpio: 16,
                 command: String::from("tapped"),
+                ..Default::default()
             }),
             GpioInput::Switch(SwitchInput {
                 gpio: 17,
                 command_high: String::from("sw1_high"),
                 command_low: String::from("sw1_low"),
+                sync: None,
+                layers: BTreeMap::new(),
             }),
             GpioInput::Switch(SwitchInput {
                 gpio: 18,
                 command_high: String::from("sw2_high"),
                 command_low: String::from("sw2_low"),
+                sync: None,
+                layers: BTreeMap::new(),
             }),
         ]
         .to_vec()
@@ -306,10 +731,10 @@ mod gpio_tests {
     fn enc_left() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        assert!(input_map.on_event(1, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(0, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(1, Edge::Rising, &t(0)).is_none());
-        let ev = input_map.on_event(0, Edge::Rising, &t(0));
+        assert!(input_map.on_event(15, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(14, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(15, Edge::Rising, &t(0)).is_none());
+        let ev = input_map.on_event(14, Edge::Rising, &t(0));
 
         match ev {
             Some(GpioEvent::Encoder(ee)) => {
@@ -324,10 +749,10 @@ mod gpio_tests {
     fn enc_right() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        assert!(input_map.on_event(0, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(1, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(0, Edge::Rising, &t(0)).is_none());
-        let ev = input_map.on_event(1, Edge::Rising, &t(0));
+        assert!(input_map.on_event(14, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(15, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(14, Edge::Rising, &t(0)).is_none());
+        let ev = input_map.on_event(15, Edge::Rising, &t(0));
 
         match ev {
             Some(GpioEvent::Encoder(ee)) => {
@@ -342,11 +767,11 @@ mod gpio_tests {
     fn enc_debounce() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        assert!(input_map.on_event(0, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(1, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(1, Edge::Falling, &t(0)).is_none());
-        assert!(input_map.on_event(0, Edge::Rising, &t(0)).is_none());
-        let ev = input_map.on_event(1, Edge::Rising, &t(0));
+        assert!(input_map.on_event(14, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(15, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(15, Edge::Falling, &t(0)).is_none());
+        assert!(input_map.on_event(14, Edge::Rising, &t(0)).is_none());
+        let ev = input_map.on_event(15, Edge::Rising, &t(0));
 
         match ev {
             Some(GpioEvent::Encoder(ee)) => {
@@ -356,15 +781,15 @@ mod gpio_tests {
             _ => panic!("Got unexpected event {:?}", ev),
         }
 
-        assert!(input_map.on_event(1, Edge::Rising, &t(0)).is_none());
+        assert!(input_map.on_event(15, Edge::Rising, &t(0)).is_none());
     }
 
     #[test]
     fn button_press() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        assert!(input_map.on_event(2, Edge::Falling, &t(0)).is_none());
-        let ev = input_map.on_event(2, Edge::Rising, &t(0));
+        assert!(input_map.on_event(16, Edge::Falling, &t(0)).is_none());
+        let ev = input_map.on_event(16, Edge::Rising, &t(0));
         match ev {
             Some(GpioEvent::Button(be)) => {
                 assert_eq!(be.command, "tapped")
@@ -377,8 +802,8 @@ mod gpio_tests {
     fn button_press_debounce() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        assert!(input_map.on_event(2, Edge::Falling, &t(0)).is_none());
-        let ev = input_map.on_event(2, Edge::Rising, &t(51));
+        assert!(input_map.on_event(16, Edge::Falling, &t(0)).is_none());
+        let ev = input_map.on_event(16, Edge::Rising, &t(51));
         match ev {
             Some(GpioEvent::Button(be)) => {
                 assert_eq!(be.command, "tapped");
@@ -386,12 +811,12 @@ mod gpio_tests {
             _ => panic!("Got unexpected event {:?}", ev),
         }
 
-        assert!(input_map.on_event(2, Edge::Rising, &t(52)).is_none());
-        assert!(input_map.on_event(2, Edge::Falling, &t(90)).is_none());
-        assert!(input_map.on_event(2, Edge::Rising, &t(99)).is_none());
+        assert!(input_map.on_event(16, Edge::Rising, &t(52)).is_none());
+        assert!(input_map.on_event(16, Edge::Falling, &t(90)).is_none());
+        assert!(input_map.on_event(16, Edge::Rising, &t(99)).is_none());
 
-        assert!(input_map.on_event(2, Edge::Falling, &t(101)).is_none());
-        let ev = input_map.on_event(2, Edge::Rising, &t(102));
+        assert!(input_map.on_event(16, Edge::Falling, &t(101)).is_none());
+        let ev = input_map.on_event(16, Edge::Rising, &t(102));
         match ev {
             Some(GpioEvent::Button(be)) => {
                 assert_eq!(be.command, "tapped");
@@ -404,7 +829,7 @@ mod gpio_tests {
     fn switch_toggle_simple() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        let ev = success_swinput(3, &mut input_map);
+        let ev = success_swinput(17, &mut input_map);
         let resolved_pending = input_map.on_pending_event(&ev, true).unwrap();
         assert_eq!(resolved_pending.command, "sw1_high");
     }
@@ -413,11 +838,11 @@ mod gpio_tests {
     fn switch_toggle_back_forth() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        let ev = success_swinput(3, &mut input_map);
+        let ev = success_swinput(17, &mut input_map);
         let resolved_pending = input_map.on_pending_event(&ev, true).unwrap();
         assert_eq!(resolved_pending.command, "sw1_high");
 
-        let ev2 = success_swinput(3, &mut input_map);
+        let ev2 = success_swinput(17, &mut input_map);
         let resolved_pending2 = input_map.on_pending_event(&ev2, false).unwrap();
         assert_eq!(resolved_pending2.command, "sw1_low");
     }
@@ -426,11 +851,11 @@ mod gpio_tests {
     fn switch_toggle_back_forth_reverse() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        let ev = success_swinput(3, &mut input_map);
+        let ev = success_swinput(17, &mut input_map);
         let resolved_pending = input_map.on_pending_event(&ev, false).unwrap();
         assert_eq!(resolved_pending.command, "sw1_low");
 
-        let ev2 = success_swinput(3, &mut input_map);
+        let ev2 = success_swinput(17, &mut input_map);
         let resolved_pending2 = input_map.on_pending_event(&ev2, true).unwrap();
         assert_eq!(resolved_pending2.command, "sw1_high");
     }
@@ -439,14 +864,14 @@ mod gpio_tests {
     fn switch_toggle_back_forth_deduplicate() {
         let mut input_map = GpioEventDetect::new(&test_inputs());
 
-        let ev = success_swinput(3, &mut input_map);
+        let ev = success_swinput(17, &mut input_map);
         let resolved_pending = input_map.on_pending_event(&ev, true).unwrap();
         assert_eq!(resolved_pending.command, "sw1_high");
 
-        let ev = success_swinput(3, &mut input_map);
+        let ev = success_swinput(17, &mut input_map);
         assert!(input_map.on_pending_event(&ev, true).is_none());
 
-        let ev2 = success_swinput(3, &mut input_map);
+        let ev2 = success_swinput(17, &mut input_map);
         let resolved_pending2 = input_map.on_pending_event(&ev2, false).unwrap();
         assert_eq!(resolved_pending2.command, "sw1_low");
     }
@@ -467,4 +892,409 @@ mod gpio_tests {
 
         ev
     }
+
+    fn button_detect(button: ButtonInput) -> GpioEventDetect {
+        GpioEventDetect::new(&[GpioInput::Button(button)])
+    }
+
+    fn button_command(ev: Option<GpioEvent>) -> String {
+        match ev {
+            Some(GpioEvent::Button(be)) => be.command,
+            _ => panic!("Got unexpected event {:?}", ev),
+        }
+    }
+
+    fn pending(ev: Option<GpioEvent>) -> (Duration, PendingEvent) {
+        match ev {
+            Some(GpioEvent::Pending { debounce, event }) => (debounce, event),
+            _ => panic!("Got unexpected event {:?}", ev),
+        }
+    }
+
+    #[test]
+    fn button_long_press() {
+        let mut input_map = button_detect(ButtonInput {
+            gpio: 16,
+            command: String::from("short"),
+            long_press: Some(LongPress {
+                command: String::from("long"),
+                threshold_ms: 800,
+            }),
+            ..Default::default()
+        });
+
+        // A short press sends the short command on release.
+        let (delay, held) = pending(input_map.on_event(16, Edge::Falling, &t(1000)));
+        assert_eq!(delay, Duration::from_millis(800));
+        assert_eq!(
+            button_command(input_map.on_event(16, Edge::Rising, &t(1200))),
+            "short"
+        );
+        assert!(input_map.on_pending_event(&held, true).is_none());
+
+        // A long press sends the long command when the threshold passes,
+        // and nothing on release.
+        let (_, held) = pending(input_map.on_event(16, Edge::Falling, &t(2000)));
+        let resolved = input_map.on_pending_event(&held, false).unwrap();
+        assert_eq!(resolved.command, "long");
+        assert!(resolved.next.is_none());
+        assert!(input_map.on_event(16, Edge::Rising, &t(3000)).is_none());
+
+        // A release after the threshold is a long press even if the timer
+        // has not been handled yet.
+        let (_, held) = pending(input_map.on_event(16, Edge::Falling, &t(4000)));
+        assert_eq!(
+            button_command(input_map.on_event(16, Edge::Rising, &t(4900))),
+            "long"
+        );
+        assert!(input_map.on_pending_event(&held, true).is_none());
+    }
+
+    #[test]
+    fn button_long_press_release_bounce() {
+        let mut input_map = button_detect(ButtonInput {
+            gpio: 16,
+            command: String::from("short"),
+            long_press: Some(LongPress {
+                command: String::from("long"),
+                threshold_ms: 800,
+            }),
+            ..Default::default()
+        });
+
+        let (_, held) = pending(input_map.on_event(16, Edge::Falling, &t(1000)));
+        assert_eq!(
+            input_map.on_pending_event(&held, false).unwrap().command,
+            "long"
+        );
+        assert!(input_map.on_event(16, Edge::Rising, &t(2000)).is_none());
+
+        // The bounce of the release is neither a press nor a short press.
+        assert!(input_map.on_event(16, Edge::Falling, &t(2010)).is_none());
+        assert!(input_map.on_event(16, Edge::Rising, &t(2020)).is_none());
+
+        let (_, held) = pending(input_map.on_event(16, Edge::Falling, &t(3000)));
+        assert_eq!(
+            button_command(input_map.on_event(16, Edge::Rising, &t(3100))),
+            "short"
+        );
+        assert!(input_map.on_pending_event(&held, true).is_none());
+    }
+
+    #[test]
+    fn button_double_press() {
+        let mut input_map = button_detect(ButtonInput {
+            gpio: 16,
+            command: String::from("single"),
+            double_press: Some(DoublePress {
+                command: String::from("double"),
+                window_ms: 300,
+            }),
+            ..Default::default()
+        });
+
+        assert!(input_map.on_event(16, Edge::Falling, &t(1000)).is_none());
+        let (delay, click) = pending(input_map.on_event(16, Edge::Rising, &t(1100)));
+        assert_eq!(delay, Duration::from_millis(300));
+        assert!(input_map.on_event(16, Edge::Falling, &t(1250)).is_none());
+        assert_eq!(
+            button_command(input_map.on_event(16, Edge::Rising, &t(1300))),
+            "double"
+        );
+        assert!(input_map.on_pending_event(&click, true).is_none());
+
+        // A single press is sent when the window has passed.
+        assert!(input_map.on_event(16, Edge::Falling, &t(2000)).is_none());
+        let (_, click) = pending(input_map.on_event(16, Edge::Rising, &t(2100)));
+        let resolved = input_map.on_pending_event(&click, true).unwrap();
+        assert_eq!(resolved.command, "single");
+
+        // A second press after the window starts a new single press.
+        assert!(input_map.on_event(16, Edge::Falling, &t(2500)).is_none());
+        pending(input_map.on_event(16, Edge::Rising, &t(2600)));
+    }
+
+    #[test]
+    fn button_hold_repeat() {
+        let mut input_map = button_detect(ButtonInput {
+            gpio: 16,
+            command: String::from("heading_up"),
+            hold_repeat: Some(HoldRepeat {
+                command: None,
+                delay_ms: 500,
+                rate_ms: 100,
+            }),
+            ..Default::default()
+        });
+
+        let (delay, held) = pending(input_map.on_event(16, Edge::Falling, &t(1000)));
+        assert_eq!(delay, Duration::from_millis(500));
+
+        let mut next = held;
+        for _ in 0..3 {
+            let resolved = input_map.on_pending_event(&next, false).unwrap();
+            assert_eq!(resolved.command, "heading_up");
+            let (rate, event) = resolved.next.unwrap();
+            assert_eq!(rate, Duration::from_millis(100));
+            next = event;
+        }
+
+        // Releasing stops the repeat without sending the short press.
+        assert!(input_map.on_event(16, Edge::Rising, &t(1800)).is_none());
+        assert!(input_map.on_pending_event(&next, true).is_none());
+
+        // The timer of an earlier press does not repeat a new press.
+        let (_, held) = pending(input_map.on_event(16, Edge::Falling, &t(2000)));
+        assert_eq!(
+            button_command(input_map.on_event(16, Edge::Rising, &t(2100))),
+            "heading_up"
+        );
+        pending(input_map.on_event(16, Edge::Falling, &t(2200)));
+        assert!(input_map.on_pending_event(&held, false).is_none());
+    }
+
+    fn turn_right(input_map: &mut GpioEventDetect, time: u64) -> (String, u32) {
+        assert!(input_map.on_event(14, Edge::Falling, &t(time)).is_none());
+        assert!(input_map.on_event(15, Edge::Falling, &t(time)).is_none());
+        assert!(input_map.on_event(14, Edge::Rising, &t(time)).is_none());
+        match input_map.on_event(15, Edge::Rising, &t(time)) {
+            Some(GpioEvent::Encoder(ee)) => (ee.command, ee.count),
+            ev => panic!("Got unexpected event {:?}", ev),
+        }
+    }
+
+    fn turn_left(input_map: &mut GpioEventDetect, time: u64) -> (String, u32) {
+        assert!(input_map.on_event(15, Edge::Falling, &t(time)).is_none());
+        assert!(input_map.on_event(14, Edge::Falling, &t(time)).is_none());
+        assert!(input_map.on_event(15, Edge::Rising, &t(time)).is_none());
+        match input_map.on_event(14, Edge::Rising, &t(time)) {
+            Some(GpioEvent::Encoder(ee)) => (ee.command, ee.count),
+            ev => panic!("Got unexpected event {:?}", ev),
+        }
+    }
+
+    fn accelerated_encoder() -> GpioEventDetect {
+        GpioEventDetect::new(&[GpioInput::Encoder(EncoderInput {
+            gpio1: 14,
+            gpio2: 15,
+            command: EncoderCommands {
+                encoder_name: String::from("heading"),
+                cmd_right: String::from("heading_up"),
+                cmd_left: String::from("heading_down"),
+            },
+            resolution: EncoderResolution::Full,
+            acceleration: Vec::from([
+                AccelerationStep {
+                    within_ms: 100,
+                    multiplier: 5,
+                    cmd_right: None,
+                    cmd_left: None,
+                },
+                AccelerationStep {
+                    within_ms: 30,
+                    multiplier: 1,
+                    cmd_right: Some(String::from("heading_up_coarse")),
+                    cmd_left: Some(String::from("heading_down_coarse")),
+                },
+            ]),
+            layers: BTreeMap::new(),
+        })])
+    }
+
+    #[test]
+    fn enc_acceleration() {
+        let mut input_map = accelerated_encoder();
+        let up = |count| (String::from("heading_up"), count);
+
+        assert_eq!(turn_right(&mut input_map, 1000), up(1));
+        assert_eq!(turn_right(&mut input_map, 1500), up(1));
+        assert_eq!(turn_right(&mut input_map, 1580), up(5));
+        assert_eq!(
+            turn_right(&mut input_map, 1600),
+            (String::from("heading_up_coarse"), 1)
+        );
+        assert_eq!(turn_right(&mut input_map, 1700), up(5));
+        assert_eq!(turn_right(&mut input_map, 1900), up(1));
+    }
+
+    #[test]
+    fn enc_acceleration_direction_change() {
+        let mut input_map = accelerated_encoder();
+
+        assert_eq!(
+            turn_right(&mut input_map, 1000),
+            (String::from("heading_up"), 1)
+        );
+        assert_eq!(
+            turn_left(&mut input_map, 1020),
+            (String::from("heading_down"), 1)
+        );
+        assert_eq!(
+            turn_left(&mut input_map, 1040),
+            (String::from("heading_down_coarse"), 1)
+        );
+    }
+
+    fn encoder_with_resolution(resolution: EncoderResolution) -> GpioEventDetect {
+        GpioEventDetect::new(&[GpioInput::Encoder(EncoderInput {
+            gpio1: 14,
+            gpio2: 15,
+            command: EncoderCommands {
+                encoder_name: String::from("obs"),
+                cmd_right: String::from("right"),
+                cmd_left: String::from("left"),
+            },
+            resolution,
+            acceleration: Vec::new(),
+            layers: BTreeMap::new(),
+        })])
+    }
+
+    /// Replays edges given as (pin, value) and returns the commands.
+    fn replay(input_map: &mut GpioEventDetect, edges: &[(usize, u8)]) -> Vec<String> {
+        edges
+            .iter()
+            .enumerate()
+            .filter_map(|(i, (pin, value))| {
+                let edge = if *value == 1 {
+                    Edge::Rising
+                } else {
+                    Edge::Falling
+                };
+                match input_map.on_event(*pin, edge, &t(i as u64 * 1000)) {
+                    Some(GpioEvent::Encoder(ee)) => Some(ee.command),
+                    None => None,
+                    ev => panic!("Got unexpected event {:?}", ev),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn enc_full_step_with_bounce() {
+        let mut input_map = encoder_with_resolution(EncoderResolution::Full);
+
+        // Pin 0 bounces at the start of the first detent, pin 1 at the
+        // end of the second.
+        let right = [(14, 0), (14, 1), (14, 0), (15, 0), (14, 1), (15, 1)];
+        let left = [(15, 0), (14, 0), (15, 1), (14, 1), (14, 0), (14, 1)];
+        assert_eq!(replay(&mut input_map, &right), ["right"]);
+        assert_eq!(replay(&mut input_map, &left), ["left"]);
+
+        // Half a turn and back is not a detent.
+        assert!(replay(&mut input_map, &[(14, 0), (15, 0), (15, 1), (14, 1)]).is_empty());
+    }
+
+    #[test]
+    fn enc_half_step() {
+        let mut input_map = encoder_with_resolution(EncoderResolution::Half);
+
+        let right = [(14, 0), (15, 0), (14, 1), (15, 1)];
+        assert_eq!(replay(&mut input_map, &right), ["right", "right"]);
+
+        let left_with_bounce = [(15, 0), (15, 1), (15, 0), (14, 0), (15, 1), (14, 1)];
+        assert_eq!(replay(&mut input_map, &left_with_bounce), ["left", "left"]);
+    }
+
+    #[test]
+    fn enc_quarter_step() {
+        let mut input_map = encoder_with_resolution(EncoderResolution::Quarter);
+
+        let right = [(14, 0), (15, 0), (14, 1), (15, 1)];
+        assert_eq!(
+            replay(&mut input_map, &right),
+            ["right", "right", "right", "right"]
+        );
+        assert_eq!(
+            replay(&mut input_map, &[(15, 0), (15, 1)]),
+            ["left", "right"]
+        );
+    }
+
+    fn magnetos() -> GpioEventDetect {
+        let position = |name: &str, low: &[usize], value: f32| SelectorPosition {
+            name: name.to_string(),
+            low: low.to_vec(),
+            action: UICommand::SetDataref {
+                dataref: String::from("sim/cockpit2/engine/actuators/ignition_key[0]"),
+                value,
+            },
+        };
+
+        GpioEventDetect::new(&[GpioInput::Selector(SelectorInput {
+            name: String::from("magnetos"),
+            gpios: Vec::from([5, 6, 7]),
+            positions: Vec::from([
+                position("OFF", &[], 0.0),
+                position("R", &[5], 1.0),
+                position("L", &[6], 2.0),
+                position("BOTH", &[5, 6], 3.0),
+                position("START", &[5, 6, 7], 4.0),
+            ]),
+            layers: BTreeMap::new(),
+        })])
+    }
+
+    fn selector_pending(ev: Option<GpioEvent>) -> SelectorPendingEvent {
+        match ev {
+            Some(GpioEvent::Pending {
+                event: PendingEvent::SelectorPending(pending),
+                ..
+            }) => pending,
+            _ => panic!("Got unexpected event {:?}", ev),
+        }
+    }
+
+    fn line_values(low: &[usize]) -> HashMap<usize, bool> {
+        [5, 6, 7]
+            .into_iter()
+            .map(|pin| (pin, !low.contains(&pin)))
+            .collect()
+    }
+
+    #[test]
+    fn selector_positions() {
+        let mut input_map = magnetos();
+
+        let pending = selector_pending(input_map.on_event(5, Edge::Falling, &t(0)));
+        // Bouncing and the other pins do not create new checks.
+        assert!(input_map.on_event(5, Edge::Rising, &t(1)).is_none());
+        assert!(input_map.on_event(6, Edge::Falling, &t(2)).is_none());
+
+        let resolved = input_map
+            .on_pending_selector_event(&pending, &line_values(&[5, 6]))
+            .unwrap();
+        assert_eq!(resolved.position, "BOTH");
+        assert_eq!(
+            resolved.command,
+            UICommand::SetDataref {
+                dataref: String::from("sim/cockpit2/engine/actuators/ignition_key[0]"),
+                value: 3.0,
+            }
+        );
+
+        // The same position is not sent again.
+        let pending = selector_pending(input_map.on_event(7, Edge::Falling, &t(100)));
+        assert!(input_map
+            .on_pending_selector_event(&pending, &line_values(&[5, 6]))
+            .is_none());
+
+        let pending = selector_pending(input_map.on_event(7, Edge::Falling, &t(200)));
+        let resolved = input_map
+            .on_pending_selector_event(&pending, &line_values(&[5, 6, 7]))
+            .unwrap();
+        assert_eq!(resolved.position, "START");
+
+        // Between the positions nothing is sent.
+        let pending = selector_pending(input_map.on_event(5, Edge::Rising, &t(300)));
+        assert!(input_map
+            .on_pending_selector_event(&pending, &line_values(&[7]))
+            .is_none());
+
+        let pending = selector_pending(input_map.on_event(7, Edge::Rising, &t(400)));
+        let resolved = input_map
+            .on_pending_selector_event(&pending, &line_values(&[]))
+            .unwrap();
+        assert_eq!(resolved.position, "OFF");
+    }
 }
@@ -0,0 +1,160 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::io;
+
+use super::types::Edge;
+
+/// An I2C GPIO expander whose pins are inputs with pull-ups.
+pub trait Expander: Send {
+    /// Reads the levels of all pins; bit n is pin n.
+    fn read_port(&mut self) -> Result<u16, io::Error>;
+}
+
+/// Returns the edges of the pins whose level differs between two reads
+/// of the port.
+pub fn port_edges(previous: u16, current: u16, pins: &[usize]) -> Vec<(usize, Edge)> {
+    pins.iter()
+        .filter(|pin| (previous ^ current) & (1 << **pin) != 0)
+        .map(|pin| {
+            let edge = if current & (1 << *pin) != 0 {
+                Edge::Rising
+            } else {
+                Edge::Falling
+            };
+            (*pin, edge)
+        })
+        .collect()
+}
+
+/// Bit mask of the pins.
+pub fn pin_mask(pins: &[usize]) -> u16 {
+    pins.iter().fold(0, |mask, pin| mask | (1 << pin))
+}
+
+#[cfg(target_os = "linux")]
+pub use self::i2c::{Mcp23017, Pcf8574};
+
+#[cfg(target_os = "linux")]
+mod i2c {
+    use std::{
+        fs::{File, OpenOptions},
+        io::{self, Read, Write},
+        os::fd::AsRawFd,
+    };
+
+    use log::info;
+
+    use super::Expander;
+
+    /// I2C_SLAVE of linux/i2c-dev.h
+    const I2C_SLAVE: u32 = 0x0703;
+
+    // MCP23017 registers with IOCON.BANK = 0
+    const MCP_IODIRA: u8 = 0x00;
+    const MCP_GPINTENA: u8 = 0x04;
+    const MCP_INTCONA: u8 = 0x08;
+    const MCP_IOCON: u8 = 0x0a;
+    const MCP_GPPUA: u8 = 0x0c;
+    const MCP_GPIOA: u8 = 0x12;
+    /// IOCON: INTA and INTB mirrored, open-drain INT
+    const MCP_IOCON_MIRROR_ODR: u8 = 0x44;
+
+    fn open_i2c(device: &str, address: u16) -> Result<File, io::Error> {
+        let device = OpenOptions::new().read(true).write(true).open(device)?;
+        let result =
+            unsafe { libc::ioctl(device.as_raw_fd(), I2C_SLAVE as _, address as libc::c_ulong) };
+        if result < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(device)
+    }
+
+    /// MCP23017 through i2c-dev.
+    pub struct Mcp23017 {
+        device: File,
+    }
+
+    impl Mcp23017 {
+        /// Configures all pins as inputs, with pull-ups and interrupt on
+        /// change for the used pins.
+        pub fn open(device: &str, address: u16, used_pins: u16) -> Result<Mcp23017, io::Error> {
+            let mut device = open_i2c(device, address)?;
+            let [low, high] = used_pins.to_le_bytes();
+
+            device.write_all(&[MCP_IOCON, MCP_IOCON_MIRROR_ODR])?;
+            device.write_all(&[MCP_IODIRA, 0xff, 0xff])?;
+            device.write_all(&[MCP_GPPUA, low, high])?;
+            device.write_all(&[MCP_INTCONA, 0x00, 0x00])?;
+            device.write_all(&[MCP_GPINTENA, low, high])?;
+
+            info!(
+                "Opened MCP23017 expander at {:?}, address {:#x}",
+                device, address
+            );
+            Ok(Mcp23017 { device })
+        }
+    }
+
+    impl Expander for Mcp23017 {
+        fn read_port(&mut self) -> Result<u16, io::Error> {
+            // Reading the ports also clears the interrupt
+            self.device.write_all(&[MCP_GPIOA])?;
+            let mut port = [0u8; 2];
+            self.device.read_exact(&mut port)?;
+            Ok(u16::from_le_bytes(port))
+        }
+    }
+
+    /// PCF8574 through i2c-dev. Its quasi-bidirectional pins are inputs
+    /// with weak pull-ups when they are written high.
+    pub struct Pcf8574 {
+        device: File,
+    }
+
+    impl Pcf8574 {
+        pub fn open(device: &str, address: u16) -> Result<Pcf8574, io::Error> {
+            let mut device = open_i2c(device, address)?;
+            device.write_all(&[0xff])?;
+
+            info!(
+                "Opened PCF8574 expander at {:?}, address {:#x}",
+                device, address
+            );
+            Ok(Pcf8574 { device })
+        }
+    }
+
+    impl Expander for Pcf8574 {
+        fn read_port(&mut self) -> Result<u16, io::Error> {
+            let mut port = [0u8; 1];
+            self.device.read_exact(&mut port)?;
+            Ok(port[0] as u16)
+        }
+    }
+}
+
+#[cfg(test)]
+mod expander_tests {
+    use super::{pin_mask, port_edges};
+    use crate::gpio::types::Edge;
+
+    #[test]
+    fn edges_of_changed_pins() {
+        let pins = [0, 3, 9];
+
+        assert!(port_edges(0xffff, 0xffff, &pins).is_empty());
+        assert_eq!(
+            port_edges(0xffff, 0xfdf6, &pins),
+            vec![(0, Edge::Falling), (3, Edge::Falling), (9, Edge::Falling)]
+        );
+        assert_eq!(port_edges(0xfff6, 0xfffe, &pins), vec![(3, Edge::Rising)]);
+        // Unused pins are ignored
+        assert!(port_edges(0xffff, 0x0201, &[0, 9]).is_empty());
+    }
+
+    #[test]
+    fn mask_of_pins() {
+        assert_eq!(pin_mask(&[0, 3, 9]), 0x0209);
+        assert_eq!(pin_mask(&[]), 0);
+    }
+}
@@ -1,32 +1,371 @@
 #![cfg_attr(not(target_os = "linux"), allow(dead_code))]
 
-use super::types::GpioInput;
+use super::types::{default_chip, GpioInput, GpioOutput, InputGroup, MatrixInput};
+use crate::xpc_types::ReceivedDatarefs;
 
 use log::error;
+use serde::Deserialize;
+use std::collections::{BTreeSet, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 
-pub fn read_input_config(config_file: &String) -> Result<Vec<GpioInput>, std::io::Error> {
+/// The configuration is either a plain list of inputs, which all use the
+/// first connection, or a list of input groups bound to connections.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum InputConfig {
+    Groups {
+        groups: Vec<InputGroup>,
+        #[serde(default)]
+        layers: Vec<String>,
+    },
+    Inputs(Vec<GpioInput>),
+}
+
+/// The input groups, and the layers of the inputs. The first layer is
+/// active at start.
+#[derive(Debug)]
+pub struct GpioConfig {
+    pub groups: Vec<InputGroup>,
+    pub layers: Vec<String>,
+}
+
+impl From<InputConfig> for GpioConfig {
+    fn from(config: InputConfig) -> GpioConfig {
+        match config {
+            InputConfig::Groups { groups, layers } => GpioConfig { groups, layers },
+            InputConfig::Inputs(inputs) => GpioConfig {
+                groups: Vec::from([InputGroup {
+                    connection: None,
+                    chip: default_chip(),
+                    inputs,
+                    outputs: Vec::new(),
+                    matrices: Vec::new(),
+                    expander: None,
+                }]),
+                layers: Vec::new(),
+            },
+        }
+    }
+}
+
+pub fn read_input_config(config_file: &str) -> Result<GpioConfig, std::io::Error> {
     let input_file = File::open(config_file).map_err(|e| {
         error!("Reading configuration file {} failed: {:?}", config_file, e);
         e
     })?;
     let buf_reader = BufReader::new(input_file);
-    serde_json::from_reader(buf_reader).map_err(|e| {
-        let s = e.to_string();
-        error!("Reading configuration file {} failed: {:?}", config_file, s);
-        std::io::Error::other(s)
-    })
+    let config = serde_json::from_reader::<_, InputConfig>(buf_reader)
+        .map(GpioConfig::from)
+        .map_err(|e| {
+            let s = e.to_string();
+            error!("Reading configuration file {} failed: {:?}", config_file, s);
+            std::io::Error::other(s)
+        })?;
+
+    validate_layers(&config)?;
+    validate_outputs(&config)?;
+    validate_values(&config)?;
+    validate_keys(&config)?;
+    validate_selectors(&config)?;
+    Ok(config)
+}
+
+/// Most commands sent for one encoder detent
+const MAX_MULTIPLIER: u32 = 10;
+
+/// Checks that the timer intervals are not 0, and that the encoder
+/// multipliers are between 1 and `MAX_MULTIPLIER`.
+fn validate_values(config: &GpioConfig) -> Result<(), std::io::Error> {
+    for group in config.groups.iter() {
+        if let Some(matrix) = group.matrices.iter().find(|m| m.scan_interval_ms == 0) {
+            return Err(std::io::Error::other(format!(
+                "Matrix {} has a scan_interval_ms of 0",
+                matrix.name
+            )));
+        }
+        let bad_multiplier = group.inputs.iter().find_map(|input| match input {
+            GpioInput::Encoder(enc) => enc
+                .acceleration
+                .iter()
+                .find(|step| !(1..=MAX_MULTIPLIER).contains(&step.multiplier))
+                .map(|step| (&enc.command.encoder_name, step.multiplier)),
+            _ => None,
+        });
+        if let Some((name, multiplier)) = bad_multiplier {
+            return Err(std::io::Error::other(format!(
+                "Encoder {} has an acceleration multiplier of {}, not between 1 and {}",
+                name, multiplier, MAX_MULTIPLIER
+            )));
+        }
+        let no_rate = group.inputs.iter().find_map(|input| match input {
+            GpioInput::Button(b) if b.hold_repeat.as_ref().is_some_and(|h| h.rate_ms == 0) => {
+                Some(b.gpio)
+            }
+            _ => None,
+        });
+        if let Some(gpio) = no_rate {
+            return Err(std::io::Error::other(format!(
+                "Button {} has a hold_repeat rate_ms of 0",
+                gpio
+            )));
+        }
+        if group.expander.as_ref().is_some_and(|e| e.poll_ms == 0) {
+            return Err(std::io::Error::other("An expander has a poll_ms of 0"));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that each selector has pins, and that its positions are told
+/// apart by different sets of its pins.
+fn validate_selectors(config: &GpioConfig) -> Result<(), std::io::Error> {
+    let selectors = config
+        .groups
+        .iter()
+        .flat_map(|group| group.inputs.iter())
+        .filter_map(|input| match input {
+            GpioInput::Selector(selector) => Some(selector),
+            _ => None,
+        });
+
+    for selector in selectors {
+        if selector.gpios.is_empty() {
+            return Err(std::io::Error::other(format!(
+                "Selector {} has no gpios",
+                selector.name
+            )));
+        }
+
+        let mut low_sets = HashSet::new();
+        for position in &selector.positions {
+            if let Some(pin) = position.low.iter().find(|p| !selector.gpios.contains(p)) {
+                return Err(std::io::Error::other(format!(
+                    "Position {} of selector {} has gpio{}, which is not one of its gpios",
+                    position.name, selector.name, pin
+                )));
+            }
+            if !low_sets.insert(position.low.iter().collect::<BTreeSet<_>>()) {
+                return Err(std::io::Error::other(format!(
+                    "Position {} of selector {} has the same low gpios as another position",
+                    position.name, selector.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that the switch synchronizations and the output conditions use
+/// known dataref keys.
+fn validate_keys(config: &GpioConfig) -> Result<(), std::io::Error> {
+    let known_keys = ReceivedDatarefs::default().values();
+
+    for input in config.groups.iter().flat_map(|group| group.inputs.iter()) {
+        if let Some(switch) = input.as_switch() {
+            if let Some(sync) = switch.sync.as_ref().filter(|s| !known_keys.has_key(&s.key)) {
+                return Err(std::io::Error::other(format!(
+                    "Unknown dataref key {} in the sync of switch gpio{}",
+                    sync.key, switch.gpio
+                )));
+            }
+        }
+    }
+
+    for output in config.groups.iter().flat_map(|group| group.outputs.iter()) {
+        if let Some(condition) = output
+            .condition
+            .as_ref()
+            .filter(|c| !known_keys.has_key(c.key()))
+        {
+            return Err(std::io::Error::other(format!(
+                "Unknown dataref key {} in the condition of output gpio{}",
+                condition.key(),
+                output.gpio
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Checks that each output has either a condition or a rule.
+fn validate_outputs(config: &GpioConfig) -> Result<(), std::io::Error> {
+    let invalid = config
+        .groups
+        .iter()
+        .flat_map(|group| group.outputs.iter())
+        .find(|output| output.condition.is_some() == output.rule.is_some());
+
+    match invalid {
+        Some(output) => Err(std::io::Error::other(format!(
+            "Output {} needs either a condition or a rule",
+            output.gpio
+        ))),
+        None => Ok(()),
+    }
+}
+
+/// Checks that the inputs only have commands for the configured layers,
+/// and that no layer is named like an argument of the layer commands.
+fn validate_layers(config: &GpioConfig) -> Result<(), std::io::Error> {
+    if let Some(layer) = config
+        .layers
+        .iter()
+        .find(|layer| ["next", "previous"].contains(&layer.as_str()))
+    {
+        return Err(std::io::Error::other(format!(
+            "Reserved layer name {}",
+            layer
+        )));
+    }
+
+    let inputs = config.groups.iter().flat_map(|group| group.inputs.iter());
+    let keys = config
+        .groups
+        .iter()
+        .flat_map(|group| group.matrices.iter())
+        .flat_map(|matrix| matrix.keys.iter());
+
+    let unknown = inputs
+        .clone()
+        .flat_map(|input| input.layer_names())
+        .chain(keys.flat_map(|key| key.layers.keys()))
+        .find(|layer| !config.layers.contains(layer));
+
+    if let Some(layer) = unknown {
+        return Err(std::io::Error::other(format!(
+            "Unknown layer {} in input configuration",
+            layer
+        )));
+    }
+
+    match inputs.filter_map(invalid_layer_commands).next() {
+        Some(e) => Err(std::io::Error::other(e)),
+        None => Ok(()),
+    }
+}
+
+/// Layer commands for actions that the input does not have
+fn invalid_layer_commands(input: &GpioInput) -> Option<String> {
+    match input {
+        GpioInput::Button(button) => button.layers.iter().find_map(|(layer, commands)| {
+            let commands = commands.commands();
+            let action = [
+                (
+                    "long_press",
+                    commands.long_press.is_some(),
+                    button.long_press.is_none(),
+                ),
+                (
+                    "double_press",
+                    commands.double_press.is_some(),
+                    button.double_press.is_none(),
+                ),
+                (
+                    "hold_repeat",
+                    commands.hold_repeat.is_some(),
+                    button.hold_repeat.is_none(),
+                ),
+            ]
+            .into_iter()
+            .find(|(_, in_layer, missing)| *in_layer && *missing)?
+            .0;
+            Some(format!(
+                "Layer {} of button gpio{} has a {} command, but the button has no {}",
+                layer, button.gpio, action, action
+            ))
+        }),
+        GpioInput::Encoder(encoder) => encoder
+            .layers
+            .iter()
+            .find(|(_, commands)| commands.coarse.len() > encoder.acceleration.len())
+            .map(|(layer, _)| {
+                format!(
+                    "Layer {} of encoder {} has more coarse commands than acceleration steps",
+                    layer, encoder.command.encoder_name
+                )
+            }),
+        GpioInput::Selector(selector) => selector.layers.iter().find_map(|(layer, actions)| {
+            let position = actions
+                .keys()
+                .find(|name| !selector.positions.iter().any(|p| &p.name == *name))?;
+            Some(format!(
+                "Layer {} of selector {} has unknown position {}",
+                layer, selector.name, position
+            ))
+        }),
+        _ => None,
+    }
+}
+
+/// Checks that the inputs, outputs, matrices and expander interrupt
+/// lines of one chip use each pin at most once, and only the lines the
+/// chip has.
+pub fn validate_pins(
+    inputs: &[GpioInput],
+    outputs: &[GpioOutput],
+    matrices: &[MatrixInput],
+    interrupts: &[usize],
+    num_lines: u32,
+) -> Result<(), std::io::Error> {
+    for matrix in matrices {
+        if let Some(key) = matrix
+            .keys
+            .iter()
+            .find(|key| key.row >= matrix.rows.len() || key.column >= matrix.columns.len())
+        {
+            return Err(std::io::Error::other(format!(
+                "Key {} of matrix {} is outside the matrix",
+                key.command, matrix.name
+            )));
+        }
+    }
+
+    let mut used = HashSet::new();
+    let pins = inputs
+        .iter()
+        .flat_map(|input| input.pins())
+        .chain(outputs.iter().map(|output| output.gpio))
+        .chain(matrices.iter().flat_map(|matrix| matrix.pins()))
+        .chain(interrupts.iter().copied());
+
+    for pin in pins {
+        if pin >= num_lines as usize {
+            return Err(std::io::Error::other(format!(
+                "GPIO {} is out of range, the chip has {} lines",
+                pin, num_lines
+            )));
+        }
+        if !used.insert(pin) {
+            return Err(std::io::Error::other(format!(
+                "GPIO {} is used more than once",
+                pin
+            )));
+        }
+    }
+
+    Ok(())
 }
 
 #[cfg(test)]
 mod gpio_input_cfg_tests {
+    use std::collections::BTreeMap;
     use std::fs::File;
     use std::io::{prelude::*, BufReader};
 
-    use super::super::types::{ButtonInput, EncoderCommands, EncoderInput, GpioInput};
+    use super::super::types::{
+        ButtonInput, EncoderCommands, EncoderInput, EncoderResolution, ExpanderChip, GpioInput,
+        MatrixInput, MatrixKey,
+    };
 
-    use super::read_input_config;
+    use super::{
+        read_input_config, validate_keys, validate_layers, validate_pins, validate_selectors,
+        validate_values, GpioConfig, GpioOutput, InputConfig,
+    };
+    use crate::conditions::Condition;
 
     #[test]
     fn serialise_and_deserialize_config() {
@@ -49,10 +388,360 @@ mod gpio_input_cfg_tests {
 
     #[test]
     fn deserialize_current_config() {
-        let cfg = read_input_config(&String::from("hw-inputs.json")).unwrap();
+        let cfg = read_input_config("hw-inputs.json").unwrap();
         println!("Current configuration is {:#?}", cfg);
     }
 
+    #[test]
+    fn deserialize_input_groups() {
+        let config: InputConfig = serde_json::from_str(
+            r#"{ "groups": [
+                { "connection": "captain", "inputs": [
+                    { "type": "button", "gpio": 18, "command": "captain_button" }
+                ] },
+                { "inputs": [
+                    { "type": "button", "gpio": 19, "command": "default_button" }
+                ] }
+            ] }"#,
+        )
+        .unwrap();
+        let groups = GpioConfig::from(config).groups;
+        assert_eq!(groups.len(), 2);
+        assert_eq!(groups[0].connection.as_deref(), Some("captain"));
+        assert_eq!(groups[1].connection, None);
+        assert_eq!(groups[1].chip, "gpiochip0");
+
+        let legacy: InputConfig =
+            serde_json::from_str(&serde_json::to_string(&sample_inputs()).unwrap()).unwrap();
+        let groups = GpioConfig::from(legacy).groups;
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].connection, None);
+        assert_eq!(groups[0].inputs.len(), 3);
+    }
+
+    #[test]
+    fn value_validation() {
+        let config = || {
+            GpioConfig::from(
+                serde_json::from_str::<InputConfig>(
+                    r#"{ "groups": [
+                        { "matrices": [ { "name": "CDU", "rows": [1], "columns": [2], "keys": [] } ] },
+                        { "expander": { "type": "pcf8574", "device": "/dev/i2c-1" }, "inputs": [] },
+                        { "inputs": [ { "type": "button", "gpio": 18, "command": "hdg_up",
+                                        "hold_repeat": { "delay_ms": 500, "rate_ms": 100 } },
+                                      { "type": "encoder", "gpio1": 14, "gpio2": 15,
+                                        "command": { "encoder_name": "HDG", "cmd_right": "r", "cmd_left": "l" },
+                                        "acceleration": [ { "within_ms": 50, "multiplier": 5 } ] } ] }
+                    ] }"#,
+                )
+                .unwrap(),
+            )
+        };
+        assert!(validate_values(&config()).is_ok());
+
+        let mut matrix = config();
+        matrix.groups[0].matrices[0].scan_interval_ms = 0;
+        assert!(validate_values(&matrix).is_err());
+
+        let mut expander = config();
+        expander.groups[1].expander.as_mut().unwrap().poll_ms = 0;
+        assert!(validate_values(&expander).is_err());
+
+        let mut hold_repeat = config();
+        let GpioInput::Button(button) = &mut hold_repeat.groups[2].inputs[0] else {
+            panic!("Not a button");
+        };
+        button.hold_repeat.as_mut().unwrap().rate_ms = 0;
+        assert!(validate_values(&hold_repeat).is_err());
+
+        let acceleration = |multiplier| {
+            let mut acceleration = config();
+            let GpioInput::Encoder(encoder) = &mut acceleration.groups[2].inputs[1] else {
+                panic!("Not an encoder");
+            };
+            encoder.acceleration[0].multiplier = multiplier;
+            acceleration
+        };
+        assert!(validate_values(&acceleration(0)).is_err());
+        assert!(validate_values(&acceleration(10)).is_ok());
+        assert!(validate_values(&acceleration(11)).is_err());
+    }
+
+    #[test]
+    fn selector_validation() {
+        let config = |gpios: &str, positions: &[&str]| {
+            let positions = positions
+                .iter()
+                .map(|low| {
+                    format!(
+                        r#"{{ "name": "p", "low": {}, "action": {{ "command": "c" }} }}"#,
+                        low
+                    )
+                })
+                .collect::<Vec<String>>()
+                .join(",");
+            GpioConfig::from(
+                serde_json::from_str::<InputConfig>(&format!(
+                    r#"{{ "groups": [ {{ "inputs": [
+                        {{ "type": "selector", "name": "mags", "gpios": {}, "positions": [{}] }}
+                    ] }} ] }}"#,
+                    gpios, positions
+                ))
+                .unwrap(),
+            )
+        };
+
+        assert!(validate_selectors(&config("[5, 6]", &["[]", "[5]", "[6]", "[6, 5]"])).is_ok());
+        assert!(validate_selectors(&config("[]", &[])).is_err());
+        assert!(validate_selectors(&config("[5, 6]", &["[5]", "[7]"])).is_err());
+        assert!(validate_selectors(&config("[5, 6]", &["[5, 6]", "[6, 5]"])).is_err());
+    }
+
+    #[test]
+    fn key_validation() {
+        let config = |key: &str| {
+            GpioConfig::from(
+                serde_json::from_str::<InputConfig>(&format!(
+                    r#"{{ "groups": [ {{ "inputs": [
+                        {{ "type": "switch", "gpio": 17, "command_high": "off", "command_low": "on",
+                           "sync": {{ "key": "{}" }} }}
+                    ] }} ] }}"#,
+                    key
+                ))
+                .unwrap(),
+            )
+        };
+
+        assert!(validate_keys(&config("beacon")).is_ok());
+        assert!(validate_keys(&config("beacom")).is_err());
+
+        let toggle = |state: &str| {
+            GpioConfig::from(
+                serde_json::from_str::<InputConfig>(&format!(
+                    r#"{{ "groups": [ {{ "inputs": [
+                        {{ "type": "toggle_switch", "gpio": 17, "command": "toggle", "state": "{}" }}
+                    ] }} ] }}"#,
+                    state
+                ))
+                .unwrap(),
+            )
+        };
+
+        assert!(validate_keys(&toggle("beacon")).is_ok());
+        assert!(validate_keys(&toggle("beacom")).is_err());
+
+        let output = |key: &str| {
+            GpioConfig::from(
+                serde_json::from_str::<InputConfig>(&format!(
+                    r#"{{ "groups": [ {{ "inputs": [], "outputs": [
+                        {{ "gpio": 20, "condition": {{ "type": "bool", "key": "{}" }} }}
+                    ] }} ] }}"#,
+                    key
+                ))
+                .unwrap(),
+            )
+        };
+
+        assert!(validate_keys(&output("is-gear-unsafe")).is_ok());
+        assert!(validate_keys(&output("is-gear-save")).is_err());
+    }
+
+    #[test]
+    fn deserialize_expander_group() {
+        let config: InputConfig = serde_json::from_str(
+            r#"{ "groups": [
+                { "expander": { "type": "mcp23017", "device": "/dev/i2c-1", "interrupt": 17 },
+                  "inputs": [
+                    { "type": "button", "gpio": 15, "command": "expander_button" }
+                ] }
+            ] }"#,
+        )
+        .unwrap();
+        let groups = GpioConfig::from(config).groups;
+        let expander = groups[0].expander.as_ref().unwrap();
+        assert!(matches!(
+            expander.chip,
+            ExpanderChip::Mcp23017 { address: 0x20, .. }
+        ));
+        assert_eq!(expander.chip.num_lines(), 16);
+        assert_eq!(expander.interrupt, Some(17));
+        assert_eq!(expander.poll_ms, 10);
+    }
+
+    #[test]
+    fn deserialize_layers() {
+        let config: InputConfig = serde_json::from_str(
+            r#"{ "layers": ["hdg", "crs"], "groups": [
+                { "inputs": [
+                    { "type": "button", "gpio": 18, "command": "layer:next" },
+                    { "type": "encoder", "gpio1": 14, "gpio2": 15,
+                      "command": { "encoder_name": "Heading", "cmd_right": "hdg_up", "cmd_left": "hdg_down" },
+                      "layers": { "crs": { "encoder_name": "Course", "cmd_right": "crs_up", "cmd_left": "crs_down" } } }
+                ] }
+            ] }"#,
+        )
+        .unwrap();
+        let mut config = GpioConfig::from(config);
+        assert_eq!(config.layers, ["hdg", "crs"]);
+        assert!(validate_layers(&config).is_ok());
+
+        let encoder = &config.groups[0].inputs[1];
+        let GpioInput::Encoder(course) = encoder.for_layer(Some("crs")) else {
+            panic!("Not an encoder");
+        };
+        assert_eq!(course.command.cmd_right, "crs_up");
+        let GpioInput::Encoder(heading) = encoder.for_layer(Some("hdg")) else {
+            panic!("Not an encoder");
+        };
+        assert_eq!(heading.command.cmd_right, "hdg_up");
+
+        config.layers.pop();
+        assert!(validate_layers(&config).is_err());
+
+        config.layers = ["crs", "next"].map(String::from).to_vec();
+        assert!(validate_layers(&config).is_err());
+    }
+
+    #[test]
+    fn layers_of_all_inputs() {
+        let config = |layers: &str| {
+            GpioConfig::from(
+                serde_json::from_str::<InputConfig>(&format!(
+                    r#"{{ "layers": ["nav", "fms"], "groups": [
+                        {{ "inputs": [
+                            {{ "type": "button", "gpio": 18, "command": "ap",
+                               "long_press": {{ "command": "ap_disc", "threshold_ms": 800 }},
+                               "layers": {{ "nav": "nav", "fms": {{ "command": "exec", "long_press": "exec_all" }} }} }},
+                            {{ "type": "encoder", "gpio1": 14, "gpio2": 15,
+                               "command": {{ "encoder_name": "HDG", "cmd_right": "hdg_up", "cmd_left": "hdg_down" }},
+                               "acceleration": [ {{ "within_ms": 50, "cmd_right": "hdg_up_10", "cmd_left": "hdg_down_10" }} ],
+                               "layers": {{ "nav": {{ "encoder_name": "CRS", "cmd_right": "crs_up", "cmd_left": "crs_down",
+                                                     "coarse": [ {{ "cmd_right": "crs_up_10" }} ] }} }} }},
+                            {{ "type": "toggle_switch", "gpio": 17, "command": "beacon_toggle", "state": "beacon",
+                               "layers": {{ "nav": "strobe_toggle" }} }},
+                            {{ "type": "selector", "name": "mode", "gpios": [5],
+                               "positions": [ {{ "name": "OFF", "low": [], "action": {{ "command": "off" }} }},
+                                              {{ "name": "ON", "low": [5], "action": {{ "command": "on" }} }} ],
+                               "layers": {{ "nav": {{ "ON": {{ "command": "nav_on" }} }} }} }}
+                        ],
+                        "matrices": [ {{ "name": "CDU", "rows": [1], "columns": [2],
+                                        "keys": [ {{ "row": 0, "column": 0, "command": "key", "layers": {{ "fms": "fms_key" }} }} ] }} ] }},
+                        {{ "inputs": [ {layers} ] }}
+                    ] }}"#
+                ))
+                .unwrap(),
+            )
+        };
+        let valid = config("");
+        assert!(validate_layers(&valid).is_ok());
+
+        let inputs = &valid.groups[0].inputs;
+        let GpioInput::Button(exec) = inputs[0].for_layer(Some("fms")) else {
+            panic!("Not a button");
+        };
+        assert_eq!(exec.command, "exec");
+        assert_eq!(exec.long_press.unwrap().command, "exec_all");
+        let GpioInput::Button(nav) = inputs[0].for_layer(Some("nav")) else {
+            panic!("Not a button");
+        };
+        assert_eq!(nav.command, "nav");
+        assert_eq!(nav.long_press.unwrap().command, "ap_disc");
+
+        let GpioInput::Encoder(course) = inputs[1].for_layer(Some("nav")) else {
+            panic!("Not an encoder");
+        };
+        assert_eq!(
+            course.acceleration[0].cmd_right.as_deref(),
+            Some("crs_up_10")
+        );
+        assert_eq!(course.acceleration[0].cmd_left, None);
+
+        let GpioInput::ToggleSwitch(strobe) = inputs[2].for_layer(Some("nav")) else {
+            panic!("Not a toggle switch");
+        };
+        assert_eq!(strobe.command, "strobe_toggle");
+        assert_eq!(strobe.to_switch().command_low, "strobe_toggle");
+
+        let GpioInput::Selector(mode) = inputs[3].for_layer(Some("nav")) else {
+            panic!("Not a selector");
+        };
+        let actions = mode.positions.iter().map(|p| format!("{:?}", p.action));
+        assert_eq!(
+            actions.collect::<Vec<String>>(),
+            [
+                r#"Command { command: "off" }"#,
+                r#"Command { command: "nav_on" }"#
+            ]
+        );
+
+        let key = &valid.groups[0].matrices[0].keys[0];
+        assert_eq!(key.command_for(Some("fms")), "fms_key");
+        assert_eq!(key.command_for(Some("nav")), "key");
+
+        // Commands for actions that the inputs do not have, and unknown layers
+        for layers in [
+            r#"{ "type": "button", "gpio": 20, "command": "a", "layers": { "nav": { "command": "b", "hold_repeat": "c" } } }"#,
+            r#"{ "type": "encoder", "gpio1": 21, "gpio2": 22,
+                 "command": { "encoder_name": "ALT", "cmd_right": "up", "cmd_left": "down" },
+                 "layers": { "nav": { "encoder_name": "VS", "cmd_right": "up", "cmd_left": "down", "coarse": [ {} ] } } }"#,
+            r#"{ "type": "selector", "name": "lights", "gpios": [23],
+                 "positions": [ { "name": "OFF", "low": [], "action": { "command": "off" } } ],
+                 "layers": { "nav": { "ON": { "command": "on" } } } }"#,
+            r#"{ "type": "toggle_switch", "gpio": 24, "command": "a", "state": "beacon", "layers": { "apu": "b" } }"#,
+        ] {
+            assert!(validate_layers(&config(layers)).is_err(), "{}", layers);
+        }
+    }
+
+    #[test]
+    fn pin_validation() {
+        let inputs = sample_inputs();
+        assert!(validate_pins(&inputs, &[], &[], &[], 28).is_ok());
+        assert!(validate_pins(&inputs, &[], &[], &[], 24).is_err());
+
+        let output = |gpio| GpioOutput {
+            gpio,
+            condition: Some(Condition::Bool {
+                key: String::from("beacon"),
+                value: true,
+            }),
+            rule: None,
+            blink_ms: None,
+            active_low: false,
+        };
+        assert!(validate_pins(&inputs, &[output(20)], &[], &[], 28).is_ok());
+        assert!(validate_pins(&inputs, &[output(18)], &[], &[], 28).is_err());
+
+        let mut duplicate = sample_inputs();
+        duplicate.push(GpioInput::Button(ButtonInput {
+            gpio: 15,
+            command: String::from("duplicate"),
+            ..Default::default()
+        }));
+        assert!(validate_pins(&duplicate, &[], &[], &[], 28).is_err());
+
+        let matrix = |rows: Vec<usize>, row| MatrixInput {
+            name: String::from("CDU"),
+            rows,
+            columns: vec![20, 21],
+            keys: vec![MatrixKey {
+                row,
+                column: 1,
+                command: String::from("key"),
+                layers: BTreeMap::new(),
+            }],
+            scan_interval_ms: 5,
+            debounce_scans: 3,
+        };
+        assert!(validate_pins(&inputs, &[], &[matrix(vec![1, 2], 1)], &[], 28).is_ok());
+        assert!(validate_pins(&inputs, &[], &[matrix(vec![1, 2], 2)], &[], 28).is_err());
+        assert!(validate_pins(&inputs, &[], &[matrix(vec![1, 18], 1)], &[], 28).is_err());
+
+        assert!(validate_pins(&inputs, &[], &[], &[17], 28).is_ok());
+        assert!(validate_pins(&inputs, &[], &[], &[18], 28).is_err());
+        assert!(validate_pins(&inputs, &[output(17)], &[], &[17], 28).is_err());
+    }
+
     fn sample_inputs() -> Vec<GpioInput> {
         [
             GpioInput::Encoder(EncoderInput {
@@ -63,6 +752,9 @@ mod gpio_input_cfg_tests {
                     cmd_right: String::from("test_right"),
                     cmd_left: String::from("test_left"),
                 },
+                resolution: EncoderResolution::Full,
+                acceleration: Vec::new(),
+                layers: BTreeMap::new(),
             }),
             GpioInput::Encoder(EncoderInput {
                 gpio1: 14,
@@ -72,10 +764,14 @@ mod gpio_input_cfg_tests {
                     cmd_right: String::from("test_right_2nd"),
                     cmd_left: String::from("test_left_2nd"),
                 },
+                resolution: EncoderResolution::Full,
+                acceleration: Vec::new(),
+                layers: BTreeMap::new(),
             }),
             GpioInput::Button(ButtonInput {
                 gpio: 18,
                 command: String::from("button!"),
+                ..Default::default()
             }),
         ]
         .to_vec()
@@ -0,0 +1,133 @@
+use std::sync::Arc;
+
+use log::{info, warn};
+use serde::Serialize;
+use tokio::sync::watch;
+
+/// Commands with this prefix select a layer instead of being sent to
+/// X-Plane: "layer:<name>", "layer:next" or "layer:previous".
+const LAYER_COMMAND_PREFIX: &str = "layer:";
+
+/// The active layer of the inputs, and the configured layers.
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct ActiveLayer {
+    pub layer: Option<String>,
+    pub layers: Vec<String>,
+}
+
+impl ActiveLayer {
+    /// The layer selected by the argument of a layer command
+    fn select(&self, arg: &str) -> Option<String> {
+        let count = self.layers.len();
+        let current = self
+            .layer
+            .as_ref()
+            .and_then(|layer| self.layers.iter().position(|l| l == layer));
+
+        let index = match (arg, current) {
+            (_, _) if count == 0 => None,
+            ("next", Some(index)) => Some((index + 1) % count),
+            ("previous", Some(index)) => Some((index + count - 1) % count),
+            ("next" | "previous", None) => Some(0),
+            (name, _) => self.layers.iter().position(|l| l == name),
+        }?;
+
+        Some(self.layers[index].clone())
+    }
+}
+
+/// The active layer shared by the inputs of all chips, and published to
+/// the web UI.
+#[derive(Debug, Clone)]
+pub struct Layers {
+    sender: Arc<watch::Sender<ActiveLayer>>,
+}
+
+impl Default for Layers {
+    fn default() -> Layers {
+        Layers {
+            sender: Arc::new(watch::channel(ActiveLayer::default()).0),
+        }
+    }
+}
+
+impl Layers {
+    pub fn subscribe(&self) -> watch::Receiver<ActiveLayer> {
+        self.sender.subscribe()
+    }
+
+    /// Sets the layers of the configuration. The active layer is kept if
+    /// it still exists, or else the first layer is activated.
+    pub fn configure(&self, layers: Vec<String>) {
+        self.sender.send_modify(|active| {
+            if !active.layer.as_ref().is_some_and(|l| layers.contains(l)) {
+                active.layer = layers.first().cloned();
+            }
+            active.layers = layers;
+        });
+    }
+
+    /// Handles a layer command. Returns false if the command is not a
+    /// layer command.
+    pub fn handle_command(&self, command: &str) -> bool {
+        let Some(arg) = command.strip_prefix(LAYER_COMMAND_PREFIX) else {
+            return false;
+        };
+
+        let selected = self.sender.borrow().select(arg);
+        match selected {
+            Some(layer) => {
+                info!("Activating layer {}", layer);
+                self.sender.send_modify(|active| active.layer = Some(layer));
+            }
+            None => warn!("Cannot select layer with {}", command),
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod layers_tests {
+    use super::{ActiveLayer, Layers};
+
+    fn layers() -> Vec<String> {
+        ["hdg", "crs", "alt"].map(String::from).to_vec()
+    }
+
+    fn active(layers: &Layers) -> Option<String> {
+        layers.subscribe().borrow().layer.clone()
+    }
+
+    #[test]
+    fn select_layers() {
+        let state = ActiveLayer {
+            layer: Some(String::from("alt")),
+            layers: layers(),
+        };
+
+        assert_eq!(state.select("next").as_deref(), Some("hdg"));
+        assert_eq!(state.select("previous").as_deref(), Some("crs"));
+        assert_eq!(state.select("crs").as_deref(), Some("crs"));
+        assert_eq!(state.select("baro"), None);
+        assert_eq!(ActiveLayer::default().select("next"), None);
+    }
+
+    #[test]
+    fn layer_commands() {
+        let state = Layers::default();
+        state.configure(layers());
+        assert_eq!(active(&state).as_deref(), Some("hdg"));
+
+        assert!(state.handle_command("layer:next"));
+        assert_eq!(active(&state).as_deref(), Some("crs"));
+        assert!(state.handle_command("layer:unknown"));
+        assert_eq!(active(&state).as_deref(), Some("crs"));
+        assert!(!state.handle_command("sim/autopilot/heading_up"));
+
+        // A new configuration keeps the active layer if it still exists
+        state.configure(["alt", "crs"].map(String::from).to_vec());
+        assert_eq!(active(&state).as_deref(), Some("crs"));
+        state.configure(layers()[..1].to_vec());
+        assert_eq!(active(&state).as_deref(), Some("hdg"));
+    }
+}
@@ -1,23 +1,231 @@
 #![cfg(target_os = "linux")]
 
+use std::collections::{BTreeSet, HashMap, VecDeque};
 use std::io;
+use std::time::Duration;
 
-use futures_util::StreamExt;
-use log::{debug, error, info};
+use futures_util::future::try_join_all;
+use log::{debug, error, info, warn};
 use tokio::select;
-use tokio::sync::mpsc::Sender;
-use tokio_gpiod::{Bias, Chip, Edge, EdgeDetect, Event, Input, Lines, Options};
-use tokio_util::time::delay_queue::Expired;
-use tokio_util::time::DelayQueue;
-
-use super::event_detect::GpioEventDetect;
-use super::input_config::read_input_config;
-use super::types::{Edge as GpioEdge, GpioEvent, GpioInput, PendingEvent};
-use crate::xpc_types::UICommand;
-
-pub async fn gpio_main(ui_cmds: Sender<UICommand>, config_file: String) -> Result<(), io::Error> {
-    let chip = Chip::new("gpiochip0").await.map_err(|e| {
-        error!("Opening GPIO chip failed: {:?}", e);
+use tokio::sync::watch;
+use tokio::task::block_in_place;
+use tokio::time::{interval, Instant, Interval};
+use tokio_gpiod::{Bias, Chip, Drive, Edge, EdgeDetect, Input, Lines, Options};
+
+use super::expander::{pin_mask, port_edges, Expander, Mcp23017, Pcf8574};
+use super::input_config::{read_input_config, validate_pins};
+use super::layers::Layers;
+use super::matrix::MatrixScanner;
+use super::outputs::output_level;
+use super::processing::{
+    inputs_by_chip, run_inputs, send_command, ChipInputs, ChipMatrix, ChipOutput, LineInputs,
+};
+use super::source::{InputSource, PinEvent};
+use super::types::{Edge as GpioEdge, ExpanderChip, ExpanderConfig, GpioOutput, MatrixInput};
+use crate::channels::ChannelsUIEndpoint;
+use crate::rules::RuleEvents;
+
+const OUTPUT_UPDATE_INTERVAL: Duration = Duration::from_millis(50);
+/// An expander with an interrupt line is also polled at this interval,
+/// in case an interrupt was missed.
+const EXPANDER_SAFETY_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+/// The requested lines of a chip. The events and the values refer to
+/// the lines by their index in the request.
+struct ChipLines {
+    lines: Lines<Input>,
+    offsets: Vec<usize>,
+}
+
+impl ChipLines {
+    async fn request(chip: &Chip, offsets: Vec<usize>) -> Result<ChipLines, io::Error> {
+        debug!("Requesting GPIOs {:?} of {}", offsets, chip.name());
+
+        let input_pins = offsets.iter().map(|pin| *pin as u32).collect::<Vec<u32>>();
+
+        let opts = Options::input(input_pins) // configure lines offsets
+            .edge(EdgeDetect::Both)
+            .bias(Bias::PullUp)
+            .consumer("xplane-location-web"); // optionally set consumer string
+
+        let lines = chip.request_lines(opts).await.map_err(|e| {
+            error!("Failed getting chip lines: {:?}", e);
+            e
+        })?;
+
+        Ok(ChipLines { lines, offsets })
+    }
+}
+
+impl InputSource for ChipLines {
+    async fn read_event(&mut self) -> Result<PinEvent, io::Error> {
+        let event = self.lines.read_event().await?;
+        debug!("event: {:?}", event);
+        Ok(PinEvent {
+            pin: self.offsets[event.line as usize],
+            edge: map_edge(event.edge),
+            time: event.time,
+        })
+    }
+
+    async fn values(&mut self) -> Result<HashMap<usize, bool>, io::Error> {
+        let values = self.lines.get_values([false; 64]).await?;
+        Ok(self
+            .offsets
+            .iter()
+            .enumerate()
+            .map(|(index, pin)| (*pin, values[index]))
+            .collect())
+    }
+}
+
+/// The pins of an I2C expander. The port is read when the interrupt
+/// line falls, or at the poll interval, and the changed pins become
+/// edges.
+struct ExpanderLines {
+    expander: Box<dyn Expander>,
+    interrupt: Option<Lines<Input>>,
+    poll: Interval,
+    pins: Vec<usize>,
+    port: u16,
+    edges: VecDeque<PinEvent>,
+    start: Instant,
+}
+
+impl ExpanderLines {
+    async fn open(
+        chip: &Chip,
+        config: &ExpanderConfig,
+        pins: Vec<usize>,
+    ) -> Result<ExpanderLines, io::Error> {
+        let mut expander: Box<dyn Expander> = match &config.chip {
+            ExpanderChip::Mcp23017 { device, address } => {
+                Box::new(Mcp23017::open(device, *address, pin_mask(&pins))?)
+            }
+            ExpanderChip::Pcf8574 { device, address } => Box::new(Pcf8574::open(device, *address)?),
+        };
+        let port = expander.read_port()?;
+
+        let interrupt = match config.interrupt {
+            Some(line) => {
+                debug!(
+                    "Requesting expander interrupt GPIO {} of {}",
+                    line,
+                    chip.name()
+                );
+                let opts = Options::input([line as u32])
+                    .edge(EdgeDetect::Falling)
+                    .bias(Bias::PullUp)
+                    .consumer("xplane-location-web");
+                Some(chip.request_lines(opts).await.map_err(|e| {
+                    error!("Failed getting expander interrupt line: {:?}", e);
+                    e
+                })?)
+            }
+            None => None,
+        };
+        let poll = interval(match interrupt {
+            Some(_) => EXPANDER_SAFETY_POLL_INTERVAL,
+            None => Duration::from_millis(config.poll_ms),
+        });
+
+        Ok(ExpanderLines {
+            expander,
+            interrupt,
+            poll,
+            pins,
+            port,
+            edges: VecDeque::new(),
+            start: Instant::now(),
+        })
+    }
+
+    fn read_port(&mut self) -> Result<(), io::Error> {
+        let port = block_in_place(|| self.expander.read_port())?;
+        let time = self.start.elapsed();
+        for (pin, edge) in port_edges(self.port, port, &self.pins) {
+            self.edges.push_back(PinEvent { pin, edge, time });
+        }
+        self.port = port;
+        Ok(())
+    }
+}
+
+impl InputSource for ExpanderLines {
+    async fn read_event(&mut self) -> Result<PinEvent, io::Error> {
+        loop {
+            if let Some(event) = self.edges.pop_front() {
+                debug!("expander event: {:?}", event);
+                return Ok(event);
+            }
+
+            match &mut self.interrupt {
+                Some(interrupt) => {
+                    select! {
+                        event = interrupt.read_event() => { event?; },
+                        _ = self.poll.tick() => {}
+                    }
+                }
+                None => {
+                    self.poll.tick().await;
+                }
+            }
+
+            self.read_port()?;
+        }
+    }
+
+    /// Reads the port, keeping the edges for `read_event`.
+    async fn values(&mut self) -> Result<HashMap<usize, bool>, io::Error> {
+        self.read_port()?;
+        Ok(self
+            .pins
+            .iter()
+            .map(|pin| (*pin, self.port & (1 << pin) != 0))
+            .collect())
+    }
+}
+
+pub async fn gpio_main(
+    endpoints: Vec<ChannelsUIEndpoint>,
+    config_file: String,
+    layers: Layers,
+    rules: RuleEvents,
+) -> Result<(), io::Error> {
+    let config = read_input_config(&config_file)?;
+    for output in config.groups.iter().flat_map(|group| group.outputs.iter()) {
+        if let Some(rule) = output.rule.as_ref().filter(|r| !rules.is_configured(r)) {
+            warn!(
+                "Output gpio{} shows the rule {}, which is not configured",
+                output.gpio, rule
+            );
+        }
+    }
+    layers.configure(config.layers);
+    let chips = inputs_by_chip(config.groups, &endpoints, &layers)?;
+
+    // All the pins are checked before any line is requested
+    let mut opened = Vec::new();
+    for (chip_name, chip_inputs) in chips {
+        let chip = open_chip(&chip_name, &chip_inputs).await?;
+        opened.push((chip, chip_inputs));
+    }
+
+    try_join_all(
+        opened
+            .into_iter()
+            .map(|(chip, chip_inputs)| run_chip(chip, chip_inputs, &layers, &rules)),
+    )
+    .await?;
+
+    Ok(())
+}
+
+/// Opens the chip, and checks the pins of its inputs, outputs, matrices
+/// and expanders.
+async fn open_chip(chip_name: &str, chip_inputs: &ChipInputs) -> Result<Chip, io::Error> {
+    let chip = Chip::new(chip_name).await.map_err(|e| {
+        error!("Opening GPIO chip {} failed: {:?}", chip_name, e);
         io::Error::other(e.to_string())
     })?;
 
@@ -28,112 +236,230 @@ pub async fn gpio_main(ui_cmds: Sender<UICommand>, config_file: String) -> Resul
         chip.num_lines()
     );
 
-    let inputs = read_input_config(&config_file)?;
+    validate_pins(
+        &chip_inputs.inputs.inputs,
+        &chip_inputs
+            .outputs
+            .iter()
+            .map(|o| o.output.clone())
+            .collect::<Vec<GpioOutput>>(),
+        &chip_inputs
+            .matrices
+            .iter()
+            .map(|m| m.matrix.clone())
+            .collect::<Vec<MatrixInput>>(),
+        &chip_inputs
+            .expanders
+            .iter()
+            .filter_map(|(config, _)| config.interrupt)
+            .collect::<Vec<usize>>(),
+        chip.num_lines(),
+    )
+    .map_err(|e| {
+        error!("Invalid GPIO configuration for chip {}: {}", chip_name, e);
+        e
+    })?;
+
+    for (config, inputs) in chip_inputs.expanders.iter() {
+        validate_pins(&inputs.inputs, &[], &[], &[], config.chip.num_lines()).map_err(|e| {
+            error!(
+                "Invalid configuration for expander {:?}: {}",
+                config.chip, e
+            );
+            e
+        })?;
+    }
+
+    Ok(chip)
+}
+
+async fn run_chip(
+    chip: Chip,
+    chip_inputs: ChipInputs,
+    layers: &Layers,
+    rules: &RuleEvents,
+) -> Result<(), io::Error> {
+    tokio::try_join!(
+        run_chip_inputs(&chip, chip_inputs.inputs, layers),
+        run_outputs(&chip, chip_inputs.outputs, rules.active()),
+        try_join_all(
+            chip_inputs
+                .expanders
+                .into_iter()
+                .map(|(config, inputs)| run_expander_inputs(&chip, config, inputs, layers))
+        ),
+        try_join_all(
+            chip_inputs
+                .matrices
+                .into_iter()
+                .map(|m| run_matrix(&chip, m))
+        )
+    )?;
+
+    Ok(())
+}
+
+async fn run_chip_inputs(
+    chip: &Chip,
+    inputs: LineInputs,
+    layers: &Layers,
+) -> Result<(), io::Error> {
+    if inputs.inputs.is_empty() {
+        return Ok(());
+    }
+
+    let lines = ChipLines::request(chip, inputs.pins()).await?;
+    run_inputs(lines, inputs, layers.clone()).await
+}
+
+async fn run_expander_inputs(
+    chip: &Chip,
+    config: ExpanderConfig,
+    inputs: LineInputs,
+    layers: &Layers,
+) -> Result<(), io::Error> {
+    let lines = ExpanderLines::open(chip, &config, inputs.pins()).await?;
+    run_inputs(lines, inputs, layers.clone()).await
+}
 
-    let input_pins = inputs
+/// Drives the output lines from the datarefs. The lines start in the
+/// safe state, and return to it when the data from X-Plane is stale.
+async fn run_outputs(
+    chip: &Chip,
+    outputs: Vec<ChipOutput>,
+    active_rules: watch::Receiver<BTreeSet<String>>,
+) -> Result<(), io::Error> {
+    if outputs.is_empty() {
+        return Ok(());
+    }
+
+    let offsets = outputs
         .iter()
-        .map(|input| match input {
-            GpioInput::Encoder(enc) => Vec::from([enc.gpio1 as u32, enc.gpio2 as u32]),
-            GpioInput::Button(b) => Vec::from([b.gpio as u32]),
-            GpioInput::Switch(sw) => Vec::from([sw.gpio as u32]),
-        })
-        .flatten()
+        .map(|o| o.output.gpio as u32)
         .collect::<Vec<u32>>();
+    let mut levels = outputs
+        .iter()
+        .map(|o| output_level(&o.output, None, &BTreeSet::new(), Duration::ZERO))
+        .collect::<Vec<bool>>();
 
-    debug!("Requesting GPIOs {:?}", input_pins);
+    debug!("Requesting output GPIOs {:?} of {}", offsets, chip.name());
 
-    let opts = Options::input(input_pins) // configure lines offsets
-        .edge(EdgeDetect::Both)
-        .bias(Bias::PullUp)
-        .consumer("xplane-location-web"); // optionally set consumer string
+    let opts = Options::output(offsets)
+        .values(levels.clone())
+        .consumer("xplane-location-web");
 
-    let mut gpio_inputs = chip.request_lines(opts).await.map_err(|e| {
-        error!("Failed getting chip lines: {:?}", e);
+    let lines = chip.request_lines(opts).await.map_err(|e| {
+        error!("Failed getting chip output lines: {:?}", e);
         e
     })?;
 
-    let mut event_detect = GpioEventDetect::new(&inputs);
-    let mut pending_events = DelayQueue::<PendingEvent>::new();
+    let start = Instant::now();
+    let mut timer = interval(OUTPUT_UPDATE_INTERVAL);
 
     loop {
-        if pending_events.is_empty() {
-            let event = gpio_inputs.read_event().await?;
-            process_event(&event, &mut event_detect, &mut pending_events, &ui_cmds).await;
-        } else {
-            select! {
-                Ok(event) = gpio_inputs.read_event() => {
-                    process_event(&event, &mut event_detect, &mut pending_events, &ui_cmds).await;
-                },
-                e = pending_events.next() => {
-                    process_pending_event(e, &mut gpio_inputs, &mut event_detect, &ui_cmds).await?;
-                }
-            }
-        }
-    }
-}
+        timer.tick().await;
+        let now = start.elapsed();
+        let active_rules = active_rules.borrow().clone();
 
-async fn process_event(
-    event: &Event,
-    event_detect: &mut GpioEventDetect,
-    pending_events: &mut DelayQueue<PendingEvent>,
-    ui_cmds: &Sender<UICommand>,
-) {
-    debug!("event: {:?}", event);
-
-    match event_detect.on_event(event.line as usize, map_edge(event.edge), &event.time) {
-        Some(GpioEvent::Encoder(ee)) => {
-            info!("Got encoder event {:?}", ee);
-            ui_cmds
-                .send(UICommand {
-                    command: ee.command,
-                })
-                .await
-                .ok();
+        // The datarefs of each connection are serialized once per tick
+        let mut values = HashMap::new();
+        for o in &outputs {
+            values
+                .entry(o.source.connection.as_str())
+                .or_insert_with(|| {
+                    o.source
+                        .is_fresh()
+                        .then(|| o.source.datarefs.borrow().values())
+                });
         }
-        Some(GpioEvent::Button(be)) => {
-            info!("Got button event {:?}", be);
-            ui_cmds
-                .send(UICommand {
-                    command: be.command,
-                })
-                .await
-                .ok();
-        }
-        Some(GpioEvent::Pending { debounce, event }) => {
-            info!("Got switch pending event {:?}", event);
-            pending_events.insert(event, debounce);
+
+        let new_levels = outputs
+            .iter()
+            .map(|o| {
+                let datarefs = values[o.source.connection.as_str()].as_ref();
+                output_level(&o.output, datarefs, &active_rules, now)
+            })
+            .collect::<Vec<bool>>();
+
+        if new_levels != levels {
+            debug!("Setting outputs of {} to {:?}", chip.name(), new_levels);
+            lines.set_values(new_levels.clone()).await?;
+            levels = new_levels;
         }
-        None => {}
     }
 }
 
-async fn process_pending_event(
-    opt: Option<Expired<PendingEvent>>,
-    lines: &mut Lines<Input>,
-    event_detect: &mut GpioEventDetect,
-    ui_cmds: &Sender<UICommand>,
-) -> Result<(), io::Error> {
-    debug!("pending event: {:?}", opt);
+/// Scans a key matrix. The rows are open-drain outputs, so that two
+/// pressed keys on the same column do not short a high row to the
+/// scanned row.
+async fn run_matrix(chip: &Chip, chip_matrix: ChipMatrix) -> Result<(), io::Error> {
+    let matrix = chip_matrix.matrix;
+    let num_rows = matrix.rows.len();
+    let num_columns = matrix.columns.len();
 
-    let pending = match opt {
-        Some(ev) => Ok(ev.into_inner()),
-        None => Err(io::Error::other("Got unexpected empty pending event")),
-    }?;
+    debug!(
+        "Requesting matrix {} rows {:?} and columns {:?} of {}",
+        matrix.name,
+        matrix.rows,
+        matrix.columns,
+        chip.name()
+    );
 
-    let pin = pending.pin();
-    let value = lines.get_values([false; 64]).await?[pin];
+    let row_opts = Options::output(
+        matrix
+            .rows
+            .iter()
+            .map(|pin| *pin as u32)
+            .collect::<Vec<u32>>(),
+    )
+    .values(vec![true; num_rows])
+    .drive(Drive::OpenDrain)
+    .consumer("xplane-location-web");
+    let rows = chip.request_lines(row_opts).await.map_err(|e| {
+        error!("Failed getting matrix {} rows: {:?}", matrix.name, e);
+        e
+    })?;
 
-    if let Some(rpe) = event_detect.on_pending_event(&pending, value) {
-        info!("Got resolved pending event {:?}", rpe);
-        ui_cmds
-            .send(UICommand {
-                command: rpe.command,
-            })
-            .await
-            .ok();
-    }
+    let column_opts = Options::input(
+        matrix
+            .columns
+            .iter()
+            .map(|pin| *pin as u32)
+            .collect::<Vec<u32>>(),
+    )
+    .bias(Bias::PullUp)
+    .consumer("xplane-location-web");
+    let columns = chip.request_lines(column_opts).await.map_err(|e| {
+        error!("Failed getting matrix {} columns: {:?}", matrix.name, e);
+        e
+    })?;
 
-    Ok(())
+    let mut scanner = MatrixScanner::new(&matrix);
+    let mut timer = interval(Duration::from_millis(matrix.scan_interval_ms));
+    let mut active_layer = chip_matrix.target.layers.subscribe();
+    active_layer.mark_changed();
+
+    loop {
+        timer.tick().await;
+
+        if active_layer.has_changed().unwrap_or(false) {
+            scanner.set_layer(active_layer.borrow_and_update().layer.clone());
+        }
+
+        let mut scan = Vec::with_capacity(num_rows);
+        for row in 0..num_rows {
+            rows.set_values((0..num_rows).map(|r| r != row).collect::<Vec<bool>>())
+                .await?;
+            let values = columns.get_values([false; 64]).await?;
+            scan.push(values[..num_columns].iter().map(|high| !high).collect());
+        }
+        rows.set_values(vec![true; num_rows]).await?;
+
+        for command in scanner.on_scan(&scan) {
+            info!("Key {} of matrix {} released", command, matrix.name);
+            send_command(&chip_matrix.target, command).await;
+        }
+    }
 }
 
 fn map_edge(e: Edge) -> GpioEdge {
@@ -0,0 +1,206 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::collections::HashMap;
+
+use super::types::{MatrixInput, MatrixKey};
+
+#[derive(Debug, Default, Clone, Copy)]
+struct KeyState {
+    pressed: bool,
+    /// Consecutive scans that differ from `pressed`
+    changed_scans: u32,
+}
+
+/// Debounces the scans of a key matrix, and finds the keys that have
+/// been released. The scans are read by the caller, so that this can
+/// be tested without hardware.
+pub struct MatrixScanner {
+    columns: usize,
+    debounce_scans: u32,
+    keys: HashMap<(usize, usize), MatrixKey>,
+    /// The active layer, whose commands the keys send
+    layer: Option<String>,
+    states: Vec<KeyState>,
+}
+
+impl MatrixScanner {
+    pub fn new(matrix: &MatrixInput) -> MatrixScanner {
+        MatrixScanner {
+            columns: matrix.columns.len(),
+            debounce_scans: matrix.debounce_scans.max(1),
+            keys: matrix
+                .keys
+                .iter()
+                .map(|key| ((key.row, key.column), key.clone()))
+                .collect(),
+            layer: None,
+            states: vec![KeyState::default(); matrix.rows.len() * matrix.columns.len()],
+        }
+    }
+
+    pub fn set_layer(&mut self, layer: Option<String>) {
+        self.layer = layer;
+    }
+
+    /// `scan[row][column]` is true when the key reads as pressed. Returns
+    /// the commands of the keys that were released.
+    pub fn on_scan(&mut self, scan: &[Vec<bool>]) -> Vec<String> {
+        let ghosts = ghost_keys(scan);
+        let mut released = Vec::new();
+
+        for (row, columns) in scan.iter().enumerate() {
+            for (column, pressed) in columns.iter().take(self.columns).enumerate() {
+                let state = &mut self.states[row * self.columns + column];
+
+                // A key that may be a ghost keeps its state
+                if *pressed == state.pressed || ghosts.contains(&(row, column)) {
+                    state.changed_scans = 0;
+                    continue;
+                }
+
+                state.changed_scans += 1;
+                if state.changed_scans >= self.debounce_scans {
+                    state.pressed = *pressed;
+                    state.changed_scans = 0;
+                    if !state.pressed {
+                        if let Some(key) = self.keys.get(&(row, column)) {
+                            released.push(key.command_for(self.layer.as_deref()).to_owned());
+                        }
+                    }
+                }
+            }
+        }
+
+        released
+    }
+}
+
+/// Without diodes, three pressed keys at the corners of a rectangle
+/// make the fourth corner read as pressed too. Returns the keys of all
+/// such rectangles, as any of them can be the ghost.
+fn ghost_keys(scan: &[Vec<bool>]) -> Vec<(usize, usize)> {
+    let mut ghosts = Vec::new();
+
+    for (row1, columns1) in scan.iter().enumerate() {
+        for (row2, columns2) in scan.iter().enumerate().skip(row1 + 1) {
+            let common = columns1
+                .iter()
+                .zip(columns2.iter())
+                .enumerate()
+                .filter(|(_, (p1, p2))| **p1 && **p2)
+                .map(|(column, _)| column)
+                .collect::<Vec<usize>>();
+
+            if common.len() >= 2 {
+                for column in common {
+                    ghosts.push((row1, column));
+                    ghosts.push((row2, column));
+                }
+            }
+        }
+    }
+
+    ghosts
+}
+
+#[cfg(test)]
+mod matrix_tests {
+    use std::collections::BTreeMap;
+
+    use super::MatrixScanner;
+    use crate::gpio::types::{MatrixInput, MatrixKey};
+
+    fn keypad() -> MatrixScanner {
+        let key = |row, column, command: &str| MatrixKey {
+            row,
+            column,
+            command: String::from(command),
+            layers: BTreeMap::from([(String::from("FMS"), format!("fms_{}", command))]),
+        };
+        MatrixScanner::new(&MatrixInput {
+            name: String::from("CDU"),
+            rows: vec![5, 6],
+            columns: vec![9, 10],
+            keys: vec![
+                key(0, 0, "key_1"),
+                key(0, 1, "key_2"),
+                key(1, 0, "key_3"),
+                key(1, 1, "key_4"),
+            ],
+            scan_interval_ms: 5,
+            debounce_scans: 3,
+        })
+    }
+
+    /// A scan where the given (row, column) keys are pressed
+    fn scan(pressed: &[(usize, usize)]) -> Vec<Vec<bool>> {
+        (0..2)
+            .map(|row| {
+                (0..2)
+                    .map(|column| pressed.contains(&(row, column)))
+                    .collect()
+            })
+            .collect()
+    }
+
+    fn scan_times(
+        scanner: &mut MatrixScanner,
+        pressed: &[(usize, usize)],
+        n: usize,
+    ) -> Vec<String> {
+        (0..n)
+            .flat_map(|_| scanner.on_scan(&scan(pressed)))
+            .collect()
+    }
+
+    #[test]
+    fn press_and_release() {
+        let mut scanner = keypad();
+
+        assert!(scan_times(&mut scanner, &[(1, 0)], 5).is_empty());
+        assert!(scan_times(&mut scanner, &[], 2).is_empty());
+        assert_eq!(scan_times(&mut scanner, &[], 1), vec!["key_3"]);
+        assert!(scan_times(&mut scanner, &[], 5).is_empty());
+    }
+
+    #[test]
+    fn bounce_is_ignored() {
+        let mut scanner = keypad();
+
+        // Too short to be a press
+        assert!(scan_times(&mut scanner, &[(0, 1)], 2).is_empty());
+        assert!(scan_times(&mut scanner, &[], 5).is_empty());
+
+        // Bounces while released
+        scan_times(&mut scanner, &[(0, 1)], 3);
+        assert!(scan_times(&mut scanner, &[], 2).is_empty());
+        assert!(scan_times(&mut scanner, &[(0, 1)], 1).is_empty());
+        assert!(scan_times(&mut scanner, &[], 2).is_empty());
+        assert_eq!(scan_times(&mut scanner, &[], 1), vec!["key_2"]);
+    }
+
+    #[test]
+    fn ghost_key_is_blocked() {
+        let mut scanner = keypad();
+
+        scan_times(&mut scanner, &[(0, 0), (0, 1)], 3);
+        // (1, 1) pressed: (1, 0) reads as pressed too
+        scan_times(&mut scanner, &[(0, 0), (0, 1), (1, 0), (1, 1)], 5);
+        assert_eq!(scan_times(&mut scanner, &[(0, 0)], 3), vec!["key_2"]);
+        // Neither key of the second row was registered
+        assert_eq!(scan_times(&mut scanner, &[], 3), vec!["key_1"]);
+    }
+
+    #[test]
+    fn layer_commands() {
+        let mut scanner = keypad();
+
+        scanner.set_layer(Some(String::from("FMS")));
+        scan_times(&mut scanner, &[(0, 0)], 3);
+        assert_eq!(scan_times(&mut scanner, &[], 3), vec!["fms_key_1"]);
+
+        scanner.set_layer(Some(String::from("RADIO")));
+        scan_times(&mut scanner, &[(0, 0)], 3);
+        assert_eq!(scan_times(&mut scanner, &[], 3), vec!["key_1"]);
+    }
+}
@@ -0,0 +1,138 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::{collections::BTreeSet, time::Duration};
+
+use super::types::GpioOutput;
+use crate::xpc_types::DatarefValues;
+
+/// The outputs go to the safe state (lights off) when no packets have
+/// been received from X-Plane for this long.
+pub const STALE_DATA: Duration = Duration::from_secs(5);
+
+/// Returns the line value of the output at `now`. `datarefs` is None
+/// when the data from X-Plane is stale.
+pub fn output_level(
+    output: &GpioOutput,
+    datarefs: Option<&DatarefValues>,
+    active_rules: &BTreeSet<String>,
+    now: Duration,
+) -> bool {
+    let on = datarefs.is_some_and(|datarefs| {
+        output
+            .condition
+            .as_ref()
+            .is_some_and(|c| c.evaluate(datarefs))
+            || output
+                .rule
+                .as_ref()
+                .is_some_and(|r| active_rules.contains(r))
+    });
+    let lit = match output.blink_ms {
+        Some(blink_ms) if blink_ms > 0 => {
+            on && (now.as_millis() / blink_ms as u128).is_multiple_of(2)
+        }
+        _ => on,
+    };
+
+    lit != output.active_low
+}
+
+#[cfg(test)]
+mod gpio_outputs_tests {
+    use std::{collections::BTreeSet, time::Duration};
+
+    use super::output_level;
+    use crate::conditions::Condition;
+    use crate::gpio::types::GpioOutput;
+    use crate::xpc_types::ReceivedDatarefs;
+
+    fn gear_light(blink_ms: Option<u64>, active_low: bool) -> GpioOutput {
+        GpioOutput {
+            gpio: 20,
+            condition: Some(Condition::Bool {
+                key: String::from("is-gear-unsafe"),
+                value: true,
+            }),
+            rule: None,
+            blink_ms,
+            active_low,
+        }
+    }
+
+    fn ms(ms: u64) -> Duration {
+        Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn steady_and_blinking() {
+        let in_transit = ReceivedDatarefs {
+            is_gear_unsafe: true,
+            ..Default::default()
+        }
+        .values();
+        let locked = ReceivedDatarefs::default().values();
+
+        let no_rules = BTreeSet::new();
+
+        let steady = gear_light(None, false);
+        assert!(output_level(&steady, Some(&in_transit), &no_rules, ms(0)));
+        assert!(output_level(&steady, Some(&in_transit), &no_rules, ms(700)));
+        assert!(!output_level(&steady, Some(&locked), &no_rules, ms(0)));
+
+        let blinking = gear_light(Some(500), false);
+        assert!(output_level(
+            &blinking,
+            Some(&in_transit),
+            &no_rules,
+            ms(100)
+        ));
+        assert!(!output_level(
+            &blinking,
+            Some(&in_transit),
+            &no_rules,
+            ms(600)
+        ));
+        assert!(output_level(
+            &blinking,
+            Some(&in_transit),
+            &no_rules,
+            ms(1100)
+        ));
+        assert!(!output_level(&blinking, Some(&locked), &no_rules, ms(100)));
+    }
+
+    #[test]
+    fn safe_state_when_stale() {
+        let no_rules = BTreeSet::new();
+        assert!(!output_level(
+            &gear_light(None, false),
+            None,
+            &no_rules,
+            ms(0)
+        ));
+        assert!(output_level(
+            &gear_light(None, true),
+            None,
+            &no_rules,
+            ms(0)
+        ));
+    }
+
+    #[test]
+    fn rule_output() {
+        let mut alert = gear_light(None, false);
+        alert.condition = None;
+        alert.rule = Some(String::from("gear-alert"));
+        let datarefs = ReceivedDatarefs::default().values();
+
+        let active = BTreeSet::from([String::from("gear-alert")]);
+        assert!(output_level(&alert, Some(&datarefs), &active, ms(0)));
+        assert!(!output_level(
+            &alert,
+            Some(&datarefs),
+            &BTreeSet::new(),
+            ms(0)
+        ));
+        assert!(!output_level(&alert, None, &active, ms(0)));
+    }
+}
@@ -0,0 +1,639 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::collections::{BTreeMap, HashMap};
+use std::io;
+use std::time::Duration;
+
+use futures_util::future::try_join_all;
+use futures_util::{FutureExt, StreamExt};
+use log::{debug, error, info, warn};
+use tokio::select;
+use tokio::sync::{mpsc::Sender, watch};
+use tokio::time::interval;
+use tokio_util::time::delay_queue::Expired;
+use tokio_util::time::DelayQueue;
+
+use super::event_detect::GpioEventDetect;
+use super::input_config::read_input_config;
+use super::layers::{ActiveLayer, Layers};
+use super::outputs::STALE_DATA;
+use super::source::{read_edge_script, InjectedSource, InputSource, PinEvent, ScriptSource};
+use super::switch_sync::{sync_command, ConnectionWatch};
+use super::types::{
+    ExpanderConfig, GpioEvent, GpioInput, GpioOutput, InputGroup, MatrixInput, PendingEvent,
+    SwitchInput,
+};
+use super::InputBackend;
+use crate::channels::ChannelsUIEndpoint;
+use crate::metrics::METRICS;
+use crate::xpc_types::{ReceivedDatarefs, UICommand};
+
+const SYNC_CHECK_INTERVAL: Duration = Duration::from_millis(500);
+
+/// Runs the inputs of the configuration with virtual edges instead of
+/// the GPIO chips. Outputs, matrices and expanders are not simulated.
+pub async fn virtual_main(
+    endpoints: Vec<ChannelsUIEndpoint>,
+    config_file: String,
+    backend: InputBackend,
+    layers: Layers,
+) -> Result<(), io::Error> {
+    let config = read_input_config(&config_file)?;
+    layers.configure(config.layers);
+    let chips = inputs_by_chip(config.groups, &endpoints, &layers)?;
+    let script = match &backend {
+        InputBackend::Script(script_file) => read_edge_script(script_file)?,
+        _ => Vec::new(),
+    };
+
+    let mut runs = Vec::new();
+    for (chip_name, chip) in chips {
+        if !chip.outputs.is_empty() || !chip.matrices.is_empty() || !chip.expanders.is_empty() {
+            warn!(
+                "Outputs, matrices and expanders of {} are not simulated",
+                chip_name
+            );
+        }
+        if chip.inputs.inputs.is_empty() {
+            continue;
+        }
+
+        let pins = chip.inputs.pins();
+        info!("Simulating GPIOs {:?} of {}", pins, chip_name);
+        runs.push(match &backend {
+            InputBackend::Injected(edges) => {
+                let source = InjectedSource::new(&chip_name, &pins, edges.subscribe());
+                run_inputs(source, chip.inputs, layers.clone()).boxed()
+            }
+            _ => {
+                let source = ScriptSource::new(&chip_name, &pins, script.clone());
+                run_inputs(source, chip.inputs, layers.clone()).boxed()
+            }
+        });
+    }
+
+    try_join_all(runs).await?;
+
+    Ok(())
+}
+
+/// The inputs, outputs and expanders of one GPIO chip.
+#[derive(Default)]
+pub struct ChipInputs {
+    pub inputs: LineInputs,
+    pub outputs: Vec<ChipOutput>,
+    pub matrices: Vec<ChipMatrix>,
+    /// Expanders whose interrupt lines are on the chip
+    pub expanders: Vec<(ExpanderConfig, LineInputs)>,
+}
+
+/// Inputs read from the same lines, and the command channel of each
+/// line.
+#[derive(Default)]
+pub struct LineInputs {
+    pub inputs: Vec<GpioInput>,
+    pub targets: HashMap<usize, CommandTarget>,
+    /// Connections of the switches that are synchronized with or check
+    /// the sim state
+    pub sync_sources: HashMap<usize, DatarefSource>,
+}
+
+impl LineInputs {
+    pub fn add(&mut self, input: GpioInput, target: &CommandTarget, source: &DatarefSource) {
+        for pin in input.pins() {
+            self.targets.insert(pin, target.clone());
+        }
+        if let Some(switch) = input.as_switch().filter(|sw| sw.sync.is_some()) {
+            self.sync_sources.insert(switch.gpio, source.clone());
+        }
+        self.inputs.push(input);
+    }
+
+    pub fn pins(&self) -> Vec<usize> {
+        self.inputs.iter().flat_map(|input| input.pins()).collect()
+    }
+}
+
+/// A key matrix and the command channel of its connection.
+pub struct ChipMatrix {
+    pub matrix: MatrixInput,
+    pub target: CommandTarget,
+}
+
+/// Where the commands of an input go: the command channel of its
+/// connection, or the layers for layer commands.
+#[derive(Clone)]
+pub struct CommandTarget {
+    pub ui_cmds: Sender<UICommand>,
+    pub layers: Layers,
+}
+
+/// The datarefs of a connection.
+#[derive(Clone)]
+pub struct DatarefSource {
+    pub connection: String,
+    pub datarefs: watch::Receiver<ReceivedDatarefs>,
+    pub last_packet: watch::Receiver<Option<std::time::Instant>>,
+}
+
+impl DatarefSource {
+    pub fn is_fresh(&self) -> bool {
+        self.last_packet
+            .borrow()
+            .is_some_and(|last| last.elapsed() < STALE_DATA)
+    }
+}
+
+/// An output and the datarefs of its connection.
+pub struct ChipOutput {
+    pub output: GpioOutput,
+    pub source: DatarefSource,
+}
+
+/// Detects the input events from the edges of the source, and sends
+/// their commands.
+pub async fn run_inputs<S: InputSource>(
+    mut lines: S,
+    line_inputs: LineInputs,
+    layers: Layers,
+) -> Result<(), io::Error> {
+    let LineInputs {
+        inputs,
+        targets,
+        sync_sources,
+    } = line_inputs;
+
+    let mut active_layer = layers.subscribe();
+    let layer_inputs = |active: &ActiveLayer| {
+        inputs
+            .iter()
+            .map(|input| input.for_layer(active.layer.as_deref()))
+            .collect::<Vec<GpioInput>>()
+    };
+    let mut current_inputs = layer_inputs(&active_layer.borrow_and_update());
+    let mut toggles = toggle_switches(&current_inputs);
+
+    let mut event_detect = GpioEventDetect::new(&current_inputs);
+    let mut pending_events = DelayQueue::<PendingEvent>::new();
+    let mut connection_watches = HashMap::<String, ConnectionWatch>::new();
+    let mut sync_timer = interval(SYNC_CHECK_INTERVAL);
+
+    loop {
+        select! {
+            event = lines.read_event() => {
+                process_event(&event?, &mut event_detect, &mut pending_events, &targets).await;
+            },
+            e = pending_events.next(), if !pending_events.is_empty() => {
+                process_pending_event(e, &mut lines, &mut event_detect, &mut pending_events, &targets, &toggles, &sync_sources).await?;
+            },
+            Ok(()) = active_layer.changed() => {
+                current_inputs = layer_inputs(&active_layer.borrow_and_update());
+                toggles = toggle_switches(&current_inputs);
+                event_detect.update_inputs(&current_inputs);
+            },
+            _ = sync_timer.tick(), if !sync_sources.is_empty() => {
+                sync_switches(&current_inputs, &mut lines, &sync_sources, &mut connection_watches, &targets).await?;
+            }
+        }
+    }
+}
+
+/// Sends the commands that make the sim match the switches, once the
+/// switches' connection has (re)connected.
+async fn sync_switches(
+    inputs: &[GpioInput],
+    lines: &mut impl InputSource,
+    sync_sources: &HashMap<usize, DatarefSource>,
+    connection_watches: &mut HashMap<String, ConnectionWatch>,
+    targets: &HashMap<usize, CommandTarget>,
+) -> Result<(), io::Error> {
+    let now = std::time::Instant::now();
+    let mut connected = Vec::new();
+    for source in sync_sources.values() {
+        let watch = connection_watches
+            .entry(source.connection.clone())
+            .or_default();
+        if !connected.contains(&source.connection) && watch.update(source.is_fresh(), now) {
+            connected.push(source.connection.clone());
+        }
+    }
+
+    if connected.is_empty() {
+        return Ok(());
+    }
+
+    let values = lines.values().await?;
+
+    for input in inputs {
+        let Some(switch) = input.synced_on_connect() else {
+            continue;
+        };
+        let Some(source) = sync_sources.get(&switch.gpio) else {
+            continue;
+        };
+        if !connected.contains(&source.connection) {
+            continue;
+        }
+        let Some(value) = values.get(&switch.gpio) else {
+            continue;
+        };
+
+        let command = sync_command(&switch, *value, &source.datarefs.borrow());
+        match command {
+            Ok(Some(command)) => {
+                info!(
+                    "Synchronizing switch gpio{} with {}: {:?}",
+                    switch.gpio, source.connection, command
+                );
+                send_ui_command(&targets[&switch.gpio], command).await;
+            }
+            Ok(None) => (),
+            Err(e) => error!("Cannot synchronize switch gpio{}: {}", switch.gpio, e),
+        }
+    }
+
+    Ok(())
+}
+
+async fn process_event(
+    event: &PinEvent,
+    event_detect: &mut GpioEventDetect,
+    pending_events: &mut DelayQueue<PendingEvent>,
+    targets: &HashMap<usize, CommandTarget>,
+) {
+    let ui_cmds = &targets[&event.pin];
+
+    match event_detect.on_event(event.pin, event.edge.clone(), &event.time) {
+        Some(GpioEvent::Encoder(ee)) => {
+            info!(
+                "Got encoder event {:?} x{} from {}",
+                ee.event_type, ee.count, ee.encoder_name
+            );
+            for _ in 0..ee.count {
+                send_command(ui_cmds, ee.command.clone()).await;
+            }
+        }
+        Some(GpioEvent::Button(be)) => {
+            info!("Got button event {:?}", be);
+            send_command(ui_cmds, be.command).await;
+        }
+        Some(GpioEvent::Pending { debounce, event }) => {
+            info!("Got pending event {:?}", event);
+            pending_events.insert(event, debounce);
+        }
+        None => {}
+    }
+}
+
+/// The toggle switches of the inputs, as the switches that they work as
+fn toggle_switches(inputs: &[GpioInput]) -> HashMap<usize, SwitchInput> {
+    inputs
+        .iter()
+        .filter_map(|input| match input {
+            GpioInput::ToggleSwitch(toggle) => Some((toggle.gpio, toggle.to_switch())),
+            _ => None,
+        })
+        .collect()
+}
+
+async fn process_pending_event(
+    opt: Option<Expired<PendingEvent>>,
+    lines: &mut impl InputSource,
+    event_detect: &mut GpioEventDetect,
+    pending_events: &mut DelayQueue<PendingEvent>,
+    targets: &HashMap<usize, CommandTarget>,
+    toggles: &HashMap<usize, SwitchInput>,
+    sync_sources: &HashMap<usize, DatarefSource>,
+) -> Result<(), io::Error> {
+    debug!("pending event: {:?}", opt);
+
+    let pending = match opt {
+        Some(ev) => Ok(ev.into_inner()),
+        None => Err(io::Error::other("Got unexpected empty pending event")),
+    }?;
+
+    let pin = pending.pin();
+    let values = lines.values().await?;
+    let value_of = |pin: usize| {
+        values
+            .get(&pin)
+            .copied()
+            .ok_or_else(|| io::Error::other(format!("Pending event for unknown GPIO {}", pin)))
+    };
+
+    if let PendingEvent::SelectorPending(selector) = &pending {
+        let line_values = selector
+            .pins
+            .iter()
+            .map(|pin| value_of(*pin).map(|value| (*pin, value)))
+            .collect::<Result<HashMap<usize, bool>, io::Error>>()?;
+
+        if let Some(rse) = event_detect.on_pending_selector_event(selector, &line_values) {
+            info!("Selector {} turned to {}", rse.selector_name, rse.position);
+            send_ui_command(&targets[&pin], rse.command).await;
+        }
+        return Ok(());
+    }
+
+    let value = value_of(pin)?;
+
+    if let Some(rpe) = event_detect.on_pending_event(&pending, value) {
+        info!("Got resolved pending event {:?}", rpe);
+        if let Some((delay, next)) = rpe.next {
+            pending_events.insert(next, delay);
+        }
+
+        match (toggles.get(&pin), sync_sources.get(&pin)) {
+            (Some(_), Some(source)) if !source.is_fresh() => warn!(
+                "Unknown position of switch gpio{}, not toggling: no data from the sim",
+                pin
+            ),
+            (Some(switch), Some(source)) => {
+                let command = sync_command(switch, value, &source.datarefs.borrow());
+                match command {
+                    Ok(Some(command)) => send_ui_command(&targets[&pin], command).await,
+                    Ok(None) => info!("Sim is already in the position of switch gpio{}", pin),
+                    Err(e) => warn!(
+                        "Unknown position of switch gpio{}, not toggling: {}",
+                        pin, e
+                    ),
+                }
+            }
+            _ => send_command(&targets[&pin], rpe.command).await,
+        }
+    }
+
+    Ok(())
+}
+
+/// Collects the inputs of each chip, and finds the command channel of
+/// every line.
+pub fn inputs_by_chip(
+    groups: Vec<InputGroup>,
+    endpoints: &[ChannelsUIEndpoint],
+    layers: &Layers,
+) -> Result<BTreeMap<String, ChipInputs>, io::Error> {
+    let mut chips = BTreeMap::<String, ChipInputs>::new();
+
+    for group in groups {
+        let endpoint = match &group.connection {
+            Some(name) => endpoints.iter().find(|e| &e.connection == name),
+            None => endpoints.first(),
+        }
+        .ok_or_else(|| {
+            io::Error::other(format!(
+                "Unknown connection {} in GPIO configuration",
+                group.connection.as_deref().unwrap_or_default()
+            ))
+        })?;
+
+        let source = DatarefSource {
+            connection: endpoint.connection.clone(),
+            datarefs: endpoint.data.clone(),
+            last_packet: endpoint.last_packet.clone(),
+        };
+        let target = CommandTarget {
+            ui_cmds: endpoint.ui_cmds.clone(),
+            layers: layers.clone(),
+        };
+
+        let chip = chips.entry(group.chip).or_default();
+
+        if let Some(expander) = group.expander {
+            if !group.outputs.is_empty() || !group.matrices.is_empty() {
+                return Err(io::Error::other(format!(
+                    "Expander {:?} can only have inputs",
+                    expander.chip
+                )));
+            }
+            let mut inputs = LineInputs::default();
+            for input in group.inputs {
+                inputs.add(input, &target, &source);
+            }
+            chip.expanders.push((expander, inputs));
+            continue;
+        }
+
+        for input in group.inputs {
+            chip.inputs.add(input, &target, &source);
+        }
+        for output in group.outputs {
+            chip.outputs.push(ChipOutput {
+                output,
+                source: source.clone(),
+            });
+        }
+        for matrix in group.matrices {
+            chip.matrices.push(ChipMatrix {
+                matrix,
+                target: target.clone(),
+            });
+        }
+    }
+
+    Ok(chips)
+}
+
+pub async fn send_command(target: &CommandTarget, command: String) {
+    send_ui_command(target, UICommand::Command { command }).await;
+}
+
+async fn send_ui_command(target: &CommandTarget, command: UICommand) {
+    if let UICommand::Command { command } = &command {
+        if target.layers.handle_command(command) {
+            return;
+        }
+    }
+
+    METRICS.commands_sent.inc(&["gpio"]);
+    target.ui_cmds.send(command).await.ok();
+}
+
+#[cfg(test)]
+mod processing_tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    use tokio::sync::{mpsc, watch};
+    use tokio::time::timeout;
+
+    use super::{run_inputs, CommandTarget, DatarefSource, LineInputs};
+    use crate::gpio::layers::Layers;
+    use crate::gpio::source::{ScriptSource, ScriptedEdge};
+    use crate::gpio::types::{ButtonInput, ButtonLayer, GpioInput, ToggleSwitchInput};
+    use crate::xpc_types::{ReceivedDatarefs, UICommand};
+
+    fn button(gpio: usize, command: &str, layers: &[(&str, &str)]) -> GpioInput {
+        GpioInput::Button(ButtonInput {
+            gpio,
+            command: String::from(command),
+            layers: layers
+                .iter()
+                .map(|(layer, command)| {
+                    let command = ButtonLayer::Command(command.to_string());
+                    (layer.to_string(), command)
+                })
+                .collect(),
+            ..Default::default()
+        })
+    }
+
+    /// Runs the inputs with the script, and returns the commands sent
+    /// within the time.
+    async fn run_script(
+        buttons: Vec<GpioInput>,
+        layers: Layers,
+        script: &str,
+        within: Duration,
+    ) -> Vec<UICommand> {
+        run_script_with_data(buttons, layers, script, within, None).await
+    }
+
+    /// Runs the script with the latest packet from the sim received at
+    /// `last_packet`.
+    async fn run_script_with_data(
+        buttons: Vec<GpioInput>,
+        layers: Layers,
+        script: &str,
+        within: Duration,
+        last_packet: Option<Instant>,
+    ) -> Vec<UICommand> {
+        let (ui_cmds, mut commands) = mpsc::channel(16);
+        let (_datarefs_tx, datarefs) = watch::channel(ReceivedDatarefs::default());
+        let source = DatarefSource {
+            connection: String::from("default"),
+            datarefs,
+            last_packet: watch::channel(last_packet).1,
+        };
+        let target = CommandTarget {
+            ui_cmds,
+            layers: layers.clone(),
+        };
+        let mut inputs = LineInputs::default();
+        for button in buttons {
+            inputs.add(button, &target, &source);
+        }
+
+        let script: Vec<ScriptedEdge> = serde_json::from_str(script).unwrap();
+        let lines = ScriptSource::new("gpiochip0", &inputs.pins(), script);
+        let run = tokio::spawn(run_inputs(lines, inputs, layers));
+
+        let mut sent = Vec::new();
+        let _ = timeout(within, async {
+            while let Some(command) = commands.recv().await {
+                sent.push(command);
+            }
+        })
+        .await;
+        run.abort();
+        sent
+    }
+
+    #[tokio::test]
+    async fn scripted_button_press() {
+        let sent = run_script(
+            vec![button(16, "sim/autopilot/heading_sync", &[])],
+            Layers::default(),
+            r#"[
+                { "at_ms": 10, "pin": 16, "value": false },
+                { "at_ms": 100, "pin": 16, "value": true }
+            ]"#,
+            Duration::from_millis(300),
+        )
+        .await;
+
+        assert_eq!(sent, [UICommand::command("sim/autopilot/heading_sync")]);
+    }
+
+    #[tokio::test]
+    async fn layer_button_changes_commands() {
+        let layers = Layers::default();
+        layers.configure(["hdg", "crs"].map(String::from).to_vec());
+
+        let sent = run_script(
+            vec![
+                button(16, "layer:next", &[]),
+                button(17, "heading_sync", &[("crs", "course_sync")]),
+            ],
+            layers.clone(),
+            r#"[
+                { "at_ms": 10, "pin": 17, "value": false },
+                { "at_ms": 100, "pin": 17, "value": true },
+                { "at_ms": 200, "pin": 16, "value": false },
+                { "at_ms": 300, "pin": 16, "value": true },
+                { "at_ms": 400, "pin": 17, "value": false },
+                { "at_ms": 500, "pin": 17, "value": true }
+            ]"#,
+            Duration::from_millis(700),
+        )
+        .await;
+
+        assert_eq!(
+            sent,
+            [
+                UICommand::command("heading_sync"),
+                UICommand::command("course_sync")
+            ]
+        );
+        assert_eq!(layers.subscribe().borrow().layer.as_deref(), Some("crs"));
+    }
+
+    fn beacon_toggle() -> GpioInput {
+        GpioInput::ToggleSwitch(ToggleSwitchInput {
+            gpio: 22,
+            command: String::from("sim/lights/beacon_lights_toggle"),
+            state: String::from("beacon"),
+            on_when_low: true,
+            sync: false,
+            layers: BTreeMap::new(),
+        })
+    }
+
+    /// Turns the beacon toggle switch on.
+    const BEACON_ON: &str = r#"[ { "at_ms": 10, "pin": 22, "value": false } ]"#;
+
+    #[tokio::test]
+    async fn toggle_from_fresh_data() {
+        let sent = run_script_with_data(
+            vec![beacon_toggle()],
+            Layers::default(),
+            BEACON_ON,
+            Duration::from_millis(300),
+            Some(Instant::now()),
+        )
+        .await;
+
+        assert_eq!(
+            sent,
+            [UICommand::command("sim/lights/beacon_lights_toggle")]
+        );
+    }
+
+    #[tokio::test]
+    async fn no_toggle_without_data() {
+        let sent = run_script_with_data(
+            vec![beacon_toggle()],
+            Layers::default(),
+            BEACON_ON,
+            Duration::from_millis(300),
+            None,
+        )
+        .await;
+
+        assert_eq!(sent, []);
+    }
+
+    #[tokio::test]
+    async fn no_toggle_from_stale_data() {
+        let sent = run_script_with_data(
+            vec![beacon_toggle()],
+            Layers::default(),
+            BEACON_ON,
+            Duration::from_millis(300),
+            Instant::now().checked_sub(Duration::from_secs(60)),
+        )
+        .await;
+
+        assert_eq!(sent, []);
+    }
+}
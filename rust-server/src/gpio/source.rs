@@ -0,0 +1,240 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs::File;
+use std::future::{pending, Future};
+use std::io::{self, BufReader};
+use std::time::Duration;
+
+use log::{error, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::{sleep_until, Instant};
+
+use super::types::{default_chip, Edge};
+
+/// An edge of an input pin.
+#[derive(Debug, Clone)]
+pub struct PinEvent {
+    pub pin: usize,
+    pub edge: Edge,
+    pub time: Duration,
+}
+
+/// Where the edges and the values of input pins are read from: the
+/// GPIO chip, an expander, or a simulation.
+pub trait InputSource: Send {
+    /// Waits for the next edge. Must be cancel safe.
+    fn read_event(&mut self) -> impl Future<Output = Result<PinEvent, io::Error>> + Send;
+
+    /// The current value of each pin
+    fn values(&mut self) -> impl Future<Output = Result<HashMap<usize, bool>, io::Error>> + Send;
+}
+
+/// A pin of a chip set to a level by a simulation or through the web.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct VirtualEdge {
+    #[serde(default = "default_chip")]
+    pub chip: String,
+    pub pin: usize,
+    /// The new level; false is low, e.g., a pressed button
+    pub value: bool,
+}
+
+/// An edge of a script, at the given time from the start.
+#[derive(Debug, Clone, Deserialize)]
+pub struct ScriptedEdge {
+    pub at_ms: u64,
+    #[serde(flatten)]
+    pub edge: VirtualEdge,
+}
+
+pub fn read_edge_script(script_file: &str) -> Result<Vec<ScriptedEdge>, io::Error> {
+    let file = File::open(script_file).map_err(|e| {
+        error!("Reading GPIO script {} failed: {:?}", script_file, e);
+        e
+    })?;
+    serde_json::from_reader(BufReader::new(file)).map_err(|e| {
+        let s = e.to_string();
+        error!("Reading GPIO script {} failed: {:?}", script_file, s);
+        io::Error::other(s)
+    })
+}
+
+/// The levels of the pins of one virtual chip. The pins start high, as
+/// with the pull-ups of the real inputs.
+struct VirtualPins {
+    chip: String,
+    values: HashMap<usize, bool>,
+    start: Instant,
+}
+
+impl VirtualPins {
+    fn new(chip: &str, pins: &[usize]) -> VirtualPins {
+        VirtualPins {
+            chip: chip.to_string(),
+            values: pins.iter().map(|pin| (*pin, true)).collect(),
+            start: Instant::now(),
+        }
+    }
+
+    /// Returns the edge, if the virtual edge changes a pin of the chip.
+    fn apply(&mut self, edge: &VirtualEdge) -> Option<PinEvent> {
+        if edge.chip != self.chip {
+            return None;
+        }
+        let value = self.values.get_mut(&edge.pin)?;
+        if *value == edge.value {
+            return None;
+        }
+        *value = edge.value;
+
+        Some(PinEvent {
+            pin: edge.pin,
+            edge: if edge.value {
+                Edge::Rising
+            } else {
+                Edge::Falling
+            },
+            time: self.start.elapsed(),
+        })
+    }
+}
+
+/// Plays a script of timed edges. When the script ends, the pins keep
+/// their levels.
+pub struct ScriptSource {
+    pins: VirtualPins,
+    script: VecDeque<ScriptedEdge>,
+}
+
+impl ScriptSource {
+    pub fn new(chip: &str, pins: &[usize], script: Vec<ScriptedEdge>) -> ScriptSource {
+        let mut script = script
+            .into_iter()
+            .filter(|scripted| scripted.edge.chip == chip)
+            .collect::<Vec<ScriptedEdge>>();
+        script.sort_by_key(|scripted| scripted.at_ms);
+
+        ScriptSource {
+            pins: VirtualPins::new(chip, pins),
+            script: script.into(),
+        }
+    }
+}
+
+impl InputSource for ScriptSource {
+    async fn read_event(&mut self) -> Result<PinEvent, io::Error> {
+        loop {
+            let Some(next) = self.script.front() else {
+                return pending().await;
+            };
+            // The edge stays in the script until its time has come
+            sleep_until(self.pins.start + Duration::from_millis(next.at_ms)).await;
+
+            if let Some(scripted) = self.script.pop_front() {
+                if let Some(event) = self.pins.apply(&scripted.edge) {
+                    return Ok(event);
+                }
+            }
+        }
+    }
+
+    async fn values(&mut self) -> Result<HashMap<usize, bool>, io::Error> {
+        Ok(self.pins.values.clone())
+    }
+}
+
+/// Edges injected through the web server.
+pub struct InjectedSource {
+    pins: VirtualPins,
+    edges: broadcast::Receiver<VirtualEdge>,
+}
+
+impl InjectedSource {
+    pub fn new(
+        chip: &str,
+        pins: &[usize],
+        edges: broadcast::Receiver<VirtualEdge>,
+    ) -> InjectedSource {
+        InjectedSource {
+            pins: VirtualPins::new(chip, pins),
+            edges,
+        }
+    }
+}
+
+impl InputSource for InjectedSource {
+    async fn read_event(&mut self) -> Result<PinEvent, io::Error> {
+        loop {
+            match self.edges.recv().await {
+                Ok(edge) => {
+                    if let Some(event) = self.pins.apply(&edge) {
+                        return Ok(event);
+                    }
+                }
+                Err(RecvError::Lagged(n)) => {
+                    warn!("Lost {} injected edges of {}", n, self.pins.chip);
+                }
+                Err(RecvError::Closed) => return pending().await,
+            }
+        }
+    }
+
+    async fn values(&mut self) -> Result<HashMap<usize, bool>, io::Error> {
+        Ok(self.pins.values.clone())
+    }
+}
+
+#[cfg(test)]
+mod source_tests {
+    use tokio::sync::broadcast;
+
+    use super::{InjectedSource, InputSource, ScriptSource, ScriptedEdge, VirtualEdge};
+    use crate::gpio::types::Edge;
+
+    fn edge(chip: &str, pin: usize, value: bool) -> VirtualEdge {
+        VirtualEdge {
+            chip: String::from(chip),
+            pin,
+            value,
+        }
+    }
+
+    #[tokio::test]
+    async fn script_edges_in_time_order() {
+        let script: Vec<ScriptedEdge> = serde_json::from_str(
+            r#"[
+                { "at_ms": 20, "pin": 16, "value": true },
+                { "at_ms": 0, "pin": 16, "value": false },
+                { "at_ms": 5, "chip": "gpiochip1", "pin": 16, "value": true },
+                { "at_ms": 10, "pin": 16, "value": false },
+                { "at_ms": 10, "pin": 99, "value": false }
+            ]"#,
+        )
+        .unwrap();
+        let mut source = ScriptSource::new("gpiochip0", &[16, 17], script);
+
+        let first = source.read_event().await.unwrap();
+        assert_eq!((first.pin, first.edge), (16, Edge::Falling));
+        assert!(!source.values().await.unwrap()[&16]);
+        assert!(source.values().await.unwrap()[&17]);
+
+        // The edge at 10 ms does not change the level, and pin 99 is
+        // not an input
+        let second = source.read_event().await.unwrap();
+        assert_eq!((second.pin, second.edge), (16, Edge::Rising));
+        assert!(second.time >= std::time::Duration::from_millis(20));
+    }
+
+    #[tokio::test]
+    async fn injected_edges() {
+        let (sender, receiver) = broadcast::channel(16);
+        let mut source = InjectedSource::new("gpiochip0", &[5], receiver);
+
+        sender.send(edge("gpiochip1", 5, false)).unwrap();
+        sender.send(edge("gpiochip0", 5, false)).unwrap();
+
+        let event = source.read_event().await.unwrap();
+        assert_eq!((event.pin, event.edge), (5, Edge::Falling));
+        assert!(!source.values().await.unwrap()[&5]);
+    }
+}
@@ -0,0 +1,214 @@
+#![cfg_attr(not(target_os = "linux"), allow(dead_code))]
+
+use std::time::{Duration, Instant};
+
+use super::types::SwitchInput;
+use crate::xpc_types::{ReceivedDatarefs, UICommand};
+
+/// The switches are synchronized when the connection has received data
+/// for this long, so that the datarefs have arrived.
+pub const SYNC_DELAY: Duration = Duration::from_secs(2);
+
+/// Returns the command or dataref write that makes the sim match the
+/// line value of the switch, None if it already matches, or an error if
+/// the position of the sim is not known.
+pub fn sync_command(
+    switch: &SwitchInput,
+    line_value: bool,
+    datarefs: &ReceivedDatarefs,
+) -> Result<Option<UICommand>, String> {
+    let Some(sync) = switch.sync.as_ref() else {
+        return Ok(None);
+    };
+    let sim_on = datarefs
+        .value_of(&sync.key)
+        .ok_or_else(|| format!("No value for {}", sync.key))?
+        != 0.0;
+    let switch_on = line_value != sync.on_when_low;
+
+    if sim_on == switch_on {
+        return Ok(None);
+    }
+
+    Ok(Some(match &sync.dataref {
+        Some(dataref) => UICommand::SetDataref {
+            dataref: dataref.clone(),
+            value: if switch_on { 1.0 } else { 0.0 },
+        },
+        None if line_value => UICommand::command(&switch.command_high),
+        None => UICommand::command(&switch.command_low),
+    }))
+}
+
+/// Follows whether a connection receives data, to synchronize the
+/// switches once after each (re)connect.
+#[derive(Debug, Default)]
+pub struct ConnectionWatch {
+    connected_since: Option<Instant>,
+    synced: bool,
+}
+
+impl ConnectionWatch {
+    /// Returns true when the switches should be synchronized now.
+    pub fn update(&mut self, fresh: bool, now: Instant) -> bool {
+        if !fresh {
+            self.connected_since = None;
+            self.synced = false;
+            return false;
+        }
+
+        let since = *self.connected_since.get_or_insert(now);
+        if !self.synced && now.duration_since(since) >= SYNC_DELAY {
+            self.synced = true;
+            return true;
+        }
+
+        false
+    }
+}
+
+#[cfg(test)]
+mod switch_sync_tests {
+    use std::collections::BTreeMap;
+    use std::time::{Duration, Instant};
+
+    use super::{sync_command, ConnectionWatch, SYNC_DELAY};
+    use crate::gpio::types::{GpioInput, SwitchInput, SwitchSync, ToggleSwitchInput};
+    use crate::xpc_types::{ReceivedDatarefs, UICommand};
+
+    fn beacon_switch(dataref: Option<&str>) -> SwitchInput {
+        SwitchInput {
+            gpio: 17,
+            command_high: String::from("sim/lights/beacon_lights_off"),
+            command_low: String::from("sim/lights/beacon_lights_on"),
+            sync: Some(SwitchSync {
+                key: String::from("beacon"),
+                on_when_low: true,
+                dataref: dataref.map(String::from),
+            }),
+            layers: BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn commands_to_match_switch() {
+        let beacon_off = ReceivedDatarefs::default();
+        let beacon_on = ReceivedDatarefs {
+            beacon: true,
+            ..Default::default()
+        };
+        let switch = beacon_switch(None);
+
+        assert_eq!(
+            sync_command(&switch, false, &beacon_off),
+            Ok(Some(UICommand::command("sim/lights/beacon_lights_on")))
+        );
+        assert_eq!(
+            sync_command(&switch, true, &beacon_on),
+            Ok(Some(UICommand::command("sim/lights/beacon_lights_off")))
+        );
+        assert_eq!(sync_command(&switch, false, &beacon_on), Ok(None));
+        assert_eq!(sync_command(&switch, true, &beacon_off), Ok(None));
+    }
+
+    #[test]
+    fn dataref_write_to_match_switch() {
+        let switch = beacon_switch(Some("sim/cockpit/electrical/beacon_lights_on"));
+
+        assert_eq!(
+            sync_command(&switch, false, &ReceivedDatarefs::default()),
+            Ok(Some(UICommand::SetDataref {
+                dataref: String::from("sim/cockpit/electrical/beacon_lights_on"),
+                value: 1.0
+            }))
+        );
+    }
+
+    #[test]
+    fn toggle_only_when_not_in_position() {
+        let switch = ToggleSwitchInput {
+            gpio: 17,
+            command: String::from("sim/lights/beacon_lights_toggle"),
+            state: String::from("beacon"),
+            on_when_low: true,
+            sync: false,
+            layers: BTreeMap::new(),
+        }
+        .to_switch();
+        let beacon_on = ReceivedDatarefs {
+            beacon: true,
+            ..Default::default()
+        };
+        let toggle = Ok(Some(UICommand::command("sim/lights/beacon_lights_toggle")));
+
+        assert_eq!(sync_command(&switch, false, &beacon_on), Ok(None));
+        assert_eq!(sync_command(&switch, true, &beacon_on), toggle);
+        assert_eq!(
+            sync_command(&switch, false, &ReceivedDatarefs::default()),
+            toggle
+        );
+    }
+
+    #[test]
+    fn toggle_synced_on_connect_only_if_asked() {
+        let mut toggle = ToggleSwitchInput {
+            gpio: 17,
+            command: String::from("sim/lights/beacon_lights_toggle"),
+            state: String::from("beacon"),
+            on_when_low: true,
+            sync: false,
+            layers: BTreeMap::new(),
+        };
+        assert!(GpioInput::ToggleSwitch(toggle.clone())
+            .synced_on_connect()
+            .is_none());
+
+        toggle.sync = true;
+        assert!(GpioInput::ToggleSwitch(toggle)
+            .synced_on_connect()
+            .is_some());
+
+        let mut switch = beacon_switch(None);
+        assert!(GpioInput::Switch(switch.clone())
+            .synced_on_connect()
+            .is_some());
+        switch.sync = None;
+        assert!(GpioInput::Switch(switch).synced_on_connect().is_none());
+    }
+
+    #[test]
+    fn no_sync_configured() {
+        let mut switch = beacon_switch(None);
+        switch.sync = None;
+
+        assert_eq!(
+            sync_command(&switch, false, &ReceivedDatarefs::default()),
+            Ok(None)
+        );
+    }
+
+    #[test]
+    fn unknown_state() {
+        let mut switch = beacon_switch(None);
+        switch.sync.as_mut().unwrap().key = String::from("latitude");
+
+        assert!(sync_command(&switch, false, &ReceivedDatarefs::default()).is_err());
+    }
+
+    #[test]
+    fn sync_once_per_connect() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut watch = ConnectionWatch::default();
+
+        assert!(!watch.update(false, at(0)));
+        assert!(!watch.update(true, at(100)));
+        assert!(watch.update(true, at(100) + SYNC_DELAY));
+        assert!(!watch.update(true, at(5000)));
+
+        // Reconnect
+        assert!(!watch.update(false, at(10000)));
+        assert!(!watch.update(true, at(11000)));
+        assert!(watch.update(true, at(11000) + SYNC_DELAY));
+    }
+}
@@ -1,10 +1,14 @@
 #![cfg_attr(not(target_os = "linux"), allow(dead_code))]
 
+use std::collections::BTreeMap;
 use std::time::Duration;
 
 use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+use crate::conditions::Condition;
+use crate::xpc_types::UICommand;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub enum EncoderEventType {
     Right,
     Left,
@@ -15,6 +19,8 @@ pub struct EncoderEvent {
     pub event_type: EncoderEventType,
     pub encoder_name: String,
     pub command: String,
+    /// How many times the command is sent
+    pub count: u32,
 }
 
 #[derive(Debug, Clone)]
@@ -25,6 +31,9 @@ pub struct ButtonEvent {
 #[derive(Debug, Clone)]
 pub struct ResolvedPendingEvent {
     pub command: String,
+    /// Event to check again after the delay, e.g., the next repeat of a
+    /// held button
+    pub next: Option<(Duration, PendingEvent)>,
 }
 
 #[derive(Debug, Clone)]
@@ -32,15 +41,49 @@ pub struct SwitchPendingEvent {
     pub pin: usize,
 }
 
+#[derive(Debug, Clone)]
+pub struct ButtonHeldEvent {
+    pub pin: usize,
+    /// Number of the press, so that an old timer is not taken as a new hold
+    pub press: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct ButtonClickEvent {
+    pub pin: usize,
+    /// Number of the release that may become the first of a double press
+    pub click: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct SelectorPendingEvent {
+    /// The first pin of the selector
+    pub pin: usize,
+    pub pins: Vec<usize>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ResolvedSelectorEvent {
+    pub selector_name: String,
+    pub position: String,
+    pub command: UICommand,
+}
+
 #[derive(Debug, Clone)]
 pub enum PendingEvent {
     SwitchPending(SwitchPendingEvent),
+    ButtonHeld(ButtonHeldEvent),
+    ButtonClick(ButtonClickEvent),
+    SelectorPending(SelectorPendingEvent),
 }
 
 impl PendingEvent {
     pub fn pin(&self) -> usize {
         match self {
             PendingEvent::SwitchPending(SwitchPendingEvent { pin, .. }) => *pin,
+            PendingEvent::ButtonHeld(ButtonHeldEvent { pin, .. }) => *pin,
+            PendingEvent::ButtonClick(ButtonClickEvent { pin, .. }) => *pin,
+            PendingEvent::SelectorPending(SelectorPendingEvent { pin, .. }) => *pin,
         }
     }
 }
@@ -60,12 +103,161 @@ pub struct EncoderInput {
     pub gpio1: usize,
     pub gpio2: usize,
     pub command: EncoderCommands,
+    #[serde(default)]
+    pub resolution: EncoderResolution,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub acceleration: Vec<AccelerationStep>,
+    /// Commands used instead of `command` when the layer is active
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, EncoderLayer>,
 }
 
+/// The commands of an encoder in a layer. The acceleration steps without
+/// coarse commands in the layer send its fine commands.
 #[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EncoderLayer {
+    #[serde(flatten)]
+    pub command: EncoderCommands,
+    /// Coarse commands of the acceleration steps, in the same order
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub coarse: Vec<CoarseCommands>,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct CoarseCommands {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd_right: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd_left: Option<String>,
+}
+
+/// How many of the four quadrature transitions there are per detent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+pub enum EncoderResolution {
+    /// One detent per full cycle, resting with both lines high
+    #[default]
+    #[serde(rename = "full")]
+    Full,
+    /// Two detents per cycle, resting with both lines high or both low
+    #[serde(rename = "half")]
+    Half,
+    /// A detent at every transition
+    #[serde(rename = "quarter")]
+    Quarter,
+}
+
+impl EncoderResolution {
+    pub fn transitions_per_detent(&self) -> i8 {
+        match self {
+            EncoderResolution::Full => 4,
+            EncoderResolution::Half => 2,
+            EncoderResolution::Quarter => 1,
+        }
+    }
+
+    /// Checks whether the encoder rests in a detent when its lines
+    /// have the values `ab`.
+    pub fn is_detent(&self, ab: u8) -> bool {
+        match self {
+            EncoderResolution::Full => ab == 0b11,
+            EncoderResolution::Half => ab == 0b11 || ab == 0b00,
+            EncoderResolution::Quarter => true,
+        }
+    }
+}
+
+/// Applies to a detent that comes within `within_ms` of the previous
+/// detent in the same direction. The step with the smallest matching
+/// `within_ms` is used.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct AccelerationStep {
+    pub within_ms: u64,
+    #[serde(default = "default_multiplier")]
+    pub multiplier: u32,
+    /// Coarse commands sent instead of `cmd_right` and `cmd_left`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd_right: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cmd_left: Option<String>,
+}
+
+fn default_multiplier() -> u32 {
+    1
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 pub struct ButtonInput {
     pub gpio: usize,
+    /// Sent when the button is released after a short press
     pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_press: Option<LongPress>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub double_press: Option<DoublePress>,
+    /// Takes precedence over `long_press` if both have been given
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold_repeat: Option<HoldRepeat>,
+    /// Commands used instead of the button's own when the layer is
+    /// active
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, ButtonLayer>,
+}
+
+/// The commands of a button in a layer: only the short press command,
+/// or also those of the other actions of the button.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum ButtonLayer {
+    Command(String),
+    Commands(ButtonCommands),
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ButtonCommands {
+    pub command: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub long_press: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub double_press: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub hold_repeat: Option<String>,
+}
+
+impl ButtonLayer {
+    pub fn commands(&self) -> ButtonCommands {
+        match self {
+            ButtonLayer::Command(command) => ButtonCommands {
+                command: command.clone(),
+                long_press: None,
+                double_press: None,
+                hold_repeat: None,
+            },
+            ButtonLayer::Commands(commands) => commands.clone(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct LongPress {
+    pub command: String,
+    pub threshold_ms: u64,
+}
+
+/// With a double press action, the short press command is sent only
+/// after the window has passed without a second press.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DoublePress {
+    pub command: String,
+    pub window_ms: u64,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct HoldRepeat {
+    /// Defaults to the short press command
+    #[serde(default)]
+    pub command: Option<String>,
+    pub delay_ms: u64,
+    pub rate_ms: u64,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -73,6 +265,104 @@ pub struct SwitchInput {
     pub gpio: usize,
     pub command_high: String,
     pub command_low: String,
+    /// Makes the sim match the switch when connecting to X-Plane
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sync: Option<SwitchSync>,
+    /// Commands used instead of `command_high` and `command_low` when
+    /// the layer is active
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, SwitchCommands>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchCommands {
+    pub command_high: String,
+    pub command_low: String,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SwitchSync {
+    /// Key of the matching value in the received datarefs, e.g., "beacon"
+    pub key: String,
+    /// The value is on (non-zero) when the line is low
+    #[serde(default = "default_true")]
+    pub on_when_low: bool,
+    /// Writes this dataref (1 or 0) instead of sending the command
+    #[serde(default)]
+    pub dataref: Option<String>,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+/// A switch bound to a toggle command. The toggle is sent only if the
+/// sim is not already in the switch's position.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToggleSwitchInput {
+    pub gpio: usize,
+    pub command: String,
+    /// Key of the state in the received datarefs, e.g., "beacon"
+    pub state: String,
+    /// The state is on when the line is low
+    #[serde(default = "default_true")]
+    pub on_when_low: bool,
+    /// Also makes the sim match the switch when connecting to X-Plane
+    #[serde(default)]
+    pub sync: bool,
+    /// Toggle commands used instead of `command` when the layer is active
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, String>,
+}
+
+impl ToggleSwitchInput {
+    /// A switch that sends the toggle for both positions, and checks the
+    /// state before sending it.
+    pub fn to_switch(&self) -> SwitchInput {
+        SwitchInput {
+            gpio: self.gpio,
+            command_high: self.command.clone(),
+            command_low: self.command.clone(),
+            sync: Some(SwitchSync {
+                key: self.state.clone(),
+                on_when_low: self.on_when_low,
+                dataref: None,
+            }),
+            layers: self
+                .layers
+                .iter()
+                .map(|(layer, command)| {
+                    let commands = SwitchCommands {
+                        command_high: command.clone(),
+                        command_low: command.clone(),
+                    };
+                    (layer.clone(), commands)
+                })
+                .collect(),
+        }
+    }
+}
+
+/// A rotary or multi-position switch wired to several pins. Each
+/// position is recognized by the pins that are pulled low in it.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectorInput {
+    pub name: String,
+    pub gpios: Vec<usize>,
+    pub positions: Vec<SelectorPosition>,
+    /// Actions used instead of those of the positions when the layer is
+    /// active, by position name
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, BTreeMap<String, UICommand>>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SelectorPosition {
+    pub name: String,
+    /// The pins that are low in this position; the other pins are high
+    pub low: Vec<usize>,
+    /// Sent when the selector is turned to this position
+    pub action: UICommand,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -84,6 +374,283 @@ pub enum GpioInput {
     Button(ButtonInput),
     #[serde(rename = "switch")]
     Switch(SwitchInput),
+    #[serde(rename = "toggle_switch")]
+    ToggleSwitch(ToggleSwitchInput),
+    #[serde(rename = "selector")]
+    Selector(SelectorInput),
+}
+
+/// Inputs whose commands are sent to the named X-Plane connection, or
+/// to the first connection if no name has been given.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct InputGroup {
+    #[serde(default)]
+    pub connection: Option<String>,
+    /// GPIO chip of the inputs; the pins are its line offsets
+    #[serde(default = "default_chip")]
+    pub chip: String,
+    #[serde(default)]
+    pub inputs: Vec<GpioInput>,
+    /// Lights driven by the datarefs of the connection
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub outputs: Vec<GpioOutput>,
+    /// Scanned key matrices
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matrices: Vec<MatrixInput>,
+    /// If set, the pins of the inputs are the pins of this I2C expander
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expander: Option<ExpanderConfig>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ExpanderConfig {
+    #[serde(flatten)]
+    pub chip: ExpanderChip,
+    /// Line of the group's chip that the INT pin of the expander is
+    /// connected to. Without it, the expander is polled.
+    #[serde(default)]
+    pub interrupt: Option<usize>,
+    #[serde(default = "default_expander_poll_ms")]
+    pub poll_ms: u64,
+}
+
+fn default_expander_poll_ms() -> u64 {
+    10
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum ExpanderChip {
+    /// 16 pins: 0 to 7 are GPA0 to GPA7, 8 to 15 are GPB0 to GPB7
+    #[serde(rename = "mcp23017")]
+    Mcp23017 {
+        device: String,
+        #[serde(default = "default_expander_address")]
+        address: u16,
+    },
+    /// 8 pins, P0 to P7
+    #[serde(rename = "pcf8574")]
+    Pcf8574 {
+        device: String,
+        #[serde(default = "default_expander_address")]
+        address: u16,
+    },
+}
+
+fn default_expander_address() -> u16 {
+    0x20
+}
+
+impl ExpanderChip {
+    pub fn num_lines(&self) -> u32 {
+        match self {
+            ExpanderChip::Mcp23017 { .. } => 16,
+            ExpanderChip::Pcf8574 { .. } => 8,
+        }
+    }
+}
+
+/// A keyboard matrix. The rows are driven low one at a time, and the
+/// columns are read with pull-ups: a pressed key pulls its column low.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixInput {
+    pub name: String,
+    pub rows: Vec<usize>,
+    pub columns: Vec<usize>,
+    pub keys: Vec<MatrixKey>,
+    #[serde(default = "default_scan_interval_ms")]
+    pub scan_interval_ms: u64,
+    /// A key changes state when it reads the same for this many scans
+    #[serde(default = "default_debounce_scans")]
+    pub debounce_scans: u32,
+}
+
+fn default_scan_interval_ms() -> u64 {
+    5
+}
+
+fn default_debounce_scans() -> u32 {
+    3
+}
+
+/// A key of a matrix, by row and column index. The command is sent when
+/// the key is released, like the command of a button.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MatrixKey {
+    pub row: usize,
+    pub column: usize,
+    pub command: String,
+    /// Commands used instead of `command` when the layer is active
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub layers: BTreeMap<String, String>,
+}
+
+impl MatrixKey {
+    /// The command of the key in the layer
+    pub fn command_for(&self, layer: Option<&str>) -> &str {
+        layer
+            .and_then(|layer| self.layers.get(layer))
+            .unwrap_or(&self.command)
+    }
+}
+
+impl MatrixInput {
+    pub fn pins(&self) -> Vec<usize> {
+        self.rows
+            .iter()
+            .chain(self.columns.iter())
+            .copied()
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct GpioOutput {
+    pub gpio: usize,
+    /// The light is on when the condition is true
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub condition: Option<Condition>,
+    /// ... or when the server-side rule with this name is active
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule: Option<String>,
+    /// Blink with this half-period instead of staying on, e.g., while
+    /// the gear is in transit
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub blink_ms: Option<u64>,
+    /// The light is on when the line is low
+    #[serde(default)]
+    pub active_low: bool,
+}
+
+pub fn default_chip() -> String {
+    String::from("gpiochip0")
+}
+
+impl GpioInput {
+    pub fn pins(&self) -> Vec<usize> {
+        match self {
+            GpioInput::Encoder(enc) => Vec::from([enc.gpio1, enc.gpio2]),
+            GpioInput::Button(b) => Vec::from([b.gpio]),
+            GpioInput::Switch(sw) => Vec::from([sw.gpio]),
+            GpioInput::ToggleSwitch(sw) => Vec::from([sw.gpio]),
+            GpioInput::Selector(sel) => sel.gpios.clone(),
+        }
+    }
+
+    /// The input with the commands of the layer, or the default commands
+    /// if the input has none for it.
+    pub fn for_layer(&self, layer: Option<&str>) -> GpioInput {
+        let Some(layer) = layer else {
+            return self.clone();
+        };
+
+        match self {
+            GpioInput::Encoder(enc) => match enc.layers.get(layer) {
+                Some(layer) => GpioInput::Encoder(EncoderInput {
+                    command: layer.command.clone(),
+                    acceleration: enc
+                        .acceleration
+                        .iter()
+                        .enumerate()
+                        .map(|(i, step)| {
+                            let coarse = layer.coarse.get(i).cloned().unwrap_or_default();
+                            AccelerationStep {
+                                cmd_right: coarse.cmd_right,
+                                cmd_left: coarse.cmd_left,
+                                ..step.clone()
+                            }
+                        })
+                        .collect(),
+                    ..enc.clone()
+                }),
+                None => self.clone(),
+            },
+            GpioInput::Button(b) => match b.layers.get(layer).map(ButtonLayer::commands) {
+                Some(commands) => GpioInput::Button(ButtonInput {
+                    command: commands.command,
+                    long_press: b.long_press.as_ref().map(|long_press| LongPress {
+                        command: commands
+                            .long_press
+                            .unwrap_or_else(|| long_press.command.clone()),
+                        ..long_press.clone()
+                    }),
+                    double_press: b.double_press.as_ref().map(|double_press| DoublePress {
+                        command: commands
+                            .double_press
+                            .unwrap_or_else(|| double_press.command.clone()),
+                        ..double_press.clone()
+                    }),
+                    hold_repeat: b.hold_repeat.as_ref().map(|hold_repeat| HoldRepeat {
+                        command: commands.hold_repeat.or_else(|| hold_repeat.command.clone()),
+                        ..hold_repeat.clone()
+                    }),
+                    ..b.clone()
+                }),
+                None => self.clone(),
+            },
+            GpioInput::Switch(sw) => match sw.layers.get(layer) {
+                Some(commands) => GpioInput::Switch(SwitchInput {
+                    command_high: commands.command_high.clone(),
+                    command_low: commands.command_low.clone(),
+                    ..sw.clone()
+                }),
+                None => self.clone(),
+            },
+            GpioInput::ToggleSwitch(sw) => match sw.layers.get(layer) {
+                Some(command) => GpioInput::ToggleSwitch(ToggleSwitchInput {
+                    command: command.clone(),
+                    ..sw.clone()
+                }),
+                None => self.clone(),
+            },
+            GpioInput::Selector(sel) => match sel.layers.get(layer) {
+                Some(actions) => GpioInput::Selector(SelectorInput {
+                    positions: sel
+                        .positions
+                        .iter()
+                        .map(|position| SelectorPosition {
+                            action: actions
+                                .get(&position.name)
+                                .unwrap_or(&position.action)
+                                .clone(),
+                            ..position.clone()
+                        })
+                        .collect(),
+                    ..sel.clone()
+                }),
+                None => self.clone(),
+            },
+        }
+    }
+
+    /// The layers that the input has commands for
+    pub fn layer_names(&self) -> Vec<&String> {
+        match self {
+            GpioInput::Encoder(enc) => enc.layers.keys().collect(),
+            GpioInput::Button(b) => b.layers.keys().collect(),
+            GpioInput::Switch(sw) => sw.layers.keys().collect(),
+            GpioInput::ToggleSwitch(sw) => sw.layers.keys().collect(),
+            GpioInput::Selector(sel) => sel.layers.keys().collect(),
+        }
+    }
+
+    /// The switch, or the switch that a toggle switch works as
+    pub fn as_switch(&self) -> Option<SwitchInput> {
+        match self {
+            GpioInput::Switch(sw) => Some(sw.clone()),
+            GpioInput::ToggleSwitch(sw) => Some(sw.to_switch()),
+            _ => None,
+        }
+    }
+
+    /// The switch to synchronize with the sim when connecting, if any
+    pub fn synced_on_connect(&self) -> Option<SwitchInput> {
+        match self {
+            GpioInput::Switch(sw) if sw.sync.is_some() => Some(sw.clone()),
+            GpioInput::ToggleSwitch(sw) if sw.sync => Some(sw.to_switch()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize)]
@@ -93,7 +660,7 @@ pub struct EncoderCommands {
     pub cmd_left: String,
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum Edge {
     Falling = 0,
     Rising = 1,
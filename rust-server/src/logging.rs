@@ -0,0 +1,311 @@
+use std::{
+    collections::VecDeque,
+    io::Write,
+    sync::{Arc, Mutex, RwLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use clap::ValueEnum;
+use log::{Log, Metadata, Record};
+use serde::Serialize;
+use tokio::sync::broadcast;
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LogLine {
+    /// Milliseconds since the Unix epoch
+    pub time: u64,
+    pub level: String,
+    pub module: String,
+    pub message: String,
+}
+
+impl LogLine {
+    fn from_record(record: &Record) -> LogLine {
+        LogLine {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|d| d.as_millis() as u64)
+                .unwrap_or(0),
+            level: record.level().to_string(),
+            module: record.target().to_string(),
+            message: record.args().to_string(),
+        }
+    }
+
+    /// Checks whether the line comes from a module whose path contains
+    /// `module`, e.g., "gpio" or "beacon".
+    pub fn is_from_module(&self, module: &str) -> bool {
+        self.module.split("::").any(|part| part.contains(module))
+    }
+}
+
+/// Handle for inspecting and changing the logging at runtime.
+#[derive(Clone)]
+pub struct LogControl {
+    state: Arc<LogState>,
+}
+
+struct LogState {
+    filter: RwLock<LogFilter>,
+    history: Mutex<VecDeque<LogLine>>,
+    history_size: usize,
+    live: broadcast::Sender<LogLine>,
+}
+
+struct LogFilter {
+    spec: String,
+    filter: env_logger::Logger,
+}
+
+impl LogFilter {
+    fn new(spec: &str) -> LogFilter {
+        LogFilter {
+            spec: spec.to_string(),
+            filter: env_logger::Builder::new().parse_filters(spec).build(),
+        }
+    }
+}
+
+/// Checks the directives of an env_logger filter specification, which
+/// env_logger itself only reports on stderr.
+fn validate_spec(spec: &str) -> Result<(), String> {
+    let directives = spec.split('/').next().unwrap_or_default();
+    if directives.trim().is_empty() {
+        return Err(String::from("Empty log level"));
+    }
+
+    for directive in directives.split(',').map(str::trim) {
+        let (module, level) = match directive.split_once('=') {
+            Some((module, level)) => (module, Some(level)),
+            None if directive.parse::<log::LevelFilter>().is_ok() => continue,
+            None => (directive, None),
+        };
+        let valid_module = !module.is_empty()
+            && module
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == ':');
+        if !valid_module {
+            return Err(format!(
+                "Invalid module in log level directive {}",
+                directive
+            ));
+        }
+        if level.is_some_and(|level| level.parse::<log::LevelFilter>().is_err()) {
+            return Err(format!(
+                "Invalid level in log level directive {}",
+                directive
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+struct ServerLogger {
+    output: env_logger::Logger,
+    state: Arc<LogState>,
+}
+
+/// Installs the logger. `level` is an env_logger filter specification
+/// like "info" or "xplane_location_web::gpio=debug". The RUST_LOG
+/// environment variable overrides it if it has been set.
+pub fn init_logging(level: &str, format: LogFormat, history_size: usize) -> LogControl {
+    let spec = std::env::var("RUST_LOG").unwrap_or_else(|_| level.to_string());
+
+    let mut builder = env_logger::Builder::new();
+    builder.filter_level(log::LevelFilter::Trace);
+    if format == LogFormat::Json {
+        builder.format(|buf, record| {
+            let line = serde_json::to_string(&LogLine::from_record(record)).unwrap_or_default();
+            writeln!(buf, "{}", line)
+        });
+    }
+
+    let (live, _) = broadcast::channel(64);
+    let state = Arc::new(LogState {
+        filter: RwLock::new(LogFilter::new(&spec)),
+        history: Mutex::new(VecDeque::with_capacity(history_size)),
+        history_size,
+        live,
+    });
+
+    let logger = ServerLogger {
+        output: builder.build(),
+        state: state.clone(),
+    };
+
+    let control = LogControl { state };
+    log::set_boxed_logger(Box::new(logger)).expect("Installing the logger failed");
+    control.apply_max_level();
+
+    control
+}
+
+impl LogControl {
+    pub fn level(&self) -> String {
+        self.state.filter.read().unwrap().spec.clone()
+    }
+
+    /// Changes the filter specification. An invalid specification
+    /// leaves the filter unchanged.
+    pub fn set_level(&self, spec: &str) -> Result<(), String> {
+        validate_spec(spec)?;
+        *self.state.filter.write().unwrap() = LogFilter::new(spec);
+        self.apply_max_level();
+        Ok(())
+    }
+
+    /// Returns the latest `count` log lines, optionally only from `module`.
+    pub fn history(&self, module: Option<&str>, count: usize) -> Vec<LogLine> {
+        let history = self.state.history.lock().unwrap();
+        let mut lines = history
+            .iter()
+            .rev()
+            .filter(|line| module.is_none_or(|m| line.is_from_module(m)))
+            .take(count)
+            .cloned()
+            .collect::<Vec<LogLine>>();
+        lines.reverse();
+        lines
+    }
+
+    pub fn subscribe(&self) -> broadcast::Receiver<LogLine> {
+        self.state.live.subscribe()
+    }
+
+    fn apply_max_level(&self) {
+        log::set_max_level(self.state.filter.read().unwrap().filter.filter());
+    }
+}
+
+impl LogState {
+    fn record(&self, line: LogLine) {
+        {
+            let mut history = self.history.lock().unwrap();
+            if history.len() >= self.history_size {
+                history.pop_front();
+            }
+            history.push_back(line.clone());
+        }
+        self.live.send(line).ok();
+    }
+}
+
+impl Log for ServerLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.state.filter.read().unwrap().filter.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if !self.state.filter.read().unwrap().filter.matches(record) {
+            return;
+        }
+
+        self.output.log(record);
+        self.state.record(LogLine::from_record(record));
+    }
+
+    fn flush(&self) {
+        self.output.flush();
+    }
+}
+
+#[cfg(test)]
+mod logging_tests {
+    use std::{
+        collections::VecDeque,
+        sync::{Arc, Mutex, RwLock},
+    };
+
+    use tokio::sync::broadcast;
+
+    use super::{LogControl, LogFilter, LogLine, LogState};
+
+    fn record(control: &LogControl, module: &str, message: &str) {
+        control.state.record(LogLine {
+            time: 0,
+            level: String::from("INFO"),
+            module: module.to_string(),
+            message: message.to_string(),
+        });
+    }
+
+    fn control(history_size: usize) -> LogControl {
+        let (live, _) = broadcast::channel(4);
+        LogControl {
+            state: Arc::new(LogState {
+                filter: RwLock::new(LogFilter::new("info")),
+                history: Mutex::new(VecDeque::new()),
+                history_size,
+                live,
+            }),
+        }
+    }
+
+    #[test]
+    fn history_is_bounded() {
+        let control = control(2);
+        record(&control, "xplane_location_web::gpio::linux", "1");
+        record(&control, "xplane_location_web::gpio::linux", "2");
+        record(&control, "xplane_location_web::gpio::linux", "3");
+
+        let messages = control
+            .history(None, 10)
+            .into_iter()
+            .map(|l| l.message)
+            .collect::<Vec<String>>();
+        assert_eq!(messages, ["2", "3"]);
+    }
+
+    #[test]
+    fn history_filtered_by_module() {
+        let control = control(10);
+        record(&control, "xplane_location_web::gpio::linux", "g1");
+        record(&control, "xplane_location_web::xplane_beacon", "b1");
+        record(&control, "xplane_location_web::gpio::event_detect", "g2");
+        record(&control, "xplane_location_web::xplane_comms", "c1");
+
+        let messages = |module, count| {
+            control
+                .history(Some(module), count)
+                .into_iter()
+                .map(|l| l.message)
+                .collect::<Vec<String>>()
+        };
+
+        assert_eq!(messages("gpio", 10), ["g1", "g2"]);
+        assert_eq!(messages("gpio", 1), ["g2"]);
+        assert_eq!(messages("beacon", 10), ["b1"]);
+        assert_eq!(messages("xplane_comms", 10), ["c1"]);
+    }
+
+    #[test]
+    fn change_level() {
+        let control = control(10);
+        assert_eq!(control.level(), "info");
+        assert!(control.set_level("xplane_location_web::gpio=debug").is_ok());
+        assert_eq!(control.level(), "xplane_location_web::gpio=debug");
+        assert!(control
+            .set_level("warn,xplane_location_web::gpio=TRACE")
+            .is_ok());
+        assert!(control.set_level("warn,xplane_location_web=off").is_ok());
+    }
+
+    #[test]
+    fn invalid_level() {
+        let control = control(10);
+
+        assert!(control.set_level("").is_err());
+        assert!(control.set_level("xplane_location_web::gpio=loud").is_err());
+        assert!(control.set_level("info,=debug").is_err());
+        assert!(control.set_level("info,,debug").is_err());
+        assert!(control.set_level("gpio=debug=trace").is_err());
+        assert_eq!(control.level(), "info");
+    }
+}
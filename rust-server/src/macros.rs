@@ -0,0 +1,406 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    io::BufReader,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use log::{error, info};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{mpsc, watch},
+    time::sleep,
+};
+
+use crate::{
+    conditions::Condition,
+    metrics::METRICS,
+    xpc_types::{ReceivedDatarefs, UICommand},
+};
+
+/// Commands starting with this run the macro named by the rest of the
+/// command instead of being sent to X-Plane.
+pub const MACRO_COMMAND_PREFIX: &str = "macro:";
+
+/// A named sequence of commands and dataref writes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacroConfig {
+    pub name: String,
+    pub steps: Vec<MacroStep>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct MacroStep {
+    #[serde(flatten)]
+    pub action: MacroAction,
+
+    /// The step is skipped if this doesn't hold when it is reached
+    #[serde(default)]
+    pub condition: Option<Condition>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, try_from = "serde_json::Map<String, serde_json::Value>")]
+pub enum MacroAction {
+    Delay { delay_ms: u64 },
+    Send(UICommand),
+}
+
+/// A delay step has no other fields, so that a mistyped step is not
+/// silently turned into a delay.
+impl TryFrom<serde_json::Map<String, serde_json::Value>> for MacroAction {
+    type Error = String;
+
+    fn try_from(step: serde_json::Map<String, serde_json::Value>) -> Result<Self, Self::Error> {
+        match step.get("delay_ms") {
+            Some(_) if step.len() > 1 => Err(String::from(
+                "A delay step cannot have other fields than delay_ms",
+            )),
+            Some(delay_ms) => serde_json::from_value(delay_ms.clone())
+                .map(|delay_ms| MacroAction::Delay { delay_ms })
+                .map_err(|e| e.to_string()),
+            None => serde_json::from_value(serde_json::Value::Object(step))
+                .map(MacroAction::Send)
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+/// The configured macros by name.
+#[derive(Debug, Clone, Default)]
+pub struct Macros {
+    macros: HashMap<String, Arc<MacroConfig>>,
+    /// The connections and names of the macros being run
+    running: Arc<Mutex<HashSet<(String, String)>>>,
+}
+
+impl Macros {
+    pub fn new(macros: Vec<MacroConfig>) -> Macros {
+        Macros {
+            macros: macros
+                .into_iter()
+                .map(|m| (m.name.clone(), Arc::new(m)))
+                .collect(),
+            running: Arc::default(),
+        }
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        let mut names = self.macros.keys().map(String::as_str).collect::<Vec<_>>();
+        names.sort();
+        names
+    }
+
+    pub fn contains(&self, name: &str) -> bool {
+        self.macros.contains_key(name)
+    }
+
+    /// Starts the macro named in the command, if it is a macro command.
+    /// The steps are sent to `cmds` of the connection. A macro that is
+    /// already running in the connection is not started again. Returns
+    /// false if the command is not a macro command.
+    pub fn start(
+        &self,
+        connection: &str,
+        command: &UICommand,
+        datarefs: watch::Receiver<ReceivedDatarefs>,
+        cmds: mpsc::Sender<UICommand>,
+    ) -> bool {
+        let Some(name) = macro_name(command) else {
+            return false;
+        };
+
+        let run = (connection.to_string(), name.to_string());
+        match self.macros.get(name) {
+            Some(_) if !self.running.lock().unwrap().insert(run.clone()) => {
+                info!("Macro {} is already running in {}", name, connection);
+            }
+            Some(macro_config) => {
+                info!("Running macro {} in {}", name, connection);
+                METRICS.commands_sent.inc(&["macro"]);
+                let macro_config = macro_config.clone();
+                let running = self.running.clone();
+                tokio::spawn(async move {
+                    if run_macro(&macro_config, &datarefs, &cmds).await.is_err() {
+                        error!(
+                            "Macro {} stopped, the connection is gone",
+                            macro_config.name
+                        );
+                    }
+                    running.lock().unwrap().remove(&run);
+                });
+            }
+            None => error!("Unknown macro {}", name),
+        }
+
+        true
+    }
+}
+
+fn macro_name(command: &UICommand) -> Option<&str> {
+    match command {
+        UICommand::Command { command } => command.strip_prefix(MACRO_COMMAND_PREFIX),
+        UICommand::SetDataref { .. } | UICommand::Radio(_) | UICommand::Autopilot(_) => None,
+    }
+}
+
+/// Sends the steps of the macro in order, checking the condition of each
+/// step against the received datarefs when the step is reached.
+pub async fn run_macro(
+    macro_config: &MacroConfig,
+    datarefs: &watch::Receiver<ReceivedDatarefs>,
+    cmds: &mpsc::Sender<UICommand>,
+) -> Result<(), mpsc::error::SendError<UICommand>> {
+    for step in &macro_config.steps {
+        let skip = step
+            .condition
+            .as_ref()
+            .is_some_and(|c| !c.evaluate(&datarefs.borrow().values()));
+        if skip {
+            continue;
+        }
+
+        match &step.action {
+            MacroAction::Delay { delay_ms } => sleep(Duration::from_millis(*delay_ms)).await,
+            MacroAction::Send(cmd) => cmds.send(cmd.clone()).await?,
+        }
+    }
+
+    Ok(())
+}
+
+pub fn read_macros_config(config_file: &str) -> Result<Macros, std::io::Error> {
+    let input_file = File::open(config_file).map_err(|e| {
+        error!("Reading macros file {} failed: {:?}", config_file, e);
+        e
+    })?;
+    let macros: Vec<MacroConfig> =
+        serde_json::from_reader(BufReader::new(input_file)).map_err(|e| {
+            let s = e.to_string();
+            error!("Reading macros file {} failed: {:?}", config_file, s);
+            std::io::Error::other(s)
+        })?;
+
+    validate_macros(&macros)?;
+
+    Ok(Macros::new(macros))
+}
+
+fn validate_macros(macros: &[MacroConfig]) -> Result<(), std::io::Error> {
+    let known_keys = ReceivedDatarefs::default();
+
+    for (i, m) in macros.iter().enumerate() {
+        if macros[..i].iter().any(|other| other.name == m.name) {
+            return Err(std::io::Error::other(format!(
+                "Duplicate macro name {}",
+                m.name
+            )));
+        }
+        for step in &m.steps {
+            if let Some(condition) = &step.condition {
+                if !known_keys.has_key(condition.key()) {
+                    return Err(std::io::Error::other(format!(
+                        "Unknown dataref key {} in macro {}",
+                        condition.key(),
+                        m.name
+                    )));
+                }
+            }
+            if let MacroAction::Send(cmd) = &step.action {
+                if macro_name(cmd).is_some() {
+                    return Err(std::io::Error::other(format!(
+                        "Macro {} runs another macro",
+                        m.name
+                    )));
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod macros_tests {
+    use std::time::Duration;
+
+    use tokio::{
+        sync::{mpsc, watch},
+        time::Instant,
+    };
+
+    use super::{run_macro, validate_macros, MacroAction, MacroConfig, MacroStep, Macros};
+    use crate::xpc_types::{ReceivedDatarefs, UICommand};
+
+    fn macros() -> Vec<MacroConfig> {
+        serde_json::from_str(
+            r#"[
+                {
+                    "name": "before-takeoff",
+                    "steps": [
+                        { "command": "sim/ice/pitot_heat0_on" },
+                        {
+                            "command": "sim/lights/strobe_lights_toggle",
+                            "condition": { "type": "bool", "key": "strobe-lights", "value": false }
+                        },
+                        { "delay_ms": 50 },
+                        { "dataref": "sim/cockpit/radios/transponder_mode", "value": 3 }
+                    ]
+                }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    async fn run(datarefs: ReceivedDatarefs) -> Vec<UICommand> {
+        let (_datarefs_tx, datarefs) = watch::channel(datarefs);
+        let (cmds, mut sent) = mpsc::channel(10);
+        run_macro(&macros()[0], &datarefs, &cmds).await.unwrap();
+        drop(cmds);
+
+        let mut commands = Vec::new();
+        while let Some(cmd) = sent.recv().await {
+            commands.push(cmd);
+        }
+        commands
+    }
+
+    #[tokio::test]
+    async fn steps_in_order() {
+        let started = Instant::now();
+        let commands = run(ReceivedDatarefs::default()).await;
+
+        assert!(started.elapsed() >= Duration::from_millis(50));
+        assert_eq!(
+            commands,
+            [
+                UICommand::command("sim/ice/pitot_heat0_on"),
+                UICommand::command("sim/lights/strobe_lights_toggle"),
+                UICommand::SetDataref {
+                    dataref: String::from("sim/cockpit/radios/transponder_mode"),
+                    value: 3.0
+                }
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn condition_skips_step() {
+        let commands = run(ReceivedDatarefs {
+            strobe_lights: true,
+            ..Default::default()
+        })
+        .await;
+
+        assert_eq!(commands.len(), 2);
+        assert!(!commands.contains(&UICommand::command("sim/lights/strobe_lights_toggle")));
+    }
+
+    #[tokio::test]
+    async fn start_by_command() {
+        let macros = Macros::new(macros());
+        let (_datarefs_tx, datarefs) = watch::channel(ReceivedDatarefs::default());
+        let (cmds, mut sent) = mpsc::channel(10);
+
+        assert!(!macros.start(
+            "xplane",
+            &UICommand::command("sim/lights/beacon_lights_on"),
+            datarefs.clone(),
+            cmds.clone()
+        ));
+        assert!(macros.start(
+            "xplane",
+            &UICommand::command("macro:before-takeoff"),
+            datarefs,
+            cmds
+        ));
+        assert_eq!(
+            sent.recv().await,
+            Some(UICommand::command("sim/ice/pitot_heat0_on"))
+        );
+    }
+
+    #[tokio::test]
+    async fn no_concurrent_runs() {
+        let macros = Macros::new(macros());
+        let (_datarefs_tx, datarefs) = watch::channel(ReceivedDatarefs::default());
+        let (cmds, mut sent) = mpsc::channel(10);
+        let (other_cmds, mut other_sent) = mpsc::channel(10);
+        let start = |connection, cmds: &mpsc::Sender<UICommand>| {
+            macros.start(
+                connection,
+                &UICommand::command("macro:before-takeoff"),
+                datarefs.clone(),
+                cmds.clone(),
+            )
+        };
+
+        assert!(start("xplane", &cmds));
+        assert!(start("xplane", &cmds));
+        // Runs in another connection at the same time
+        assert!(start("copilot", &other_cmds));
+        for _ in 0..3 {
+            sent.recv().await;
+            other_sent.recv().await;
+        }
+        // Waits for the end of the first run
+        tokio::time::sleep(Duration::from_millis(10)).await;
+        assert!(sent.try_recv().is_err());
+        assert!(other_sent.try_recv().is_err());
+
+        assert!(start("xplane", &cmds));
+        assert_eq!(
+            sent.recv().await,
+            Some(UICommand::command("sim/ice/pitot_heat0_on"))
+        );
+    }
+
+    #[test]
+    fn delay_steps() {
+        let step = |json: &str| serde_json::from_str::<MacroStep>(json);
+
+        assert_eq!(
+            step(r#"{ "delay_ms": 500 }"#).unwrap().action,
+            MacroAction::Delay { delay_ms: 500 }
+        );
+        assert_eq!(
+            step(r#"{ "delay_ms": 500, "condition": { "type": "bool", "key": "beacon" } }"#)
+                .unwrap()
+                .action,
+            MacroAction::Delay { delay_ms: 500 }
+        );
+        assert!(step(r#"{ "delay_ms": 500, "command": "sim/lights/beacon_lights_on" }"#).is_err());
+        assert!(step(r#"{ "delay": 500 }"#).is_err());
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate_macros(&macros()).is_ok());
+
+        let mut duplicate = macros();
+        duplicate.push(duplicate[0].clone());
+        assert!(validate_macros(&duplicate).is_err());
+
+        let unknown_key: Vec<MacroConfig> = serde_json::from_str(
+            r#"[{ "name": "m", "steps": [
+                { "command": "c", "condition": { "type": "bool", "key": "no-such-key" } }
+            ] }]"#,
+        )
+        .unwrap();
+        assert!(validate_macros(&unknown_key).is_err());
+
+        let null_value: Vec<MacroConfig> = serde_json::from_str(
+            r#"[{ "name": "m", "steps": [
+                { "command": "c", "condition": { "type": "below", "key": "lat", "value": 60 } }
+            ] }]"#,
+        )
+        .unwrap();
+        assert!(validate_macros(&null_value).is_ok());
+
+        let nested: Vec<MacroConfig> =
+            serde_json::from_str(r#"[{ "name": "m", "steps": [{ "command": "macro:other" }] }]"#)
+                .unwrap();
+        assert!(validate_macros(&nested).is_err());
+    }
+}
@@ -1,17 +1,39 @@
+mod analog;
+mod autopilot;
 mod channels;
+mod conditions;
+mod connections;
 mod control_msgs;
 mod gpio;
+mod logging;
+mod macros;
+mod metrics;
+mod panels;
+mod profiles;
+mod radios;
+mod rules;
+mod supervisor;
+mod systems;
 mod webserver;
 mod xpc_types;
 mod xplane_beacon;
 mod xplane_comms;
 
+use analog::run_analog;
 use channels::create_channels;
-use env_logger::{self, Env};
-use gpio::run_gpio;
+use connections::{read_connections_config, ConnectionConfig};
+use gpio::{run_gpio, InputBackend, Layers};
 use log::{self, error, info};
+use logging::{init_logging, LogFormat};
+use macros::{read_macros_config, Macros};
+use panels::{read_panels_config, PanelsConfig};
+use profiles::{read_profiles_config, AircraftProfiles};
+use rules::{read_rules_config, run_rules, RuleEvents};
+use std::sync::Arc;
+use supervisor::Supervisor;
+use tokio::sync::{broadcast, Mutex};
 
-use webserver::run_webserver;
+use webserver::{run_webserver, GpioControl, WebServices};
 use xplane_beacon::receive_xplane_beacon;
 use xplane_comms::run_xplane_udp;
 
@@ -24,10 +46,46 @@ struct CommandArgs {
     #[arg(short, long)]
     gpio_conf: Option<String>,
 
+    /// Read the GPIO input edges from this script file instead of the
+    /// GPIO chips
+    #[arg(long)]
+    gpio_script: Option<String>,
+
+    /// Read the GPIO input edges from the web server (POST /gpio/inject)
+    /// instead of the GPIO chips
+    #[arg(long, conflicts_with = "gpio_script")]
+    gpio_inject: bool,
+
+    /// Aircraft profiles file, for switching the GPIO configuration and
+    /// the datarefs with the loaded aircraft
+    #[arg(long)]
+    profiles: Option<String>,
+
+    /// Macros file, with named sequences of commands
+    #[arg(long)]
+    macros: Option<String>,
+
+    /// Panels file, with the layouts of the controls in the web UI
+    #[arg(long)]
+    panels: Option<String>,
+
+    /// Rules file, with actions run when dataref conditions occur
+    #[arg(long)]
+    rules: Option<String>,
+
+    /// Name of the analog input configuration file
+    #[arg(long)]
+    analog_conf: Option<String>,
+
     /// UDP port number for communicating with X-Plane
     #[arg(short, long, default_value_t = 49007)]
     udp_port: u16,
 
+    /// Configuration file for connecting to several X-Plane instances.
+    /// Overrides --udp-port.
+    #[arg(long)]
+    connections: Option<String>,
+
     /// Port number for the web UI
     #[arg(short = 'p', long, default_value_t = 3000)]
     web_port: u16,
@@ -39,47 +97,165 @@ struct CommandArgs {
     /// Log level
     #[arg(short, long, default_value_t = String::from("error"))]
     log_level: String,
+
+    /// Log output format
+    #[arg(long, value_enum, default_value_t = LogFormat::Text)]
+    log_format: LogFormat,
+
+    /// Number of log lines kept in memory for the log viewer
+    #[arg(long, default_value_t = 500)]
+    log_history: usize,
 }
 
 #[tokio::main]
 async fn main() {
     let args = CommandArgs::parse();
 
-    env_logger::Builder::from_env(Env::default().default_filter_or(&args.log_level)).init();
+    let log_control = init_logging(&args.log_level, args.log_format, args.log_history);
 
     info!("Command line args: {:#?}", args);
 
-    let (controller_endpoint, xplane_comm_endpoint, ui_endpoint) = create_channels();
+    let connections = match &args.connections {
+        Some(file) => match read_connections_config(file) {
+            Ok(connections) => connections,
+            Err(e) => {
+                error!("Cannot start without the connections configuration: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => vec![ConnectionConfig::default_connection(args.udp_port)],
+    };
+
+    let profiles = match &args.profiles {
+        Some(file) => match read_profiles_config(file) {
+            Ok(profiles) => profiles,
+            Err(e) => {
+                error!("Cannot start without the aircraft profiles: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => AircraftProfiles::default(),
+    };
+    let profiles = Arc::new(profiles);
+
+    let macros = match &args.macros {
+        Some(file) => match read_macros_config(file) {
+            Ok(macros) => macros,
+            Err(e) => {
+                error!("Cannot start without the macros: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Macros::default(),
+    };
+    let macros = Arc::new(macros);
+
+    let panels = match &args.panels {
+        Some(file) => match read_panels_config(file) {
+            Ok(panels) => panels,
+            Err(e) => {
+                error!("Cannot start without the panels: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => PanelsConfig::default(),
+    };
+
+    let (controller_endpoint, xplane_comm_endpoints, ui_endpoints) = create_channels(&connections);
 
     tokio::spawn(async move {
         run_signal_handler().await;
     });
 
-    if let Some(gpio_conf) = args.gpio_conf {
-        run_gpio(&ui_endpoint, &gpio_conf);
+    let supervisor = Supervisor::default();
+
+    let gpio_inject = args.gpio_inject.then(|| broadcast::channel(64).0);
+    let gpio_layers = Layers::default();
+    let rules = match &args.rules {
+        Some(file) => match read_rules_config(file) {
+            Ok(rules) => rules,
+            Err(e) => {
+                error!("Cannot start without the rules: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Vec::new(),
+    };
+    let rule_events = RuleEvents::new(&rules);
+
+    if args.gpio_conf.is_some() || profiles.has_gpio_conf() {
+        let backend = match (&args.gpio_script, &gpio_inject) {
+            (Some(script), _) => InputBackend::Script(script.clone()),
+            (None, Some(edges)) => InputBackend::Injected(edges.clone()),
+            (None, None) => InputBackend::Gpiod,
+        };
+        if let Err(e) = run_gpio(
+            &supervisor,
+            &ui_endpoints,
+            args.gpio_conf.as_deref(),
+            profiles.clone(),
+            backend,
+            gpio_layers.clone(),
+            rule_events.clone(),
+        ) {
+            error!("Cannot start without the GPIO configuration: {}", e);
+            std::process::exit(1);
+        }
     } else {
         info!("GPIO configuration file not defined, not starting GPIO");
     }
 
-    let ws_future = run_webserver(ui_endpoint, args.web_port, &args.web_directory);
+    if let Err(e) = run_rules(&supervisor, &ui_endpoints, rules, rule_events.clone()) {
+        error!("Cannot start without the rules: {}", e);
+        std::process::exit(1);
+    }
 
-    tokio::spawn(async move {
-        if let Err(err) = receive_xplane_beacon(controller_endpoint).await {
-            error!(
-                "Running the XPlane multicast beacon receiver failed: {:?}",
-                err
-            );
+    if let Some(analog_conf) = &args.analog_conf {
+        if let Err(e) = run_analog(&supervisor, &ui_endpoints, analog_conf) {
+            error!("Cannot start without the analog configuration: {}", e);
             std::process::exit(1);
         }
-    });
+    }
 
-    tokio::spawn(async move {
-        if let Err(err) = run_xplane_udp(args.udp_port, xplane_comm_endpoint).await {
-            error!("Running the UDP communication failed: {:?}", err);
-            std::process::exit(1);
-        }
+    let ws_future = run_webserver(
+        ui_endpoints,
+        args.web_port,
+        &args.web_directory,
+        WebServices {
+            supervisor: supervisor.clone(),
+            log_control,
+            gpio: GpioControl {
+                inject: gpio_inject,
+                layers: gpio_layers,
+            },
+            macros: macros.clone(),
+            rules: rule_events,
+            panels: Arc::new(panels),
+        },
+    );
+
+    supervisor.spawn("xplane-beacon", move || {
+        receive_xplane_beacon(controller_endpoint.clone())
     });
 
+    for (connection, endpoint) in connections.into_iter().zip(xplane_comm_endpoints) {
+        let name = format!("xplane-udp/{}", connection.name);
+        let connection = Arc::new(connection);
+        let endpoint = Arc::new(Mutex::new(endpoint));
+        let profiles = profiles.clone();
+        let macros = macros.clone();
+        supervisor.spawn(&name, move || {
+            let connection = connection.clone();
+            let endpoint = endpoint.clone();
+            let profiles = profiles.clone();
+            let macros = macros.clone();
+            async move {
+                let endpoint = &mut *endpoint.lock().await;
+                run_xplane_udp(&connection, endpoint, &profiles, &macros).await
+            }
+        });
+    }
+
     ws_future.await
 }
 
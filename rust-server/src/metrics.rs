@@ -0,0 +1,245 @@
+use std::{
+    collections::BTreeMap,
+    fmt::Write,
+    sync::{
+        atomic::{AtomicI64, AtomicU64, Ordering},
+        Mutex,
+    },
+    time::Instant,
+};
+
+/// Counters and gauges exported at the /metrics endpoint in the
+/// Prometheus text format.
+pub static METRICS: Metrics = Metrics::new();
+
+pub struct Metrics {
+    pub udp_packets: CounterVec,
+    pub udp_bytes: CounterVec,
+    pub parse_failures: Counter,
+    pub unknown_dataref_ids: Counter,
+    pub commands_sent: CounterVec,
+    pub websocket_clients: Gauge,
+    pub gpio_events: CounterVec,
+    pub debounce_rejections: CounterVec,
+    last_xplane_packet: Mutex<BTreeMap<String, Instant>>,
+}
+
+impl Metrics {
+    const fn new() -> Metrics {
+        Metrics {
+            udp_packets: CounterVec::new(&["connection", "type"]),
+            udp_bytes: CounterVec::new(&["connection", "type"]),
+            parse_failures: Counter::new(),
+            unknown_dataref_ids: Counter::new(),
+            commands_sent: CounterVec::new(&["source"]),
+            websocket_clients: Gauge::new(),
+            gpio_events: CounterVec::new(&["input"]),
+            debounce_rejections: CounterVec::new(&["input"]),
+            last_xplane_packet: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn xplane_packet_received(&self, connection: &str, msg_type: &str, bytes: usize) {
+        self.udp_packets.inc(&[connection, msg_type]);
+        self.udp_bytes.add(&[connection, msg_type], bytes as u64);
+        self.last_xplane_packet
+            .lock()
+            .unwrap()
+            .insert(connection.to_string(), Instant::now());
+    }
+
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+
+        self.udp_packets.render(
+            &mut out,
+            "xplane_udp_packets_received_total",
+            "UDP packets received from X-Plane",
+        );
+        self.udp_bytes.render(
+            &mut out,
+            "xplane_udp_bytes_received_total",
+            "Bytes received from X-Plane",
+        );
+        self.parse_failures.render(
+            &mut out,
+            "xplane_parse_failures_total",
+            "Messages from X-Plane that could not be parsed",
+        );
+        self.unknown_dataref_ids.render(
+            &mut out,
+            "xplane_unknown_dataref_ids_total",
+            "Dataref values received with an unknown id",
+        );
+        self.commands_sent
+            .render(&mut out, "commands_sent_total", "Commands sent to X-Plane");
+        self.websocket_clients
+            .render(&mut out, "websocket_clients", "Connected websocket clients");
+        self.gpio_events.render(
+            &mut out,
+            "gpio_events_total",
+            "Edge events received from GPIO inputs",
+        );
+        self.debounce_rejections.render(
+            &mut out,
+            "gpio_debounce_rejections_total",
+            "GPIO edges ignored by debouncing",
+        );
+
+        let last_packets = self.last_xplane_packet.lock().unwrap();
+        if !last_packets.is_empty() {
+            write_header(
+                &mut out,
+                "xplane_seconds_since_last_packet",
+                "Time since the latest packet from X-Plane",
+                "gauge",
+            );
+        }
+        for (connection, last) in last_packets.iter() {
+            writeln!(
+                out,
+                "xplane_seconds_since_last_packet{{connection=\"{}\"}} {:.3}",
+                escape_label_value(connection),
+                last.elapsed().as_secs_f64()
+            )
+            .unwrap();
+        }
+
+        out
+    }
+}
+
+pub struct Counter(AtomicU64);
+
+impl Counter {
+    const fn new() -> Counter {
+        Counter(AtomicU64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        write_header(out, name, help, "counter");
+        writeln!(out, "{} {}", name, self.0.load(Ordering::Relaxed)).unwrap();
+    }
+}
+
+pub struct Gauge(AtomicI64);
+
+impl Gauge {
+    const fn new() -> Gauge {
+        Gauge(AtomicI64::new(0))
+    }
+
+    pub fn inc(&self) {
+        self.0.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn dec(&self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        write_header(out, name, help, "gauge");
+        writeln!(out, "{} {}", name, self.0.load(Ordering::Relaxed)).unwrap();
+    }
+}
+
+pub struct CounterVec {
+    labels: &'static [&'static str],
+    values: Mutex<BTreeMap<Vec<String>, u64>>,
+}
+
+impl CounterVec {
+    const fn new(labels: &'static [&'static str]) -> CounterVec {
+        CounterVec {
+            labels,
+            values: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    pub fn inc(&self, label_values: &[&str]) {
+        self.add(label_values, 1);
+    }
+
+    pub fn add(&self, label_values: &[&str], n: u64) {
+        let key = label_values.iter().map(|v| v.to_string()).collect();
+        *self.values.lock().unwrap().entry(key).or_insert(0) += n;
+    }
+
+    fn render(&self, out: &mut String, name: &str, help: &str) {
+        write_header(out, name, help, "counter");
+        for (label_values, value) in self.values.lock().unwrap().iter() {
+            let labels = self
+                .labels
+                .iter()
+                .zip(label_values)
+                .map(|(l, v)| format!("{}=\"{}\"", l, escape_label_value(v)))
+                .collect::<Vec<String>>()
+                .join(",");
+            writeln!(out, "{}{{{}}} {}", name, labels, value).unwrap();
+        }
+    }
+}
+
+fn write_header(out: &mut String, name: &str, help: &str, metric_type: &str) {
+    writeln!(out, "# HELP {} {}", name, help).unwrap();
+    writeln!(out, "# TYPE {} {}", name, metric_type).unwrap();
+}
+
+fn escape_label_value(v: &str) -> String {
+    v.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod metrics_tests {
+    use super::Metrics;
+
+    #[test]
+    fn render_counters_and_gauges() {
+        let metrics = Metrics::new();
+
+        metrics.xplane_packet_received("captain", "RREF", 13);
+        metrics.xplane_packet_received("captain", "RREF", 21);
+        metrics.xplane_packet_received("captain", "DATA", 40);
+        metrics.xplane_packet_received("fo", "RREF", 13);
+        metrics.commands_sent.inc(&["gpio"]);
+        metrics.websocket_clients.inc();
+        metrics.websocket_clients.inc();
+        metrics.websocket_clients.dec();
+        metrics.gpio_events.inc(&["Autopilot \"heading\""]);
+
+        let out = metrics.render();
+
+        assert!(out.contains("# TYPE xplane_udp_packets_received_total counter\n"));
+        assert!(out.contains(
+            "xplane_udp_packets_received_total{connection=\"captain\",type=\"RREF\"} 2\n"
+        ));
+        assert!(out.contains(
+            "xplane_udp_packets_received_total{connection=\"captain\",type=\"DATA\"} 1\n"
+        ));
+        assert!(
+            out.contains("xplane_udp_packets_received_total{connection=\"fo\",type=\"RREF\"} 1\n")
+        );
+        assert!(out.contains(
+            "xplane_udp_bytes_received_total{connection=\"captain\",type=\"RREF\"} 34\n"
+        ));
+        assert!(out.contains("xplane_parse_failures_total 0\n"));
+        assert!(out.contains("commands_sent_total{source=\"gpio\"} 1\n"));
+        assert!(out.contains("websocket_clients 1\n"));
+        assert!(out.contains("gpio_events_total{input=\"Autopilot \\\"heading\\\"\"} 1\n"));
+        assert!(out.contains("xplane_seconds_since_last_packet{connection=\"fo\"} "));
+    }
+
+    #[test]
+    fn no_packet_age_before_first_packet() {
+        let metrics = Metrics::new();
+        assert!(!metrics
+            .render()
+            .contains("xplane_seconds_since_last_packet"));
+    }
+}
@@ -0,0 +1,253 @@
+use std::{fs::File, io::BufReader};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::xpc_types::ReceivedDatarefs;
+
+/// The panels of the web UI, and the commands they may send.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct PanelsConfig {
+    /// Commands the controls may send. A pattern ending with `*` allows
+    /// all commands starting with the rest of it. Any command is allowed
+    /// if this is not given.
+    #[serde(default)]
+    pub allowed_commands: Option<Vec<String>>,
+
+    pub panels: Vec<Panel>,
+}
+
+/// A layout of controls, e.g., for one tablet.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Panel {
+    pub name: String,
+    pub rows: Vec<PanelRow>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PanelRow {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub row_id: Option<String>,
+    pub controls: Vec<PanelControl>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type")]
+pub enum PanelControl {
+    /// A button that sends `command`, lit when `data_key` is true
+    #[serde(rename = "button")]
+    Button {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data_key: Option<String>,
+        command: String,
+    },
+    #[serde(rename = "gear")]
+    Gear,
+    #[serde(rename = "flaps")]
+    Flaps,
+    /// A knob that sends `cmd_right` and `cmd_left`, showing the value
+    /// of `data_key`
+    #[serde(rename = "encoder")]
+    Encoder {
+        text: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        data_key: Option<String>,
+        cmd_right: String,
+        cmd_left: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        cmd_push: Option<String>,
+    },
+}
+
+impl PanelControl {
+    fn data_key(&self) -> Option<&str> {
+        match self {
+            PanelControl::Button { data_key, .. } | PanelControl::Encoder { data_key, .. } => {
+                data_key.as_deref()
+            }
+            PanelControl::Gear | PanelControl::Flaps => None,
+        }
+    }
+
+    fn commands(&self) -> Vec<&str> {
+        match self {
+            PanelControl::Button { command, .. } => vec![command],
+            PanelControl::Encoder {
+                cmd_right,
+                cmd_left,
+                cmd_push,
+                ..
+            } => [Some(cmd_right), Some(cmd_left), cmd_push.as_ref()]
+                .into_iter()
+                .flatten()
+                .map(String::as_str)
+                .collect(),
+            PanelControl::Gear | PanelControl::Flaps => Vec::new(),
+        }
+    }
+}
+
+impl PanelsConfig {
+    pub fn names(&self) -> Vec<&str> {
+        self.panels.iter().map(|p| p.name.as_str()).collect()
+    }
+
+    pub fn panel(&self, name: &str) -> Option<&Panel> {
+        self.panels.iter().find(|p| p.name == name)
+    }
+
+    fn is_allowed(&self, command: &str) -> bool {
+        self.allowed_commands.as_ref().is_none_or(|allowed| {
+            allowed
+                .iter()
+                .any(|pattern| match pattern.strip_suffix('*') {
+                    Some(prefix) => command.starts_with(prefix),
+                    None => command == pattern,
+                })
+        })
+    }
+}
+
+pub fn read_panels_config(config_file: &str) -> Result<PanelsConfig, std::io::Error> {
+    let input_file = File::open(config_file).map_err(|e| {
+        error!("Reading panels file {} failed: {:?}", config_file, e);
+        e
+    })?;
+    let panels: PanelsConfig =
+        serde_json::from_reader(BufReader::new(input_file)).map_err(|e| {
+            let s = e.to_string();
+            error!("Reading panels file {} failed: {:?}", config_file, s);
+            std::io::Error::other(s)
+        })?;
+
+    validate_panels(&panels)?;
+
+    Ok(panels)
+}
+
+/// Checks that the panel names are unique, and that the controls use
+/// known dataref keys of single values and allowed commands.
+fn validate_panels(config: &PanelsConfig) -> Result<(), std::io::Error> {
+    let known_keys = ReceivedDatarefs::default();
+
+    for (i, panel) in config.panels.iter().enumerate() {
+        if config.panels[..i].iter().any(|p| p.name == panel.name) {
+            return Err(std::io::Error::other(format!(
+                "Duplicate panel name {}",
+                panel.name
+            )));
+        }
+
+        for control in panel.rows.iter().flat_map(|row| row.controls.iter()) {
+            if let Some(key) = control
+                .data_key()
+                .filter(|key| !known_keys.has_value_key(key))
+            {
+                return Err(std::io::Error::other(format!(
+                    "Unknown dataref key {} in panel {}",
+                    key, panel.name
+                )));
+            }
+            if let Some(command) = control
+                .commands()
+                .into_iter()
+                .find(|command| !config.is_allowed(command))
+            {
+                return Err(std::io::Error::other(format!(
+                    "Command {} is not allowed in panel {}",
+                    command, panel.name
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod panels_tests {
+    use super::{read_panels_config, validate_panels, PanelControl, PanelsConfig};
+
+    fn config() -> PanelsConfig {
+        serde_json::from_str(
+            r#"{
+                "allowed_commands": ["sim/lights/*", "sim/autopilot/heading_up", "sim/autopilot/heading_down"],
+                "panels": [
+                    { "name": "lights", "rows": [
+                        { "controls": [
+                            { "type": "button", "text": "Beacon", "data_key": "beacon", "command": "sim/lights/beacon_lights_toggle" },
+                            { "type": "gear" }
+                        ] }
+                    ] },
+                    { "name": "autopilot", "rows": [
+                        { "row_id": "ap", "controls": [
+                            { "type": "encoder", "text": "HDG", "data_key": "mag-heading",
+                              "cmd_right": "sim/autopilot/heading_up", "cmd_left": "sim/autopilot/heading_down" }
+                        ] }
+                    ] }
+                ]
+            }"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn find_panels() {
+        let config = config();
+        assert_eq!(config.names(), ["lights", "autopilot"]);
+        assert_eq!(
+            config.panel("autopilot").unwrap().rows[0].row_id.as_deref(),
+            Some("ap")
+        );
+        assert!(config.panel("radios").is_none());
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate_panels(&config()).is_ok());
+
+        let mut duplicate = config();
+        duplicate.panels[1].name = String::from("lights");
+        assert!(validate_panels(&duplicate).is_err());
+
+        let mut unknown_key = config();
+        unknown_key.panels[0].rows[0]
+            .controls
+            .push(PanelControl::Button {
+                text: String::from("?"),
+                data_key: Some(String::from("no-such-key")),
+                command: String::from("sim/lights/nav_lights_toggle"),
+            });
+        assert!(validate_panels(&unknown_key).is_err());
+
+        let mut object_key = config();
+        object_key.panels[0].rows[0]
+            .controls
+            .push(PanelControl::Button {
+                text: String::from("Radios"),
+                data_key: Some(String::from("radios")),
+                command: String::from("sim/lights/nav_lights_toggle"),
+            });
+        assert!(validate_panels(&object_key).is_err());
+
+        let mut not_allowed = config();
+        not_allowed.panels[1].rows[0]
+            .controls
+            .push(PanelControl::Button {
+                text: String::from("Sync"),
+                data_key: None,
+                command: String::from("sim/autopilot/heading_sync"),
+            });
+        assert!(validate_panels(&not_allowed).is_err());
+
+        not_allowed.allowed_commands = None;
+        assert!(validate_panels(&not_allowed).is_ok());
+    }
+
+    #[test]
+    fn sample_panels() {
+        let config = read_panels_config("panels.json").unwrap();
+        assert!(config.panel("default").is_some());
+    }
+}
@@ -0,0 +1,262 @@
+use std::{collections::HashMap, fs::File, io::BufReader};
+
+use log::error;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    systems::SystemUnits,
+    xplane_comms::{is_known_dataref, ReceivedDatarefs, MAX_RREF_NAME_LEN},
+};
+
+/// Settings for one aircraft, or a family of aircraft, that uses its
+/// own commands and datarefs.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct AircraftProfile {
+    pub name: String,
+
+    /// ICAO types of the aircraft, e.g., B738
+    #[serde(default)]
+    pub icao: Vec<String>,
+
+    /// Name of the `.acf` file of the aircraft
+    #[serde(default)]
+    pub acf_file: Option<String>,
+
+    /// GPIO input configuration file used with this aircraft
+    #[serde(default)]
+    pub gpio_conf: Option<String>,
+
+    /// Datarefs read instead of the default ones, keyed by the default
+    /// dataref
+    #[serde(default)]
+    pub datarefs: HashMap<String, String>,
+
+    /// Units of the fuel and temperatures in the `systems` data
+    #[serde(default)]
+    pub units: SystemUnits,
+}
+
+impl AircraftProfile {
+    fn matches(&self, icao: &str, acf_path: &str) -> bool {
+        let acf_file = acf_path.rsplit(['/', '\\']).next().unwrap_or_default();
+        (!icao.is_empty() && self.icao.iter().any(|i| i.eq_ignore_ascii_case(icao)))
+            || (!acf_file.is_empty()
+                && self
+                    .acf_file
+                    .as_ref()
+                    .is_some_and(|f| f.eq_ignore_ascii_case(acf_file)))
+    }
+
+    /// The dataref read for the default dataref `name`.
+    pub fn dataref<'a>(&'a self, name: &'a str) -> &'a str {
+        self.datarefs.get(name).map_or(name, String::as_str)
+    }
+}
+
+/// The aircraft profiles, in the order they are matched.
+#[derive(Debug, Clone, Default)]
+pub struct AircraftProfiles {
+    profiles: Vec<AircraftProfile>,
+}
+
+impl AircraftProfiles {
+    pub fn new(profiles: Vec<AircraftProfile>) -> AircraftProfiles {
+        AircraftProfiles { profiles }
+    }
+
+    /// The first profile matching the aircraft loaded in X-Plane.
+    pub fn find(&self, datarefs: &ReceivedDatarefs) -> Option<&AircraftProfile> {
+        self.profiles
+            .iter()
+            .find(|p| p.matches(&datarefs.aircraft_icao, &datarefs.acf_path))
+    }
+
+    /// The GPIO configuration file of the profile selected for the
+    /// loaded aircraft, or `default` when the profile doesn't have one.
+    pub fn gpio_conf(&self, datarefs: &ReceivedDatarefs, default: Option<&str>) -> Option<String> {
+        self.profiles
+            .iter()
+            .find(|p| datarefs.aircraft_profile.as_ref() == Some(&p.name))
+            .and_then(|p| p.gpio_conf.as_deref())
+            .or(default)
+            .map(String::from)
+    }
+
+    pub fn has_gpio_conf(&self) -> bool {
+        self.gpio_confs().next().is_some()
+    }
+
+    /// The GPIO configuration files of the profiles
+    pub fn gpio_confs(&self) -> impl Iterator<Item = &str> {
+        self.profiles.iter().filter_map(|p| p.gpio_conf.as_deref())
+    }
+
+    /// Returns true if a profile is matched by the `.acf` file.
+    pub fn use_acf_file(&self) -> bool {
+        self.profiles.iter().any(|p| p.acf_file.is_some())
+    }
+}
+
+pub fn read_profiles_config(config_file: &str) -> Result<AircraftProfiles, std::io::Error> {
+    let input_file = File::open(config_file).map_err(|e| {
+        error!(
+            "Reading aircraft profiles file {} failed: {:?}",
+            config_file, e
+        );
+        e
+    })?;
+    let profiles: Vec<AircraftProfile> = serde_json::from_reader(BufReader::new(input_file))
+        .map_err(|e| {
+            let s = e.to_string();
+            error!(
+                "Reading aircraft profiles file {} failed: {:?}",
+                config_file, s
+            );
+            std::io::Error::other(s)
+        })?;
+
+    validate_profiles(&profiles)?;
+
+    Ok(AircraftProfiles::new(profiles))
+}
+
+fn validate_profiles(profiles: &[AircraftProfile]) -> Result<(), std::io::Error> {
+    for (i, p) in profiles.iter().enumerate() {
+        if profiles[..i].iter().any(|other| other.name == p.name) {
+            return Err(std::io::Error::other(format!(
+                "Duplicate aircraft profile {}",
+                p.name
+            )));
+        }
+        if p.icao.is_empty() && p.acf_file.is_none() {
+            return Err(std::io::Error::other(format!(
+                "Aircraft profile {} has neither icao nor acf_file",
+                p.name
+            )));
+        }
+        if let Some(dataref) = p.datarefs.keys().find(|d| !is_known_dataref(d)) {
+            return Err(std::io::Error::other(format!(
+                "Unknown dataref {} in aircraft profile {}",
+                dataref, p.name
+            )));
+        }
+        if let Some(dataref) = p.datarefs.values().find(|d| d.len() > MAX_RREF_NAME_LEN) {
+            return Err(std::io::Error::other(format!(
+                "Dataref {} in aircraft profile {} is longer than {} bytes",
+                dataref, p.name, MAX_RREF_NAME_LEN
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod profiles_tests {
+    use super::{validate_profiles, AircraftProfile, AircraftProfiles};
+    use crate::xplane_comms::ReceivedDatarefs;
+
+    fn profiles() -> Vec<AircraftProfile> {
+        serde_json::from_str(
+            r#"[
+                {
+                    "name": "zibo", "icao": ["B738"], "gpio_conf": "hw-inputs-zibo.json",
+                    "datarefs": { "sim/cockpit2/switches/beacon_on": "laminar/B738/toggle_switch/beacon" }
+                },
+                { "name": "toliss", "acf_file": "a321.acf" }
+            ]"#,
+        )
+        .unwrap()
+    }
+
+    fn aircraft(icao: &str, acf_path: &str) -> ReceivedDatarefs {
+        ReceivedDatarefs {
+            aircraft_icao: String::from(icao),
+            acf_path: String::from(acf_path),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn find_profile() {
+        let profiles = AircraftProfiles::new(profiles());
+        let name = |datarefs| profiles.find(&datarefs).map(|p| p.name.clone());
+
+        assert_eq!(name(aircraft("b738", "")).as_deref(), Some("zibo"));
+        assert_eq!(
+            name(aircraft("A321", "Aircraft/ToLiss A321/A321.acf")).as_deref(),
+            Some("toliss")
+        );
+        assert_eq!(name(aircraft("C172", "Aircraft/Cessna/c172.acf")), None);
+        assert_eq!(name(aircraft("", "")), None);
+    }
+
+    #[test]
+    fn gpio_conf_of_selected_profile() {
+        let profiles = AircraftProfiles::new(profiles());
+        let selected = |profile: Option<&str>| ReceivedDatarefs {
+            aircraft_profile: profile.map(String::from),
+            ..aircraft("B738", "")
+        };
+
+        assert_eq!(
+            profiles
+                .gpio_conf(&selected(Some("zibo")), Some("hw-inputs.json"))
+                .as_deref(),
+            Some("hw-inputs-zibo.json")
+        );
+        assert_eq!(
+            profiles
+                .gpio_conf(&selected(Some("toliss")), Some("hw-inputs.json"))
+                .as_deref(),
+            Some("hw-inputs.json")
+        );
+        // The strings alone don't select the profile before they settle
+        assert_eq!(
+            profiles
+                .gpio_conf(&selected(None), Some("hw-inputs.json"))
+                .as_deref(),
+            Some("hw-inputs.json")
+        );
+        assert_eq!(profiles.gpio_conf(&selected(None), None), None);
+    }
+
+    #[test]
+    fn dataref_replacement() {
+        let zibo = &profiles()[0];
+        assert_eq!(
+            zibo.dataref("sim/cockpit2/switches/beacon_on"),
+            "laminar/B738/toggle_switch/beacon"
+        );
+        assert_eq!(
+            zibo.dataref("sim/cockpit2/switches/taxi_light_on"),
+            "sim/cockpit2/switches/taxi_light_on"
+        );
+    }
+
+    #[test]
+    fn validation() {
+        assert!(validate_profiles(&profiles()).is_ok());
+
+        let mut duplicate = profiles();
+        duplicate[1].name = String::from("zibo");
+        assert!(validate_profiles(&duplicate).is_err());
+
+        let mut unmatched = profiles();
+        unmatched[1].acf_file = None;
+        assert!(validate_profiles(&unmatched).is_err());
+
+        let mut unknown = profiles();
+        unknown[0]
+            .datarefs
+            .insert(String::from("sim/no/such/dataref"), String::from("x"));
+        assert!(validate_profiles(&unknown).is_err());
+
+        let mut too_long = profiles();
+        too_long[0].datarefs.insert(
+            String::from("sim/cockpit2/switches/beacon_on"),
+            "x".repeat(400),
+        );
+        assert!(validate_profiles(&too_long).is_err());
+    }
+}
@@ -0,0 +1,279 @@
+use serde::{Deserialize, Serialize};
+
+use crate::xpc_types::UICommand;
+
+/// The radio stack, sent to the web clients as the `radios` object.
+/// The frequencies are in kHz.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Radios {
+    pub com1: Radio,
+    pub com2: Radio,
+    pub nav1: NavRadio,
+    pub nav2: NavRadio,
+    pub adf1: Radio,
+    pub transponder: Transponder,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Radio {
+    #[serde(rename = "active-khz")]
+    pub active_khz: u32,
+
+    #[serde(rename = "standby-khz")]
+    pub standby_khz: u32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct NavRadio {
+    #[serde(flatten)]
+    pub radio: Radio,
+
+    /// The OBS course in degrees
+    pub obs: f32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Transponder {
+    pub code: u32,
+    pub mode: TransponderMode,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum TransponderMode {
+    #[default]
+    Off,
+    Standby,
+    On,
+    Alt,
+    Test,
+    Ground,
+}
+
+impl TransponderMode {
+    const MODES: [TransponderMode; 6] = [
+        TransponderMode::Off,
+        TransponderMode::Standby,
+        TransponderMode::On,
+        TransponderMode::Alt,
+        TransponderMode::Test,
+        TransponderMode::Ground,
+    ];
+
+    /// The mode of the value of `sim/cockpit2/radios/actuators/transponder_mode`
+    pub fn from_dataref(value: f32) -> TransponderMode {
+        let index = value.round().clamp(0.0, 5.0) as usize;
+        TransponderMode::MODES[index]
+    }
+
+    fn dataref_value(self) -> f32 {
+        TransponderMode::MODES
+            .iter()
+            .position(|mode| *mode == self)
+            .unwrap_or_default() as f32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "kebab-case")]
+pub enum RadioName {
+    Com1,
+    Com2,
+    Nav1,
+    Nav2,
+    Adf1,
+}
+
+impl RadioName {
+    fn name(self) -> &'static str {
+        match self {
+            RadioName::Com1 => "com1",
+            RadioName::Com2 => "com2",
+            RadioName::Nav1 => "nav1",
+            RadioName::Nav2 => "nav2",
+            RadioName::Adf1 => "adf1",
+        }
+    }
+
+    /// The tuning range in kHz
+    fn range_khz(self) -> (u32, u32) {
+        match self {
+            RadioName::Com1 | RadioName::Com2 => (118_000, 136_990),
+            RadioName::Nav1 | RadioName::Nav2 => (108_000, 117_950),
+            RadioName::Adf1 => (190, 1750),
+        }
+    }
+
+    /// The standby frequency dataref, and the kHz in one unit of it
+    fn standby_dataref(self) -> (String, u32) {
+        match self {
+            RadioName::Com1 | RadioName::Com2 => (
+                format!(
+                    "sim/cockpit2/radios/actuators/{}_standby_frequency_hz_833",
+                    self.name()
+                ),
+                1,
+            ),
+            RadioName::Nav1 | RadioName::Nav2 => (
+                format!(
+                    "sim/cockpit2/radios/actuators/{}_standby_frequency_hz",
+                    self.name()
+                ),
+                10,
+            ),
+            RadioName::Adf1 => (
+                String::from("sim/cockpit2/radios/actuators/adf1_standby_frequency_hz"),
+                1,
+            ),
+        }
+    }
+}
+
+/// A radio command from the web UI, translated to X-Plane commands and
+/// dataref writes.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "radio-command", rename_all = "kebab-case")]
+pub enum RadioCommand {
+    SetStandby { radio: RadioName, khz: u32 },
+    Swap { radio: RadioName },
+    Squawk { code: u32 },
+    TransponderMode { mode: TransponderMode },
+}
+
+impl RadioCommand {
+    /// The X-Plane commands and dataref writes for this command, or an
+    /// error if a frequency or squawk code is not valid.
+    pub fn xplane_commands(&self) -> Result<Vec<UICommand>, String> {
+        match self {
+            RadioCommand::SetStandby { radio, khz } => {
+                let (min, max) = radio.range_khz();
+                if !(min..=max).contains(khz) {
+                    return Err(format!(
+                        "Frequency {} kHz is outside the range of {}",
+                        khz,
+                        radio.name()
+                    ));
+                }
+                let (dataref, unit_khz) = radio.standby_dataref();
+                Ok(vec![UICommand::SetDataref {
+                    dataref,
+                    value: (khz / unit_khz) as f32,
+                }])
+            }
+            RadioCommand::Swap { radio } => Ok(vec![UICommand::command(&format!(
+                "sim/radios/{}_standy_flip",
+                radio.name()
+            ))]),
+            RadioCommand::Squawk { code } => {
+                if *code > 7777 || code.to_string().chars().any(|c| c > '7') {
+                    return Err(format!("Invalid squawk code {}", code));
+                }
+                Ok(vec![UICommand::SetDataref {
+                    dataref: String::from("sim/cockpit2/radios/actuators/transponder_code"),
+                    value: *code as f32,
+                }])
+            }
+            RadioCommand::TransponderMode { mode } => Ok(vec![UICommand::SetDataref {
+                dataref: String::from("sim/cockpit2/radios/actuators/transponder_mode"),
+                value: mode.dataref_value(),
+            }]),
+        }
+    }
+}
+
+#[cfg(test)]
+mod radios_tests {
+    use super::{NavRadio, Radio, RadioCommand, RadioName, Radios, TransponderMode};
+    use crate::xpc_types::UICommand;
+
+    #[test]
+    fn radios_json() {
+        let radios = Radios {
+            nav1: NavRadio {
+                radio: Radio {
+                    active_khz: 110_300,
+                    standby_khz: 113_900,
+                },
+                obs: 270.0,
+            },
+            ..Default::default()
+        };
+        let json = serde_json::to_value(&radios).unwrap();
+
+        assert_eq!(
+            json["nav1"],
+            serde_json::json!({ "active-khz": 110300, "standby-khz": 113900, "obs": 270.0 })
+        );
+        assert_eq!(
+            json["transponder"],
+            serde_json::json!({ "code": 0, "mode": "off" })
+        );
+    }
+
+    #[test]
+    fn typed_commands() {
+        let command = |json: &str| match serde_json::from_str::<UICommand>(json).unwrap() {
+            UICommand::Radio(radio) => radio,
+            other => panic!("Not a radio command: {:?}", other),
+        };
+
+        assert_eq!(
+            command(r#"{"radio-command": "set-standby", "radio": "nav1", "khz": 113900}"#)
+                .xplane_commands(),
+            Ok(vec![UICommand::SetDataref {
+                dataref: String::from("sim/cockpit2/radios/actuators/nav1_standby_frequency_hz"),
+                value: 11390.0
+            }])
+        );
+        assert_eq!(
+            command(r#"{"radio-command": "swap", "radio": "com2"}"#).xplane_commands(),
+            Ok(vec![UICommand::command("sim/radios/com2_standy_flip")])
+        );
+        assert_eq!(
+            command(r#"{"radio-command": "squawk", "code": 7000}"#).xplane_commands(),
+            Ok(vec![UICommand::SetDataref {
+                dataref: String::from("sim/cockpit2/radios/actuators/transponder_code"),
+                value: 7000.0
+            }])
+        );
+        assert_eq!(
+            command(r#"{"radio-command": "transponder-mode", "mode": "alt"}"#).xplane_commands(),
+            Ok(vec![UICommand::SetDataref {
+                dataref: String::from("sim/cockpit2/radios/actuators/transponder_mode"),
+                value: 3.0
+            }])
+        );
+    }
+
+    #[test]
+    fn invalid_values() {
+        let set_standby = |radio, khz| RadioCommand::SetStandby { radio, khz };
+        assert!(set_standby(RadioName::Com1, 121_500)
+            .xplane_commands()
+            .is_ok());
+        assert!(set_standby(RadioName::Com1, 113_900)
+            .xplane_commands()
+            .is_err());
+        assert!(set_standby(RadioName::Adf1, 362).xplane_commands().is_ok());
+        assert!(set_standby(RadioName::Adf1, 121_500)
+            .xplane_commands()
+            .is_err());
+
+        assert!(RadioCommand::Squawk { code: 7700 }
+            .xplane_commands()
+            .is_ok());
+        assert!(RadioCommand::Squawk { code: 7800 }
+            .xplane_commands()
+            .is_err());
+        assert!(RadioCommand::Squawk { code: 17000 }
+            .xplane_commands()
+            .is_err());
+    }
+
+    #[test]
+    fn transponder_mode_values() {
+        assert_eq!(TransponderMode::from_dataref(3.0), TransponderMode::Alt);
+        assert_eq!(TransponderMode::from_dataref(9.0), TransponderMode::Ground);
+        assert_eq!(TransponderMode::Standby.dataref_value(), 1.0);
+    }
+}
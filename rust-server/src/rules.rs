@@ -0,0 +1,700 @@
+use std::{
+    collections::BTreeSet, fs::File, future::pending, io, io::BufReader, sync::Arc, time::Duration,
+};
+
+use log::{error, info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::{
+    sync::{broadcast, watch},
+    time::{sleep_until, Instant},
+};
+
+use crate::{
+    channels::ChannelsUIEndpoint,
+    conditions::Condition,
+    gpio::STALE_DATA,
+    metrics::METRICS,
+    supervisor::Supervisor,
+    xpc_types::{DatarefValues, ReceivedDatarefs, UICommand},
+};
+
+/// A rule run by the server: when the condition holds, the actions are
+/// run.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleConfig {
+    pub name: String,
+
+    /// Connection whose datarefs are checked, the first one by default
+    #[serde(default)]
+    pub connection: Option<String>,
+
+    pub condition: Condition,
+
+    /// An `above` or `below` condition holds until the value is this
+    /// much past the limit in the other direction
+    #[serde(default)]
+    pub hysteresis: f64,
+
+    /// The condition has to hold this long before the actions are run
+    #[serde(default)]
+    pub for_ms: u64,
+
+    #[serde(default)]
+    pub trigger: Trigger,
+
+    /// With the level trigger, the actions are run again at this
+    /// interval while the condition holds
+    #[serde(default = "default_repeat_ms")]
+    pub repeat_ms: u64,
+
+    #[serde(default)]
+    pub actions: Vec<RuleAction>,
+}
+
+fn default_repeat_ms() -> u64 {
+    1000
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Trigger {
+    /// The actions are run once when the condition starts to hold
+    #[default]
+    Edge,
+    /// The actions are run repeatedly while the condition holds
+    Level,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum RuleAction {
+    /// Sends a notification to the web clients
+    Notify { notify: String },
+    /// Sends a command or a dataref value to X-Plane
+    Send(UICommand),
+}
+
+/// A notification sent to the web clients by a rule.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Notification {
+    pub rule: String,
+    pub connection: String,
+    pub message: String,
+}
+
+/// The active rules, shown by the GPIO outputs, and the notifications
+/// of the rules.
+#[derive(Debug, Clone)]
+pub struct RuleEvents {
+    /// The names of the configured rules
+    names: Arc<BTreeSet<String>>,
+    active: Arc<watch::Sender<BTreeSet<String>>>,
+    notifications: broadcast::Sender<Notification>,
+}
+
+impl Default for RuleEvents {
+    fn default() -> RuleEvents {
+        RuleEvents::new(&[])
+    }
+}
+
+impl RuleEvents {
+    pub fn new(rules: &[RuleConfig]) -> RuleEvents {
+        RuleEvents {
+            names: Arc::new(rules.iter().map(|rule| rule.name.clone()).collect()),
+            active: Arc::new(watch::channel(BTreeSet::new()).0),
+            notifications: broadcast::channel(64).0,
+        }
+    }
+
+    pub fn is_configured(&self, name: &str) -> bool {
+        self.names.contains(name)
+    }
+
+    /// The names of the active rules
+    pub fn active(&self) -> watch::Receiver<BTreeSet<String>> {
+        self.active.subscribe()
+    }
+
+    pub fn notifications(&self) -> broadcast::Receiver<Notification> {
+        self.notifications.subscribe()
+    }
+
+    fn set_active(&self, name: &str, active: bool) {
+        self.active.send_if_modified(|rules| {
+            if active {
+                rules.insert(name.to_string())
+            } else {
+                rules.remove(name)
+            }
+        });
+    }
+}
+
+/// Follows the condition of one rule over time and tells when the
+/// actions are run.
+pub struct RuleTracker {
+    rule: RuleConfig,
+    holding: bool,
+    /// The condition has been evaluated since the start
+    evaluated: bool,
+    /// An edge rule whose condition held at the first evaluation, and
+    /// has not stopped holding since. It does not fire.
+    held_at_start: bool,
+    since: Option<Duration>,
+    fired_at: Option<Duration>,
+}
+
+impl RuleTracker {
+    pub fn new(rule: RuleConfig) -> RuleTracker {
+        RuleTracker {
+            rule,
+            holding: false,
+            evaluated: false,
+            held_at_start: false,
+            since: None,
+            fired_at: None,
+        }
+    }
+
+    /// Forgets the condition, as if the rule had just been started.
+    pub fn reset(&mut self) {
+        self.holding = false;
+        self.evaluated = false;
+        self.held_at_start = false;
+        self.since = None;
+        self.fired_at = None;
+    }
+
+    pub fn rule(&self) -> &RuleConfig {
+        &self.rule
+    }
+
+    fn condition_holds(&mut self, datarefs: &DatarefValues) -> bool {
+        let hysteresis = self.rule.hysteresis;
+        self.holding = match (&self.rule.condition, self.holding) {
+            (Condition::Above { key, value }, true) => datarefs
+                .value_of(key)
+                .is_some_and(|v| v > value - hysteresis),
+            (Condition::Below { key, value }, true) => datarefs
+                .value_of(key)
+                .is_some_and(|v| v < value + hysteresis),
+            (condition, _) => condition.evaluate(datarefs),
+        };
+        self.holding
+    }
+
+    /// Checks the rule with the datarefs at `now`, and returns true if
+    /// the actions are to be run. The first check of an edge rule is the
+    /// baseline: if the condition already holds, the rule fires only
+    /// after it has stopped holding and holds again.
+    pub fn update(&mut self, datarefs: &DatarefValues, now: Duration) -> bool {
+        let first = !std::mem::replace(&mut self.evaluated, true);
+        if !self.condition_holds(datarefs) {
+            self.held_at_start = false;
+            self.since = None;
+            self.fired_at = None;
+            return false;
+        }
+
+        if first && self.rule.trigger == Trigger::Edge {
+            self.held_at_start = true;
+        }
+        if self.held_at_start {
+            return false;
+        }
+
+        let since = *self.since.get_or_insert(now);
+        if now - since < Duration::from_millis(self.rule.for_ms) {
+            return false;
+        }
+
+        let fire = match (self.rule.trigger, self.fired_at) {
+            (_, None) => true,
+            (Trigger::Edge, Some(_)) => false,
+            (Trigger::Level, Some(fired_at)) => {
+                now - fired_at >= Duration::from_millis(self.rule.repeat_ms)
+            }
+        };
+        if fire {
+            self.fired_at = Some(now);
+        }
+        fire
+    }
+
+    /// The rule is active from when its actions are first run until the
+    /// condition no longer holds.
+    pub fn is_active(&self) -> bool {
+        self.fired_at.is_some()
+    }
+
+    /// When the rule has to be checked again even if the datarefs don't
+    /// change.
+    pub fn next_check(&self) -> Option<Duration> {
+        match (self.since, self.fired_at, self.rule.trigger) {
+            (Some(since), None, _) => Some(since + Duration::from_millis(self.rule.for_ms)),
+            (Some(_), Some(fired_at), Trigger::Level) => {
+                Some(fired_at + Duration::from_millis(self.rule.repeat_ms))
+            }
+            _ => None,
+        }
+    }
+}
+
+pub fn read_rules_config(config_file: &str) -> Result<Vec<RuleConfig>, io::Error> {
+    let input_file = File::open(config_file).map_err(|e| {
+        error!("Reading rules file {} failed: {:?}", config_file, e);
+        e
+    })?;
+    let rules: Vec<RuleConfig> =
+        serde_json::from_reader(BufReader::new(input_file)).map_err(|e| {
+            let s = e.to_string();
+            error!("Reading rules file {} failed: {:?}", config_file, s);
+            io::Error::other(s)
+        })?;
+
+    validate_rules(&rules)?;
+
+    Ok(rules)
+}
+
+fn validate_rules(rules: &[RuleConfig]) -> Result<(), io::Error> {
+    let known_keys = ReceivedDatarefs::default();
+
+    for (i, rule) in rules.iter().enumerate() {
+        if rules[..i].iter().any(|other| other.name == rule.name) {
+            return Err(io::Error::other(format!(
+                "Duplicate rule name {}",
+                rule.name
+            )));
+        }
+        if !known_keys.has_key(rule.condition.key()) {
+            return Err(io::Error::other(format!(
+                "Unknown dataref key {} in rule {}",
+                rule.condition.key(),
+                rule.name
+            )));
+        }
+        if rule.hysteresis < 0.0 {
+            return Err(io::Error::other(format!(
+                "Negative hysteresis in rule {}",
+                rule.name
+            )));
+        }
+        if rule.trigger == Trigger::Level && rule.repeat_ms == 0 {
+            return Err(io::Error::other(format!(
+                "Zero repeat_ms in rule {}",
+                rule.name
+            )));
+        }
+    }
+
+    Ok(())
+}
+
+/// Starts the rules of each connection as a supervised subsystem.
+pub fn run_rules(
+    supervisor: &Supervisor,
+    endpoints: &[ChannelsUIEndpoint],
+    rules: Vec<RuleConfig>,
+    events: RuleEvents,
+) -> Result<(), io::Error> {
+    if let Some(rule) = rules.iter().find(|rule| {
+        rule.connection
+            .as_ref()
+            .is_some_and(|c| !endpoints.iter().any(|e| &e.connection == c))
+    }) {
+        return Err(io::Error::other(format!(
+            "Unknown connection in rule {}",
+            rule.name
+        )));
+    }
+
+    for (i, endpoint) in endpoints.iter().enumerate() {
+        let connection_rules = rules
+            .iter()
+            .filter(|rule| match &rule.connection {
+                Some(connection) => *connection == endpoint.connection,
+                None => i == 0,
+            })
+            .cloned()
+            .collect::<Vec<RuleConfig>>();
+        if connection_rules.is_empty() {
+            continue;
+        }
+
+        info!(
+            "Running {} rules in connection {}",
+            connection_rules.len(),
+            endpoint.connection
+        );
+        let endpoint = endpoint.clone();
+        let events = events.clone();
+        supervisor.spawn(&format!("rules/{}", endpoint.connection), move || {
+            run_connection_rules(endpoint.clone(), connection_rules.clone(), events.clone())
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks the rules whenever the datarefs change, and when a rule's
+/// time is up. The rules are not checked before data has been received.
+async fn run_connection_rules(
+    endpoint: ChannelsUIEndpoint,
+    rules: Vec<RuleConfig>,
+    events: RuleEvents,
+) -> Result<(), io::Error> {
+    let start = Instant::now();
+    let mut trackers = rules.into_iter().map(RuleTracker::new).collect::<Vec<_>>();
+    let mut datarefs = endpoint.data.clone();
+    let mut next_check = None;
+    // The datarefs at start are the baseline of the edge rules
+    datarefs.mark_changed();
+
+    loop {
+        let wait = async {
+            match next_check {
+                Some(at) => sleep_until(start + at).await,
+                None => pending().await,
+            }
+        };
+        tokio::select! {
+            changed = datarefs.changed() => {
+                if changed.is_err() {
+                    return Err(io::Error::other("The datarefs are no longer received"));
+                }
+            }
+            _ = wait => {}
+        }
+
+        let current = datarefs.borrow_and_update().values();
+        let now = start.elapsed();
+
+        // The rules are not repeated from stale data, and are checked
+        // again when new data arrives
+        let last_packet = *endpoint.last_packet.borrow();
+        let Some(fresh_for) = last_packet.and_then(|last| STALE_DATA.checked_sub(last.elapsed()))
+        else {
+            if trackers.iter().any(RuleTracker::is_active) {
+                warn!(
+                    "The data of connection {} is stale, clearing its rules",
+                    endpoint.connection
+                );
+            }
+            for tracker in &mut trackers {
+                tracker.reset();
+                events.set_active(&tracker.rule().name, false);
+            }
+            next_check = None;
+            continue;
+        };
+
+        for tracker in &mut trackers {
+            if tracker.update(&current, now) {
+                run_actions(tracker.rule(), &endpoint, &events).await;
+            }
+            events.set_active(&tracker.rule().name, tracker.is_active());
+        }
+        next_check = trackers
+            .iter()
+            .filter_map(RuleTracker::next_check)
+            .chain(std::iter::once(now + fresh_for))
+            .min();
+    }
+}
+
+async fn run_actions(rule: &RuleConfig, endpoint: &ChannelsUIEndpoint, events: &RuleEvents) {
+    info!(
+        "Rule {} fired in connection {}",
+        rule.name, endpoint.connection
+    );
+
+    for action in &rule.actions {
+        match action {
+            RuleAction::Notify { notify } => {
+                // Nobody listening is fine
+                let _ = events.notifications.send(Notification {
+                    rule: rule.name.clone(),
+                    connection: endpoint.connection.clone(),
+                    message: notify.clone(),
+                });
+            }
+            RuleAction::Send(cmd) => {
+                METRICS.commands_sent.inc(&["rule"]);
+                if let Err(e) = endpoint.ui_cmds.send(cmd.clone()).await {
+                    error!("Sending command of rule {} failed: {:?}", rule.name, e);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod rules_tests {
+    use std::time::Duration;
+
+    use tokio::{
+        sync::{mpsc, watch},
+        time::timeout,
+    };
+
+    use super::{run_connection_rules, validate_rules, RuleConfig, RuleEvents, RuleTracker};
+    use crate::{
+        channels::ChannelsUIEndpoint,
+        xpc_types::{ReceivedDatarefs, UICommand},
+    };
+
+    fn rule(json: &str) -> RuleConfig {
+        serde_json::from_str(json).unwrap()
+    }
+
+    /// Feeds the stream of (time in ms, datarefs) to the rule, and
+    /// returns the times when its actions were run.
+    fn fired_at(rule: RuleConfig, stream: &[(u64, ReceivedDatarefs)]) -> Vec<u64> {
+        let mut tracker = RuleTracker::new(rule);
+        stream
+            .iter()
+            .filter(|(ms, datarefs)| tracker.update(&datarefs.values(), Duration::from_millis(*ms)))
+            .map(|(ms, _)| *ms)
+            .collect()
+    }
+
+    fn altitudes(stream: &[(u64, f32)]) -> Vec<(u64, ReceivedDatarefs)> {
+        stream
+            .iter()
+            .map(|(ms, altitude)| {
+                let datarefs = ReceivedDatarefs {
+                    altitude: *altitude,
+                    ..Default::default()
+                };
+                (*ms, datarefs)
+            })
+            .collect()
+    }
+
+    fn gear_unsafe(stream: &[(u64, bool)]) -> Vec<(u64, ReceivedDatarefs)> {
+        stream
+            .iter()
+            .map(|(ms, unsafe_gear)| {
+                let datarefs = ReceivedDatarefs {
+                    is_gear_unsafe: *unsafe_gear,
+                    ..Default::default()
+                };
+                (*ms, datarefs)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn edge_with_hysteresis() {
+        let lights_off = rule(
+            r#"{
+                "name": "lights-off",
+                "condition": { "type": "above", "key": "altitude", "value": 10000 },
+                "hysteresis": 100,
+                "actions": [{ "command": "sim/lights/landing_lights_off" }]
+            }"#,
+        );
+        let stream = altitudes(&[
+            (0, 9000.0),
+            (1000, 10010.0),
+            (2000, 9950.0),
+            (3000, 10050.0),
+            (4000, 9800.0),
+            (5000, 10200.0),
+        ]);
+
+        assert_eq!(fired_at(lights_off, &stream), [1000, 5000]);
+    }
+
+    #[test]
+    fn edge_not_fired_at_start() {
+        let lights_off = rule(
+            r#"{
+                "name": "lights-off",
+                "condition": { "type": "above", "key": "altitude", "value": 10000 },
+                "actions": [{ "command": "sim/lights/landing_lights_off" }]
+            }"#,
+        );
+        let stream = altitudes(&[
+            (0, 12000.0),
+            (1000, 12500.0),
+            (2000, 9000.0),
+            (3000, 11000.0),
+        ]);
+        assert_eq!(fired_at(lights_off.clone(), &stream), [3000]);
+
+        // After a reset, the next check is the baseline again
+        let mut tracker = RuleTracker::new(lights_off);
+        let values = |i: usize| stream[i].1.values();
+        assert!(!tracker.update(&values(2), Duration::ZERO));
+        tracker.reset();
+        assert!(!tracker.update(&values(0), Duration::from_millis(1000)));
+        assert!(!tracker.update(&values(1), Duration::from_millis(2000)));
+        assert!(!tracker.is_active());
+    }
+
+    #[test]
+    fn level_held_for_a_time() {
+        let gear_alert = rule(
+            r#"{
+                "name": "gear-alert",
+                "condition": { "type": "bool", "key": "is-gear-unsafe" },
+                "for_ms": 30000, "trigger": "level", "repeat_ms": 10000,
+                "actions": [{ "notify": "Gear unsafe" }]
+            }"#,
+        );
+        let stream = gear_unsafe(&[
+            (0, true),
+            (20000, false),
+            (25000, true),
+            (50000, true),
+            (55000, true),
+            (60000, true),
+            (65000, true),
+            (70000, false),
+            (80000, true),
+        ]);
+
+        assert_eq!(fired_at(gear_alert, &stream), [55000, 65000]);
+    }
+
+    #[test]
+    fn active_and_next_check() {
+        let mut tracker = RuleTracker::new(rule(
+            r#"{
+                "name": "gear-alert",
+                "condition": { "type": "bool", "key": "is-gear-unsafe" },
+                "for_ms": 1000
+            }"#,
+        ));
+        let stream = gear_unsafe(&[(0, false), (0, true), (1000, false)]);
+
+        assert!(!tracker.update(&stream[0].1.values(), Duration::ZERO));
+        assert_eq!(tracker.next_check(), None);
+
+        assert!(!tracker.update(&stream[1].1.values(), Duration::ZERO));
+        assert!(!tracker.is_active());
+        assert_eq!(tracker.next_check(), Some(Duration::from_millis(1000)));
+
+        assert!(tracker.update(&stream[1].1.values(), Duration::from_millis(1000)));
+        assert!(tracker.is_active());
+        assert_eq!(tracker.next_check(), None);
+
+        assert!(!tracker.update(&stream[2].1.values(), Duration::from_millis(1500)));
+        assert!(!tracker.is_active());
+    }
+
+    #[test]
+    fn validation() {
+        let valid = rule(r#"{ "name": "r", "condition": { "type": "bool", "key": "beacon" } }"#);
+        assert!(validate_rules(std::slice::from_ref(&valid)).is_ok());
+        assert!(validate_rules(&[valid.clone(), valid.clone()]).is_err());
+
+        let unknown_key =
+            rule(r#"{ "name": "r", "condition": { "type": "bool", "key": "no-such-key" } }"#);
+        assert!(validate_rules(&[unknown_key]).is_err());
+
+        let mut negative = valid;
+        negative.hysteresis = -1.0;
+        assert!(validate_rules(&[negative]).is_err());
+    }
+
+    #[tokio::test]
+    async fn run_actions_on_updates() {
+        let (datarefs_tx, data) = watch::channel(ReceivedDatarefs::default());
+        let (ui_cmds, mut commands) = mpsc::channel(4);
+        let endpoint = ChannelsUIEndpoint {
+            connection: String::from("rules-test"),
+            data,
+            ui_cmds,
+            last_packet: watch::channel(Some(std::time::Instant::now())).1,
+        };
+        let events = RuleEvents::default();
+        let mut notifications = events.notifications();
+        let mut active = events.active();
+        let gear_alert = rule(
+            r#"{
+                "name": "gear-alert",
+                "condition": { "type": "bool", "key": "is-gear-unsafe" },
+                "for_ms": 50,
+                "actions": [
+                    { "notify": "Gear unsafe" },
+                    { "command": "sim/annunciator/clear_master_warning" }
+                ]
+            }"#,
+        );
+        let run = tokio::spawn(run_connection_rules(
+            endpoint,
+            vec![gear_alert],
+            events.clone(),
+        ));
+        // The rules take the safe gear as their baseline
+        tokio::task::yield_now().await;
+
+        datarefs_tx.send_modify(|d| d.is_gear_unsafe = true);
+        let wait = Duration::from_secs(1);
+        let notification = timeout(wait, notifications.recv()).await.unwrap().unwrap();
+        assert_eq!(notification.rule, "gear-alert");
+        assert_eq!(notification.message, "Gear unsafe");
+        assert_eq!(
+            timeout(wait, commands.recv()).await.unwrap(),
+            Some(UICommand::command("sim/annunciator/clear_master_warning"))
+        );
+        timeout(wait, active.wait_for(|rules| rules.contains("gear-alert")))
+            .await
+            .unwrap()
+            .unwrap();
+
+        datarefs_tx.send_modify(|d| d.is_gear_unsafe = false);
+        timeout(wait, active.wait_for(|rules| rules.is_empty()))
+            .await
+            .unwrap()
+            .unwrap();
+        run.abort();
+    }
+
+    #[tokio::test]
+    async fn nothing_from_stale_data() {
+        let (datarefs_tx, data) = watch::channel(ReceivedDatarefs::default());
+        let (ui_cmds, mut commands) = mpsc::channel(4);
+        let endpoint = ChannelsUIEndpoint {
+            connection: String::from("rules-stale-test"),
+            data,
+            ui_cmds,
+            last_packet: watch::channel(None).1,
+        };
+        let events = RuleEvents::default();
+        let active = events.active();
+        let gear_alert = rule(
+            r#"{
+                "name": "gear-alert",
+                "condition": { "type": "bool", "key": "is-gear-unsafe" },
+                "actions": [ { "command": "sim/annunciator/clear_master_warning" } ]
+            }"#,
+        );
+        let run = tokio::spawn(run_connection_rules(
+            endpoint,
+            vec![gear_alert],
+            events.clone(),
+        ));
+
+        datarefs_tx.send_modify(|d| d.is_gear_unsafe = true);
+        assert!(timeout(Duration::from_millis(100), commands.recv())
+            .await
+            .is_err());
+        assert!(active.borrow().is_empty());
+        run.abort();
+    }
+
+    #[test]
+    fn configured_names() {
+        let events = RuleEvents::new(&[rule(
+            r#"{ "name": "gear-alert", "condition": { "type": "bool", "key": "is-gear-unsafe" } }"#,
+        )]);
+
+        assert!(events.is_configured("gear-alert"));
+        assert!(!events.is_configured("stall-alert"));
+    }
+}
@@ -0,0 +1,213 @@
+use std::{
+    cmp::min,
+    collections::BTreeMap,
+    future::Future,
+    io,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime, UNIX_EPOCH},
+};
+
+use log::{error, info};
+use serde::Serialize;
+use tokio::time::sleep;
+
+const INITIAL_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+const STABLE_RUN_TIME: Duration = Duration::from_secs(120);
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+pub enum SubsystemState {
+    #[serde(rename = "running")]
+    Running,
+    #[serde(rename = "restarting")]
+    Restarting,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct SubsystemHealth {
+    pub state: SubsystemState,
+
+    pub restarts: u32,
+
+    #[serde(rename = "last-error")]
+    pub last_error: Option<String>,
+
+    #[serde(rename = "last-failure-time")]
+    pub last_failure_time: Option<u64>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct HealthReport {
+    pub healthy: bool,
+    pub subsystems: BTreeMap<String, SubsystemHealth>,
+}
+
+/// Runs the subsystems of the server and restarts them with an
+/// exponential backoff if they fail.
+#[derive(Debug, Clone, Default)]
+pub struct Supervisor {
+    health: Arc<Mutex<BTreeMap<String, SubsystemHealth>>>,
+}
+
+impl Supervisor {
+    /// Starts a supervised subsystem. `run` is called again to create a new
+    /// instance of the subsystem every time the previous one has failed.
+    pub fn spawn<F, Fut>(&self, name: &str, mut run: F)
+    where
+        F: FnMut() -> Fut + Send + 'static,
+        Fut: Future<Output = io::Result<()>> + Send + 'static,
+    {
+        let supervisor = self.clone();
+        let name = name.to_string();
+
+        tokio::spawn(async move {
+            let mut backoff = Backoff::default();
+
+            loop {
+                supervisor.set_running(&name);
+                let started = Instant::now();
+
+                let reason = match tokio::spawn(run()).await {
+                    Ok(Ok(())) => String::from("Exited unexpectedly"),
+                    Ok(Err(e)) => format!("{:?}", e),
+                    Err(e) => format!("Panicked: {}", e),
+                };
+
+                let delay = backoff.next_delay(started.elapsed());
+                error!(
+                    "Subsystem {} failed: {}. Restarting in {:?}",
+                    name, reason, delay
+                );
+                supervisor.set_failed(&name, reason);
+
+                sleep(delay).await;
+                info!("Restarting subsystem {}", name);
+            }
+        });
+    }
+
+    pub fn health_report(&self) -> HealthReport {
+        let subsystems = self.health.lock().unwrap().clone();
+        let healthy = subsystems
+            .values()
+            .all(|s| s.state == SubsystemState::Running);
+
+        HealthReport {
+            healthy,
+            subsystems,
+        }
+    }
+
+    fn set_running(&self, name: &str) {
+        let mut health = self.health.lock().unwrap();
+        let entry = health
+            .entry(name.to_string())
+            .or_insert_with(|| SubsystemHealth {
+                state: SubsystemState::Running,
+                restarts: 0,
+                last_error: None,
+                last_failure_time: None,
+            });
+        if entry.state == SubsystemState::Restarting {
+            entry.restarts += 1;
+        }
+        entry.state = SubsystemState::Running;
+    }
+
+    fn set_failed(&self, name: &str, reason: String) {
+        let mut health = self.health.lock().unwrap();
+        if let Some(entry) = health.get_mut(name) {
+            entry.state = SubsystemState::Restarting;
+            entry.last_error = Some(reason);
+            entry.last_failure_time = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .ok()
+                .map(|d| d.as_secs());
+        }
+    }
+}
+
+/// Exponential backoff that starts over if the subsystem managed to run
+/// for a while before failing again.
+#[derive(Debug)]
+struct Backoff {
+    current: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            current: INITIAL_BACKOFF,
+        }
+    }
+}
+
+impl Backoff {
+    fn next_delay(&mut self, ran_for: Duration) -> Duration {
+        if ran_for >= STABLE_RUN_TIME {
+            self.current = INITIAL_BACKOFF;
+        }
+
+        let delay = self.current;
+        self.current = min(self.current * 2, MAX_BACKOFF);
+        delay
+    }
+}
+
+#[cfg(test)]
+mod supervisor_tests {
+    use std::time::Duration;
+
+    use super::{Backoff, SubsystemState, Supervisor, INITIAL_BACKOFF, MAX_BACKOFF};
+
+    #[test]
+    fn backoff_grows_up_to_max() {
+        let mut backoff = Backoff::default();
+
+        assert_eq!(backoff.next_delay(Duration::ZERO), INITIAL_BACKOFF);
+        assert_eq!(backoff.next_delay(Duration::ZERO), INITIAL_BACKOFF * 2);
+        assert_eq!(backoff.next_delay(Duration::ZERO), INITIAL_BACKOFF * 4);
+
+        for _ in 0..10 {
+            backoff.next_delay(Duration::ZERO);
+        }
+        assert_eq!(backoff.next_delay(Duration::ZERO), MAX_BACKOFF);
+    }
+
+    #[test]
+    fn backoff_resets_after_stable_run() {
+        let mut backoff = Backoff::default();
+
+        backoff.next_delay(Duration::ZERO);
+        backoff.next_delay(Duration::ZERO);
+        assert_eq!(
+            backoff.next_delay(Duration::from_secs(600)),
+            INITIAL_BACKOFF
+        );
+    }
+
+    #[test]
+    fn health_records_failures_and_restarts() {
+        let supervisor = Supervisor::default();
+
+        supervisor.set_running("beacon");
+        assert!(supervisor.health_report().healthy);
+
+        supervisor.set_failed("beacon", String::from("bind failed"));
+        let report = supervisor.health_report();
+        assert!(!report.healthy);
+        let beacon = &report.subsystems["beacon"];
+        assert_eq!(beacon.state, SubsystemState::Restarting);
+        assert_eq!(beacon.last_error.as_deref(), Some("bind failed"));
+        assert!(beacon.last_failure_time.is_some());
+
+        supervisor.set_running("beacon");
+        let report = supervisor.health_report();
+        assert!(report.healthy);
+        assert_eq!(report.subsystems["beacon"].restarts, 1);
+        assert_eq!(
+            report.subsystems["beacon"].last_error.as_deref(),
+            Some("bind failed")
+        );
+    }
+}
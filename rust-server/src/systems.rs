@@ -0,0 +1,535 @@
+use serde::{Deserialize, Serialize};
+
+/// Engine, fuel and electrical data, sent to the web clients as the
+/// `systems` object. The arrays have one entry per engine, tank, bus,
+/// battery and generator of the loaded aircraft.
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Systems {
+    pub units: SystemUnits,
+    pub engines: Vec<Engine>,
+    pub fuel: Fuel,
+    pub electrical: Electrical,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Engine {
+    pub rpm: f32,
+
+    /// N1 in percent
+    pub n1: f32,
+
+    /// N2 in percent
+    pub n2: f32,
+
+    /// Manifold pressure in inHg
+    #[serde(rename = "manifold-pressure")]
+    pub manifold_pressure: f32,
+
+    pub egt: f32,
+
+    pub itt: f32,
+
+    /// Oil pressure in psi
+    #[serde(rename = "oil-pressure")]
+    pub oil_pressure: f32,
+
+    #[serde(rename = "oil-temperature")]
+    pub oil_temperature: f32,
+
+    /// Fuel flow per hour
+    #[serde(rename = "fuel-flow")]
+    pub fuel_flow: f32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Fuel {
+    pub tanks: Vec<f32>,
+    pub total: f32,
+}
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
+pub struct Electrical {
+    #[serde(rename = "bus-volts")]
+    pub bus_volts: Vec<f32>,
+
+    #[serde(rename = "battery-amps")]
+    pub battery_amps: Vec<f32>,
+
+    #[serde(rename = "generator-amps")]
+    pub generator_amps: Vec<f32>,
+}
+
+/// The units of the fuel and temperature values, set in the aircraft
+/// profile.
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct SystemUnits {
+    #[serde(default)]
+    pub fuel: FuelUnit,
+
+    #[serde(default)]
+    pub temperature: TemperatureUnit,
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FuelUnit {
+    #[default]
+    Kg,
+    Lb,
+}
+
+impl FuelUnit {
+    fn convert_kg(self, kg: f32) -> f32 {
+        match self {
+            FuelUnit::Kg => kg,
+            FuelUnit::Lb => kg * 2.204_623,
+        }
+    }
+}
+
+#[derive(Default, Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum TemperatureUnit {
+    #[default]
+    #[serde(rename = "C")]
+    Celsius,
+    #[serde(rename = "F")]
+    Fahrenheit,
+}
+
+impl TemperatureUnit {
+    fn convert_celsius(self, celsius: f32) -> f32 {
+        match self {
+            TemperatureUnit::Celsius => celsius,
+            TemperatureUnit::Fahrenheit => celsius * 1.8 + 32.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SystemValue {
+    EngineCount,
+    TankCount,
+    BusCount,
+    BatteryCount,
+    GeneratorCount,
+    Rpm,
+    N1,
+    N2,
+    ManifoldPressure,
+    Egt,
+    Itt,
+    OilPressure,
+    OilTemperature,
+    FuelFlow,
+    FuelQuantity,
+    BusVolts,
+    BatteryAmps,
+    GeneratorAmps,
+}
+
+/// A system dataref. Array datarefs are requested one element at a
+/// time, for as many engines, tanks, etc. as the aircraft has.
+pub struct SystemDataref {
+    pub id: SystemValue,
+    pub name: &'static str,
+    pub len: usize,
+}
+
+const MAX_ENGINES: usize = 8;
+const MAX_TANKS: usize = 9;
+const MAX_BUSES: usize = 6;
+const MAX_BATTERIES: usize = 8;
+const MAX_GENERATORS: usize = 8;
+
+pub static SYSTEM_DATAREFS: &[SystemDataref] = &[
+    SystemDataref {
+        id: SystemValue::EngineCount,
+        name: "sim/aircraft/engine/acf_num_engines",
+        len: 1,
+    },
+    SystemDataref {
+        id: SystemValue::TankCount,
+        name: "sim/aircraft/overflow/acf_num_tanks",
+        len: 1,
+    },
+    SystemDataref {
+        id: SystemValue::BusCount,
+        name: "sim/aircraft/electrical/num_buses",
+        len: 1,
+    },
+    SystemDataref {
+        id: SystemValue::BatteryCount,
+        name: "sim/aircraft/electrical/num_batteries",
+        len: 1,
+    },
+    SystemDataref {
+        id: SystemValue::GeneratorCount,
+        name: "sim/aircraft/electrical/num_generators",
+        len: 1,
+    },
+    SystemDataref {
+        id: SystemValue::Rpm,
+        name: "sim/cockpit2/engine/indicators/engine_speed_rpm",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::N1,
+        name: "sim/cockpit2/engine/indicators/N1_percent",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::N2,
+        name: "sim/cockpit2/engine/indicators/N2_percent",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::ManifoldPressure,
+        name: "sim/cockpit2/engine/indicators/MPR_in_hg",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::Egt,
+        name: "sim/cockpit2/engine/indicators/EGT_deg_C",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::Itt,
+        name: "sim/cockpit2/engine/indicators/ITT_deg_C",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::OilPressure,
+        name: "sim/cockpit2/engine/indicators/oil_pressure_psi",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::OilTemperature,
+        name: "sim/cockpit2/engine/indicators/oil_temperature_deg_C",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::FuelFlow,
+        name: "sim/cockpit2/engine/indicators/fuel_flow_kg_sec",
+        len: MAX_ENGINES,
+    },
+    SystemDataref {
+        id: SystemValue::FuelQuantity,
+        name: "sim/cockpit2/fuel/fuel_quantity",
+        len: MAX_TANKS,
+    },
+    SystemDataref {
+        id: SystemValue::BusVolts,
+        name: "sim/cockpit2/electrical/bus_volts",
+        len: MAX_BUSES,
+    },
+    SystemDataref {
+        id: SystemValue::BatteryAmps,
+        name: "sim/cockpit2/electrical/battery_amps",
+        len: MAX_BATTERIES,
+    },
+    SystemDataref {
+        id: SystemValue::GeneratorAmps,
+        name: "sim/cockpit2/electrical/generator_amps",
+        len: MAX_GENERATORS,
+    },
+];
+
+/// The number of engines, tanks, buses, batteries and generators of the
+/// aircraft, which decide the array elements requested.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct SystemCounts {
+    pub engines: usize,
+    pub tanks: usize,
+    pub buses: usize,
+    pub batteries: usize,
+    pub generators: usize,
+}
+
+impl SystemCounts {
+    /// The number of elements of `dataref` used with these counts.
+    fn len(&self, dataref: &SystemDataref) -> usize {
+        let count = match dataref.id {
+            SystemValue::EngineCount
+            | SystemValue::TankCount
+            | SystemValue::BusCount
+            | SystemValue::BatteryCount
+            | SystemValue::GeneratorCount => 1,
+            SystemValue::Rpm
+            | SystemValue::N1
+            | SystemValue::N2
+            | SystemValue::ManifoldPressure
+            | SystemValue::Egt
+            | SystemValue::Itt
+            | SystemValue::OilPressure
+            | SystemValue::OilTemperature
+            | SystemValue::FuelFlow => self.engines,
+            SystemValue::FuelQuantity => self.tanks,
+            SystemValue::BusVolts => self.buses,
+            SystemValue::BatteryAmps => self.batteries,
+            SystemValue::GeneratorAmps => self.generators,
+        };
+        count.min(dataref.len)
+    }
+
+    /// The larger of the counts of each kind.
+    pub fn max(&self, other: &SystemCounts) -> SystemCounts {
+        SystemCounts {
+            engines: self.engines.max(other.engines),
+            tanks: self.tanks.max(other.tanks),
+            buses: self.buses.max(other.buses),
+            batteries: self.batteries.max(other.batteries),
+            generators: self.generators.max(other.generators),
+        }
+    }
+
+    /// The requests of the system datarefs for the counts, as the
+    /// frequency, the offset in the system values and the name of each
+    /// element. The elements requested only for `previous` are stopped
+    /// with frequency 0.
+    pub fn requests(&self, previous: &SystemCounts) -> Vec<(u32, usize, String)> {
+        let mut requests = Vec::new();
+        let mut offset = 0;
+        for dataref in SYSTEM_DATAREFS {
+            let len = self.len(dataref);
+            for i in 0..len.max(previous.len(dataref)) {
+                let freq = match dataref.len {
+                    _ if i >= len => 0,
+                    1 => 1,
+                    _ => 2,
+                };
+                let name = match dataref.len {
+                    1 => dataref.name.to_string(),
+                    _ => format!("{}[{}]", dataref.name, i),
+                };
+                requests.push((freq, offset + i, name));
+            }
+            offset += dataref.len;
+        }
+        requests
+    }
+}
+
+/// The received values of the system datarefs, in X-Plane units.
+pub struct SystemValues(Vec<Vec<f32>>);
+
+impl Default for SystemValues {
+    fn default() -> Self {
+        SystemValues(
+            SYSTEM_DATAREFS
+                .iter()
+                .map(|dataref| vec![0.0; dataref.len])
+                .collect(),
+        )
+    }
+}
+
+impl SystemValues {
+    /// The number of values that may be requested from X-Plane.
+    pub fn len() -> usize {
+        SYSTEM_DATAREFS.iter().map(|dataref| dataref.len).sum()
+    }
+
+    /// Sets value `offset` of all the system values. Returns false if
+    /// `offset` is past them.
+    pub fn set(&mut self, offset: usize, value: f32) -> bool {
+        let mut offset = offset;
+        for (dataref, values) in SYSTEM_DATAREFS.iter().zip(self.0.iter_mut()) {
+            if offset < dataref.len {
+                values[offset] = value;
+                return true;
+            }
+            offset -= dataref.len;
+        }
+        false
+    }
+
+    fn values(&self, id: SystemValue) -> &[f32] {
+        SYSTEM_DATAREFS
+            .iter()
+            .position(|dataref| dataref.id == id)
+            .map_or(&[], |i| &self.0[i])
+    }
+
+    /// The number of engines, tanks, etc. of the aircraft, from the
+    /// count dataref `id`.
+    fn count(&self, id: SystemValue, max: usize) -> usize {
+        let count = self.values(id).first().copied().unwrap_or_default();
+        (count.round().max(0.0) as usize).min(max)
+    }
+
+    /// The counts received from the count datarefs.
+    pub fn counts(&self) -> SystemCounts {
+        SystemCounts {
+            engines: self.count(SystemValue::EngineCount, MAX_ENGINES),
+            tanks: self.count(SystemValue::TankCount, MAX_TANKS),
+            buses: self.count(SystemValue::BusCount, MAX_BUSES),
+            batteries: self.count(SystemValue::BatteryCount, MAX_BATTERIES),
+            generators: self.count(SystemValue::GeneratorCount, MAX_GENERATORS),
+        }
+    }
+
+    /// The first `count` values of `id`, converted with `convert`.
+    fn array(&self, id: SystemValue, count: usize, convert: impl Fn(f32) -> f32) -> Vec<f32> {
+        self.values(id)
+            .iter()
+            .take(count)
+            .map(|v| convert(*v))
+            .collect()
+    }
+
+    pub fn systems(&self, units: SystemUnits) -> Systems {
+        let counts = self.counts();
+        let value = |id: SystemValue, i: usize| self.values(id)[i];
+        let temperature =
+            |id: SystemValue, i: usize| units.temperature.convert_celsius(value(id, i));
+
+        let engines = (0..counts.engines)
+            .map(|i| Engine {
+                rpm: value(SystemValue::Rpm, i),
+                n1: value(SystemValue::N1, i),
+                n2: value(SystemValue::N2, i),
+                manifold_pressure: value(SystemValue::ManifoldPressure, i),
+                egt: temperature(SystemValue::Egt, i),
+                itt: temperature(SystemValue::Itt, i),
+                oil_pressure: value(SystemValue::OilPressure, i),
+                oil_temperature: temperature(SystemValue::OilTemperature, i),
+                fuel_flow: units
+                    .fuel
+                    .convert_kg(value(SystemValue::FuelFlow, i) * 3600.0),
+            })
+            .collect();
+
+        let tanks = self.array(SystemValue::FuelQuantity, counts.tanks, |kg| {
+            units.fuel.convert_kg(kg)
+        });
+
+        Systems {
+            units,
+            engines,
+            fuel: Fuel {
+                total: tanks.iter().sum(),
+                tanks,
+            },
+            electrical: Electrical {
+                bus_volts: self.array(SystemValue::BusVolts, counts.buses, |v| v),
+                battery_amps: self.array(SystemValue::BatteryAmps, counts.batteries, |v| v),
+                generator_amps: self.array(SystemValue::GeneratorAmps, counts.generators, |v| v),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod systems_tests {
+    use super::{
+        FuelUnit, SystemCounts, SystemUnits, SystemValue, SystemValues, TemperatureUnit,
+        SYSTEM_DATAREFS,
+    };
+
+    fn set(values: &mut SystemValues, id: SystemValue, index: usize, value: f32) {
+        let offset: usize = SYSTEM_DATAREFS
+            .iter()
+            .take_while(|dataref| dataref.id != id)
+            .map(|dataref| dataref.len)
+            .sum();
+        assert!(values.set(offset + index, value));
+    }
+
+    #[test]
+    fn engine_count() {
+        let mut values = SystemValues::default();
+        assert!(values.systems(SystemUnits::default()).engines.is_empty());
+
+        set(&mut values, SystemValue::EngineCount, 0, 2.0);
+        set(&mut values, SystemValue::N1, 1, 85.5);
+        set(&mut values, SystemValue::TankCount, 0, 3.0);
+        set(&mut values, SystemValue::FuelQuantity, 2, 1000.0);
+
+        let systems = values.systems(SystemUnits::default());
+        assert_eq!(systems.engines.len(), 2);
+        assert_eq!(systems.engines[1].n1, 85.5);
+        assert_eq!(systems.fuel.tanks, [0.0, 0.0, 1000.0]);
+        assert_eq!(systems.fuel.total, 1000.0);
+        assert!(systems.electrical.bus_volts.is_empty());
+
+        set(&mut values, SystemValue::EngineCount, 0, 20.0);
+        assert_eq!(values.systems(SystemUnits::default()).engines.len(), 8);
+
+        let len = SYSTEM_DATAREFS.iter().map(|dataref| dataref.len).sum();
+        assert!(!values.set(len, 1.0));
+    }
+
+    #[test]
+    fn requests_for_counts() {
+        let single = SystemCounts {
+            engines: 1,
+            tanks: 2,
+            buses: 1,
+            batteries: 1,
+            generators: 1,
+        };
+        let requests = single.requests(&SystemCounts::default());
+        let names = |freq: u32| {
+            requests
+                .iter()
+                .filter(|(f, _, _)| *f == freq)
+                .map(|(_, _, name)| name.as_str())
+                .collect::<Vec<&str>>()
+        };
+        assert_eq!(names(1).len(), 5);
+        assert_eq!(names(2).len(), 9 + 2 + 3);
+        assert!(names(2).contains(&"sim/cockpit2/engine/indicators/N1_percent[0]"));
+        assert!(!names(2).contains(&"sim/cockpit2/engine/indicators/N1_percent[1]"));
+        assert!(names(0).is_empty());
+
+        let mut values = SystemValues::default();
+        for (_, offset, name) in &requests {
+            if name == "sim/cockpit2/fuel/fuel_quantity[1]" {
+                assert!(values.set(*offset, 100.0));
+            }
+        }
+        set(&mut values, SystemValue::TankCount, 0, 2.0);
+        assert_eq!(
+            values.systems(SystemUnits::default()).fuel.tanks,
+            [0.0, 100.0]
+        );
+
+        let twin = SystemCounts {
+            engines: 2,
+            ..single
+        };
+        let stopped = single.requests(&twin);
+        assert_eq!(stopped.len(), requests.len() + 9);
+        assert!(stopped
+            .iter()
+            .any(|(f, _, name)| *f == 0 && name == "sim/cockpit2/engine/indicators/N1_percent[1]"));
+    }
+
+    #[test]
+    fn unit_conversion() {
+        let mut values = SystemValues::default();
+        set(&mut values, SystemValue::EngineCount, 0, 1.0);
+        set(&mut values, SystemValue::OilTemperature, 0, 100.0);
+        set(&mut values, SystemValue::FuelFlow, 0, 0.5);
+        set(&mut values, SystemValue::TankCount, 0, 1.0);
+        set(&mut values, SystemValue::FuelQuantity, 0, 100.0);
+
+        let metric = values.systems(SystemUnits::default());
+        assert_eq!(metric.engines[0].oil_temperature, 100.0);
+        assert_eq!(metric.engines[0].fuel_flow, 1800.0);
+
+        let imperial = values.systems(SystemUnits {
+            fuel: FuelUnit::Lb,
+            temperature: TemperatureUnit::Fahrenheit,
+        });
+        assert_eq!(imperial.engines[0].oil_temperature, 212.0);
+        assert!((imperial.fuel.tanks[0] - 220.46).abs() < 0.01);
+
+        let json = serde_json::to_value(&imperial).unwrap();
+        assert_eq!(
+            json["units"],
+            serde_json::json!({ "fuel": "lb", "temperature": "F" })
+        );
+        assert_eq!(json["engines"][0]["oil-temperature"], 212.0);
+    }
+}
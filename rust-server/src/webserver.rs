@@ -2,79 +2,155 @@ use std::{convert::Infallible, sync::Arc};
 
 use log::debug;
 use tokio::sync::mpsc::Sender as MPSCSender;
-use tokio::{select, sync::Mutex};
+use tokio::sync::{broadcast, watch};
 use warp::{
     filters::ws::{Message, WebSocket},
-    Filter,
+    http::{StatusCode, Uri},
+    path::FullPath,
+    Filter, Rejection,
 };
 
 use futures_util::{SinkExt, StreamExt};
 
-use crate::{channels::ChannelsUIEndpoint, xpc_types::UICommand, xplane_comms::ReceivedDatarefs};
+use crate::{
+    channels::ChannelsUIEndpoint,
+    gpio::{Layers, VirtualEdge},
+    logging::LogControl,
+    macros::Macros,
+    metrics::METRICS,
+    panels::PanelsConfig,
+    rules::RuleEvents,
+    supervisor::Supervisor,
+    xpc_types::UICommand,
+    xplane_comms::ReceivedDatarefs,
+};
 
-pub async fn run_webserver(channels: ChannelsUIEndpoint, port: u16, web_files_dir: &String) {
-    let datarefs = Arc::new(Mutex::new(ReceivedDatarefs {
-        ..Default::default()
-    }));
-    let datarefs_clone = datarefs.clone();
+mod datarefs;
+mod gpio_inject;
+mod gpio_layers;
+mod logs;
+mod macros;
+mod panels;
+mod rules;
+
+use datarefs::{dataref_routes, dataref_view, DatarefQuery};
+
+/// The GPIO state that can be shown and changed through the web server.
+pub struct GpioControl {
+    /// Edges of the virtual pins, if they are injected through the web
+    /// server
+    pub inject: Option<broadcast::Sender<VirtualEdge>>,
+    pub layers: Layers,
+}
 
-    let ChannelsUIEndpoint {
-        mut data,
-        ui_cmds: commands_from_ui,
-    } = channels;
+/// The subsystems that the web server shows and controls.
+pub struct WebServices {
+    pub supervisor: Supervisor,
+    pub log_control: LogControl,
+    pub gpio: GpioControl,
+    pub macros: Arc<Macros>,
+    pub rules: RuleEvents,
+    pub panels: Arc<PanelsConfig>,
+}
 
-    let data_receiver = tokio::spawn(async move {
-        loop {
-            select! {
-                Some(dr) = data.recv() => {
-                    let mut datarefs_unlocked = datarefs.lock().await;
-                    *datarefs_unlocked = dr;
-                },
-            }
-        }
-    });
+pub async fn run_webserver(
+    channels: Vec<ChannelsUIEndpoint>,
+    port: u16,
+    web_files_dir: &str,
+    services: WebServices,
+) {
+    let WebServices {
+        supervisor,
+        log_control,
+        gpio,
+        macros,
+        rules,
+        panels,
+    } = services;
+
+    let connection_names = channels
+        .iter()
+        .map(|c| c.connection.clone())
+        .collect::<Vec<String>>();
+    let connection = with_connection(channels);
 
     let readme = warp::path("readme").map(|| "Boom, readme");
-    let datarefs_route = warp::path("datarefs")
-        .and(with_datarefs(datarefs_clone.clone()))
-        .and_then(reply_with_datarefs);
-    let websocket = warp::path("websocket")
+    let connections = warp::path!("connections").map(move || warp::reply::json(&connection_names));
+    let datarefs_route = dataref_routes(connection.clone());
+    let macros_route = macros::macro_routes(connection.clone(), macros);
+    let websocket = connection
+        .clone()
+        .and(warp::path("websocket"))
         .and(warp::ws())
-        .and(with_datarefs(datarefs_clone.clone()))
-        .and(with_cmdchan(commands_from_ui))
-        .map(|ws: warp::ws::Ws, datarefs, cmdchan| {
-            ws.on_upgrade(|websocket| run_websocket(websocket, datarefs, cmdchan))
-        });
-    let static_files = warp::any().and(warp::fs::dir(web_files_dir.clone()));
-    let routes = readme.or(datarefs_route).or(websocket).or(static_files);
+        .and(warp::query::<DatarefQuery>())
+        .map(
+            |endpoint: ChannelsUIEndpoint, ws: warp::ws::Ws, query: DatarefQuery| {
+                ws.on_upgrade(|websocket| {
+                    run_websocket(websocket, query, endpoint.data, endpoint.ui_cmds)
+                })
+            },
+        );
+    let metrics = warp::path("metrics").map(|| {
+        warp::reply::with_header(
+            METRICS.render(),
+            "Content-Type",
+            "text/plain; version=0.0.4",
+        )
+    });
+    let health = warp::path("health")
+        .and(with_supervisor(supervisor))
+        .and_then(reply_with_health);
+    let panels_route = connection
+        .clone()
+        .and(panels::panel_routes(panels))
+        .map(|_, reply| reply);
+    let static_files = connection
+        .and(warp::fs::dir(web_files_dir.to_string()))
+        .map(|_, file| file);
+    let routes = readme
+        .or(connections)
+        .or(connection_page())
+        .or(datarefs_route)
+        .or(macros_route)
+        .or(websocket)
+        .or(health)
+        .or(metrics)
+        .or(logs::log_routes(log_control))
+        .or(gpio_inject::gpio_inject_routes(gpio.inject))
+        .or(gpio_layers::gpio_layer_routes(gpio.layers))
+        .or(rules::rule_routes(rules))
+        .or(panels_route)
+        .or(static_files);
 
     warp::serve(routes).bind(([0, 0, 0, 0], port)).await;
-    data_receiver.abort();
 }
 
-async fn reply_with_datarefs(
-    datarefs: Arc<Mutex<ReceivedDatarefs>>,
-) -> Result<impl warp::reply::Reply, Infallible> {
-    let reply = {
-        let dr = unwrap_datarefs(&datarefs).await;
-        warp::reply::json(&dr)
+async fn reply_with_health(supervisor: Supervisor) -> Result<impl warp::reply::Reply, Infallible> {
+    let report = supervisor.health_report();
+    let status = if report.healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
     };
 
-    Ok(reply)
+    Ok(warp::reply::with_status(warp::reply::json(&report), status))
 }
 
 async fn run_websocket(
     ws: WebSocket,
-    datarefs: Arc<Mutex<ReceivedDatarefs>>,
+    query: DatarefQuery,
+    datarefs: watch::Receiver<ReceivedDatarefs>,
     cmdchan: MPSCSender<UICommand>,
 ) {
     let (mut tx, mut rx) = ws.split();
 
+    METRICS.websocket_clients.inc();
+
     tokio::spawn(async move {
         loop {
-            let dr = unwrap_datarefs(&datarefs).await;
-            let msg = Message::text(serde_json::to_string(&dr).unwrap());
-            if let Err(_) = tx.send(msg).await {
+            let view = dataref_view(&datarefs.borrow(), query.fields.as_deref());
+            let msg = Message::text(view.to_string());
+            if tx.send(msg).await.is_err() {
                 break;
             }
 
@@ -89,26 +165,66 @@ async fn run_websocket(
             }
             if let Ok(str_msg) = msg.to_str() {
                 if let Ok(cmd) = serde_json::from_str::<UICommand>(str_msg) {
-                    debug!("Got command {}", cmd.command);
+                    debug!("Got command {:?}", cmd);
+                    METRICS.commands_sent.inc(&["ui"]);
                     cmdchan.send(cmd).await.ok();
                 }
             }
         }
+
+        METRICS.websocket_clients.dec();
     });
 }
 
-fn with_datarefs(
-    datarefs: Arc<Mutex<ReceivedDatarefs>>,
-) -> impl Filter<Extract = (Arc<Mutex<ReceivedDatarefs>>,), Error = Infallible> + Clone {
-    warp::any().map(move || datarefs.clone())
+/// Redirects /connections/<name> to /connections/<name>/, so that the
+/// relative paths of the web UI stay under the prefix.
+fn connection_page() -> impl Filter<Extract = (impl warp::Reply,), Error = Rejection> + Clone {
+    warp::path!("connections" / String)
+        .and(warp::path::full())
+        .and_then(|_name: String, full: FullPath| async move {
+            if full.as_str().ends_with('/') {
+                return Err(warp::reject::not_found());
+            }
+
+            format!("{}/", full.as_str())
+                .parse::<Uri>()
+                .map(warp::redirect::permanent)
+                .map_err(|_| warp::reject::not_found())
+        })
 }
 
-fn with_cmdchan(
-    cmdchan: MPSCSender<UICommand>,
-) -> impl Filter<Extract = (MPSCSender<UICommand>,), Error = Infallible> + Clone {
-    warp::any().map(move || cmdchan.clone())
+fn with_supervisor(
+    supervisor: Supervisor,
+) -> impl Filter<Extract = (Supervisor,), Error = Infallible> + Clone {
+    warp::any().map(move || supervisor.clone())
 }
 
-async fn unwrap_datarefs(datarefs: &Arc<Mutex<ReceivedDatarefs>>) -> ReceivedDatarefs {
-    datarefs.lock().await.clone()
+/// Selects the X-Plane connection by the path prefix
+/// /connections/<name>/. Paths without the prefix use the first
+/// configured connection.
+fn with_connection(
+    endpoints: Vec<ChannelsUIEndpoint>,
+) -> impl Filter<Extract = (ChannelsUIEndpoint,), Error = Rejection> + Clone {
+    let default = endpoints[0].clone();
+    let endpoints = Arc::new(endpoints);
+
+    let named = warp::path("connections")
+        .and(warp::path::param::<String>())
+        .and_then(move |name: String| {
+            let endpoints = endpoints.clone();
+            async move {
+                endpoints
+                    .iter()
+                    .find(|e| e.connection == name)
+                    .cloned()
+                    .ok_or_else(warp::reject::not_found)
+            }
+        });
+
+    let default = warp::any().and_then(move || {
+        let default = default.clone();
+        async move { Ok::<ChannelsUIEndpoint, Rejection>(default) }
+    });
+
+    named.or(default).unify()
 }
@@ -0,0 +1,267 @@
+use std::{
+    collections::hash_map::DefaultHasher,
+    collections::HashSet,
+    convert::Infallible,
+    hash::{Hash, Hasher},
+    time::Duration,
+};
+
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+use serde_json::Value;
+use tokio::{sync::watch, time::Instant};
+use warp::{
+    filters::sse::Event,
+    http::{Response, StatusCode},
+    hyper::Body,
+    Filter, Rejection, Reply,
+};
+
+use crate::{channels::ChannelsUIEndpoint, xplane_comms::ReceivedDatarefs};
+
+const DEFAULT_POLL_TIMEOUT_SECS: u64 = 30;
+const MAX_POLL_TIMEOUT_SECS: u64 = 120;
+
+#[derive(Debug, Default, Deserialize)]
+pub struct DatarefQuery {
+    /// Comma-separated list of the keys to include in the response
+    pub fields: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct PollQuery {
+    fields: Option<String>,
+    timeout: Option<u64>,
+}
+
+/// Routes for the clients that cannot use the websocket: plain polling,
+/// long polling, and server-sent events.
+pub fn dataref_routes<C>(
+    connection: C,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    C: Filter<Extract = (ChannelsUIEndpoint,), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    let poll = connection
+        .clone()
+        .and(warp::path!("datarefs"))
+        .and(warp::get())
+        .and(warp::query::<DatarefQuery>())
+        .map(|endpoint: ChannelsUIEndpoint, query: DatarefQuery| {
+            let view = dataref_view(&endpoint.data.borrow(), query.fields.as_deref());
+            warp::reply::json(&view)
+        });
+
+    let long_poll = connection
+        .clone()
+        .and(warp::path!("datarefs" / "poll"))
+        .and(warp::get())
+        .and(warp::query::<PollQuery>())
+        .and(warp::header::optional::<String>("if-none-match"))
+        .then(
+            |endpoint: ChannelsUIEndpoint, query: PollQuery, if_none_match: Option<String>| {
+                long_poll(query, if_none_match, endpoint.data)
+            },
+        );
+
+    let events = connection
+        .and(warp::path!("events"))
+        .and(warp::get())
+        .and(warp::query::<DatarefQuery>())
+        .map(|endpoint: ChannelsUIEndpoint, query: DatarefQuery| {
+            warp::sse::reply(
+                warp::sse::keep_alive().stream(dataref_events(endpoint.data, query.fields)),
+            )
+        });
+
+    poll.or(long_poll).or(events)
+}
+
+/// Converts the datarefs to the JSON sent to the clients, keeping only
+/// the keys listed in `fields` if it has been given.
+pub fn dataref_view(datarefs: &ReceivedDatarefs, fields: Option<&str>) -> Value {
+    let mut view = serde_json::to_value(datarefs).unwrap();
+
+    if let (Some(fields), Value::Object(map)) = (fields, &mut view) {
+        let wanted = fields.split(',').map(str::trim).collect::<HashSet<&str>>();
+        map.retain(|key, _| wanted.contains(key.as_str()));
+    }
+
+    view
+}
+
+fn etag(view: &Value) -> String {
+    let mut hasher = DefaultHasher::new();
+    view.to_string().hash(&mut hasher);
+    format!("\"{:x}\"", hasher.finish())
+}
+
+/// Replies immediately if the data differs from the version the client
+/// has (given in If-None-Match). Otherwise waits until the data changes,
+/// or replies with 304 Not Modified when the timeout expires.
+async fn long_poll(
+    query: PollQuery,
+    if_none_match: Option<String>,
+    mut datarefs: watch::Receiver<ReceivedDatarefs>,
+) -> Response<Body> {
+    let timeout = query
+        .timeout
+        .unwrap_or(DEFAULT_POLL_TIMEOUT_SECS)
+        .min(MAX_POLL_TIMEOUT_SECS);
+    let deadline = Instant::now() + Duration::from_secs(timeout);
+
+    loop {
+        let view = dataref_view(&datarefs.borrow_and_update(), query.fields.as_deref());
+        let tag = etag(&view);
+
+        if if_none_match.as_deref() != Some(tag.as_str()) {
+            return Response::builder()
+                .header("Content-Type", "application/json")
+                .header("ETag", tag)
+                .body(Body::from(view.to_string()))
+                .unwrap();
+        }
+
+        match tokio::time::timeout_at(deadline, datarefs.changed()).await {
+            Ok(Ok(())) => continue,
+            _ => {
+                return Response::builder()
+                    .status(StatusCode::NOT_MODIFIED)
+                    .header("ETag", tag)
+                    .body(Body::empty())
+                    .unwrap()
+            }
+        }
+    }
+}
+
+fn dataref_events(
+    datarefs: watch::Receiver<ReceivedDatarefs>,
+    fields: Option<String>,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(
+        (datarefs, None::<Value>),
+        move |(mut datarefs, previous)| {
+            let fields = fields.clone();
+            async move {
+                loop {
+                    let view = dataref_view(&datarefs.borrow_and_update(), fields.as_deref());
+                    if previous.as_ref() != Some(&view) {
+                        let event = Event::default().event("datarefs").data(view.to_string());
+                        return Some((Ok(event), (datarefs, Some(view))));
+                    }
+
+                    datarefs.changed().await.ok()?;
+                }
+            }
+        },
+    )
+}
+
+#[cfg(test)]
+mod datarefs_route_tests {
+    use std::time::Duration;
+
+    use tokio::sync::{mpsc, watch};
+
+    use super::super::with_connection;
+    use super::{dataref_routes, dataref_view};
+    use crate::{channels::ChannelsUIEndpoint, xplane_comms::ReceivedDatarefs};
+
+    fn endpoint(connection: &str) -> (watch::Sender<ReceivedDatarefs>, ChannelsUIEndpoint) {
+        let (tx, rx) = watch::channel(ReceivedDatarefs::default());
+        let (ui_cmds, _) = mpsc::channel(1);
+        let endpoint = ChannelsUIEndpoint {
+            connection: connection.to_string(),
+            data: rx,
+            ui_cmds,
+            last_packet: watch::channel(None).1,
+        };
+        (tx, endpoint)
+    }
+
+    #[test]
+    fn view_with_selected_fields() {
+        let datarefs = ReceivedDatarefs {
+            beacon: true,
+            ias: 95.0,
+            ..Default::default()
+        };
+
+        let view = dataref_view(&datarefs, Some("beacon, ias,unknown"));
+        assert_eq!(view, serde_json::json!({ "beacon": true, "ias": 95.0 }));
+
+        let full_view = dataref_view(&datarefs, None);
+        assert!(full_view.get("altitude").is_some());
+    }
+
+    #[tokio::test]
+    async fn long_poll_returns_when_data_changes() {
+        let (tx, endpoint) = endpoint("default");
+        let routes = dataref_routes(with_connection(vec![endpoint]));
+
+        let first = warp::test::request()
+            .path("/datarefs/poll?fields=beacon")
+            .reply(&routes)
+            .await;
+        assert_eq!(first.status(), 200);
+        assert_eq!(first.body(), "{\"beacon\":false}");
+        let tag = first.headers()["etag"].to_str().unwrap().to_string();
+
+        let unchanged = warp::test::request()
+            .path("/datarefs/poll?fields=beacon&timeout=0")
+            .header("If-None-Match", &tag)
+            .reply(&routes)
+            .await;
+        assert_eq!(unchanged.status(), 304);
+
+        let waiting = warp::test::request()
+            .path("/datarefs/poll?fields=beacon&timeout=10")
+            .header("If-None-Match", &tag)
+            .reply(&routes);
+        let update = async {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            tx.send_modify(|dr| dr.ias = 100.0);
+            tokio::time::sleep(Duration::from_millis(50)).await;
+            tx.send_modify(|dr| dr.beacon = true);
+        };
+        let (changed, _) = tokio::join!(waiting, update);
+        assert_eq!(changed.status(), 200);
+        assert_eq!(changed.body(), "{\"beacon\":true}");
+    }
+
+    #[tokio::test]
+    async fn select_connection_by_path() {
+        let (captain_tx, captain) = endpoint("captain");
+        let (fo_tx, fo) = endpoint("fo");
+        let routes = dataref_routes(with_connection(vec![captain, fo]));
+
+        captain_tx.send_modify(|dr| dr.altitude = 1000.0);
+        fo_tx.send_modify(|dr| dr.altitude = 2000.0);
+
+        let altitude = |path: &'static str| {
+            let routes = routes.clone();
+            async move {
+                let reply = warp::test::request().path(path).reply(&routes).await;
+                (
+                    reply.status().as_u16(),
+                    String::from_utf8(reply.body().to_vec()).unwrap(),
+                )
+            }
+        };
+
+        assert_eq!(
+            altitude("/datarefs?fields=altitude").await,
+            (200, String::from("{\"altitude\":1000.0}"))
+        );
+        assert_eq!(
+            altitude("/connections/captain/datarefs?fields=altitude").await,
+            (200, String::from("{\"altitude\":1000.0}"))
+        );
+        assert_eq!(
+            altitude("/connections/fo/datarefs?fields=altitude").await,
+            (200, String::from("{\"altitude\":2000.0}"))
+        );
+        assert_eq!(altitude("/connections/nobody/datarefs").await.0, 404);
+    }
+}
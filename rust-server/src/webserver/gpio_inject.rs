@@ -0,0 +1,70 @@
+use tokio::sync::broadcast;
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::gpio::VirtualEdge;
+
+/// Route for setting the level of a virtual GPIO pin, when the GPIO
+/// inputs are driven through the web server.
+pub fn gpio_inject_routes(
+    edges: Option<broadcast::Sender<VirtualEdge>>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    warp::path!("gpio" / "inject")
+        .and(warp::post())
+        .and(warp::body::json())
+        .map(move |edge: VirtualEdge| {
+            let status = match &edges {
+                None => StatusCode::NOT_FOUND,
+                Some(edges) => {
+                    log::debug!("Injecting GPIO edge {:?}", edge);
+                    match edges.send(edge) {
+                        Ok(_) => StatusCode::NO_CONTENT,
+                        Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+                    }
+                }
+            };
+            warp::reply::with_status(warp::reply(), status)
+        })
+}
+
+#[cfg(test)]
+mod gpio_inject_route_tests {
+    use tokio::sync::broadcast;
+
+    use super::gpio_inject_routes;
+    use crate::gpio::VirtualEdge;
+
+    #[tokio::test]
+    async fn inject_edge() {
+        let (edges, mut received) = broadcast::channel(4);
+        let routes = gpio_inject_routes(Some(edges));
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/gpio/inject")
+            .body(r#"{ "pin": 16, "value": false }"#)
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), 204);
+        assert_eq!(
+            received.try_recv().unwrap(),
+            VirtualEdge {
+                chip: String::from("gpiochip0"),
+                pin: 16,
+                value: false
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn injection_disabled() {
+        let routes = gpio_inject_routes(None);
+
+        let reply = warp::test::request()
+            .method("POST")
+            .path("/gpio/inject")
+            .body(r#"{ "pin": 16, "value": false }"#)
+            .reply(&routes)
+            .await;
+        assert_eq!(reply.status(), 404);
+    }
+}
@@ -0,0 +1,129 @@
+use std::convert::Infallible;
+
+use futures_util::{stream, Stream};
+use serde::Deserialize;
+use warp::{filters::sse::Event, http::StatusCode, Filter, Rejection, Reply};
+
+use crate::gpio::Layers;
+
+#[derive(Debug, Deserialize)]
+struct SelectLayer {
+    layer: String,
+}
+
+/// Routes for showing the active layer of the GPIO inputs, streaming its
+/// changes with server-sent events, and selecting it from the UI.
+pub fn gpio_layer_routes(
+    layers: Layers,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let get_layer = {
+        let layers = layers.clone();
+        warp::path!("gpio" / "layer")
+            .and(warp::get())
+            .map(move || warp::reply::json(&*layers.subscribe().borrow()))
+    };
+
+    let stream = {
+        let layers = layers.clone();
+        warp::path!("gpio" / "layer" / "stream")
+            .and(warp::get())
+            .map(move || warp::sse::reply(warp::sse::keep_alive().stream(layer_events(&layers))))
+    };
+
+    let set_layer = warp::path!("gpio" / "layer")
+        .and(warp::put())
+        .and(warp::body::json())
+        .map(move |select: SelectLayer| {
+            // Only the configured layers are selected by name, not with
+            // the "next" and "previous" arguments of the layer commands
+            let known = layers.subscribe().borrow().layers.contains(&select.layer);
+            if known {
+                layers.handle_command(&format!("layer:{}", select.layer));
+            }
+            let active = layers.subscribe().borrow().clone();
+            let status = if known {
+                StatusCode::OK
+            } else {
+                StatusCode::NOT_FOUND
+            };
+            warp::reply::with_status(warp::reply::json(&active), status)
+        });
+
+    get_layer.or(stream).or(set_layer)
+}
+
+/// The active layer when the stream starts, and then at each change.
+fn layer_events(layers: &Layers) -> impl Stream<Item = Result<Event, Infallible>> {
+    let mut active = layers.subscribe();
+    active.mark_changed();
+
+    stream::unfold(active, |mut active| async move {
+        active.changed().await.ok()?;
+        let event = Event::default()
+            .event("layer")
+            .data(serde_json::to_string(&*active.borrow_and_update()).unwrap());
+        Some((Ok(event), active))
+    })
+}
+
+#[cfg(test)]
+mod gpio_layer_route_tests {
+    use futures_util::StreamExt;
+
+    use super::{gpio_layer_routes, layer_events};
+    use crate::gpio::Layers;
+
+    #[tokio::test]
+    async fn show_and_select_layer() {
+        let layers = Layers::default();
+        layers.configure(["hdg", "crs"].map(String::from).to_vec());
+        let routes = gpio_layer_routes(layers);
+
+        let shown = warp::test::request()
+            .path("/gpio/layer")
+            .reply(&routes)
+            .await;
+        assert_eq!(shown.body(), r#"{"layer":"hdg","layers":["hdg","crs"]}"#);
+
+        let selected = warp::test::request()
+            .method("PUT")
+            .path("/gpio/layer")
+            .json(&serde_json::json!({ "layer": "crs" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(selected.status(), 200);
+        assert_eq!(selected.body(), r#"{"layer":"crs","layers":["hdg","crs"]}"#);
+
+        let unknown = warp::test::request()
+            .method("PUT")
+            .path("/gpio/layer")
+            .json(&serde_json::json!({ "layer": "baro" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(unknown.status(), 404);
+
+        let next = warp::test::request()
+            .method("PUT")
+            .path("/gpio/layer")
+            .json(&serde_json::json!({ "layer": "next" }))
+            .reply(&routes)
+            .await;
+        assert_eq!(next.status(), 404);
+        assert_eq!(next.body(), r#"{"layer":"crs","layers":["hdg","crs"]}"#);
+    }
+
+    #[tokio::test]
+    async fn stream_layer_changes() {
+        let layers = Layers::default();
+        layers.configure(["hdg", "crs"].map(String::from).to_vec());
+        let events = layer_events(&layers);
+        tokio::pin!(events);
+
+        let first = events.next().await.unwrap().unwrap();
+        assert!(format!("{}", first).contains(r#"{"layer":"hdg","layers":["hdg","crs"]}"#));
+
+        layers.handle_command("layer:next");
+        let changed = events.next().await.unwrap().unwrap();
+        assert!(format!("{}", changed).contains(r#""layer":"crs""#));
+    }
+}
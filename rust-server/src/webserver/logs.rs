@@ -0,0 +1,118 @@
+use std::{convert::Infallible, future::ready};
+
+use futures_util::{stream, Stream, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast::error::RecvError;
+use warp::{filters::sse::Event, http::StatusCode, Filter, Rejection, Reply};
+
+use crate::logging::{LogControl, LogLine};
+
+const DEFAULT_LOG_LINES: usize = 100;
+
+#[derive(Debug, Deserialize)]
+struct LogQuery {
+    module: Option<String>,
+    lines: Option<usize>,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct LogLevel {
+    level: String,
+}
+
+/// Routes for viewing the latest log lines, streaming the log with
+/// server-sent events, and changing the log level at runtime.
+pub fn log_routes(
+    log_control: LogControl,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let history = warp::path!("logs")
+        .and(warp::get())
+        .and(warp::query::<LogQuery>())
+        .and(with_log_control(log_control.clone()))
+        .map(|query: LogQuery, log_control: LogControl| {
+            warp::reply::json(&log_control.history(
+                query.module.as_deref(),
+                query.lines.unwrap_or(DEFAULT_LOG_LINES),
+            ))
+        });
+
+    let stream = warp::path!("logs" / "stream")
+        .and(warp::get())
+        .and(warp::query::<LogQuery>())
+        .and(with_log_control(log_control.clone()))
+        .map(|query: LogQuery, log_control: LogControl| {
+            warp::sse::reply(warp::sse::keep_alive().stream(log_events(log_control, query)))
+        });
+
+    let get_level = warp::path!("admin" / "log-level")
+        .and(warp::get())
+        .and(with_log_control(log_control.clone()))
+        .map(|log_control: LogControl| {
+            warp::reply::json(&LogLevel {
+                level: log_control.level(),
+            })
+        });
+
+    let set_level = warp::path!("admin" / "log-level")
+        .and(warp::put())
+        .and(warp::body::json())
+        .and(with_log_control(log_control))
+        .map(|new_level: LogLevel, log_control: LogControl| {
+            let status = match log_control.set_level(&new_level.level) {
+                Ok(()) => {
+                    log::warn!("Changed log level to {}", new_level.level);
+                    StatusCode::OK
+                }
+                Err(e) => {
+                    log::warn!("Not changing log level to {}: {}", new_level.level, e);
+                    StatusCode::BAD_REQUEST
+                }
+            };
+            warp::reply::with_status(
+                warp::reply::json(&LogLevel {
+                    level: log_control.level(),
+                }),
+                status,
+            )
+        });
+
+    history.or(stream).or(get_level).or(set_level)
+}
+
+fn log_events(
+    log_control: LogControl,
+    query: LogQuery,
+) -> impl Stream<Item = Result<Event, Infallible>> {
+    let live = log_control.subscribe();
+    let history = log_control.history(
+        query.module.as_deref(),
+        query.lines.unwrap_or(DEFAULT_LOG_LINES),
+    );
+
+    let live_lines = stream::unfold(live, |mut live| async move {
+        loop {
+            match live.recv().await {
+                Ok(line) => return Some((line, live)),
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    });
+
+    let module = query.module;
+    stream::iter(history)
+        .chain(live_lines.filter(move |line: &LogLine| {
+            ready(module.as_deref().is_none_or(|m| line.is_from_module(m)))
+        }))
+        .map(|line| {
+            Ok(Event::default()
+                .event("log")
+                .data(serde_json::to_string(&line).unwrap()))
+        })
+}
+
+fn with_log_control(
+    log_control: LogControl,
+) -> impl Filter<Extract = (LogControl,), Error = Infallible> + Clone {
+    warp::any().map(move || log_control.clone())
+}
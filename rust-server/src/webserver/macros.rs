@@ -0,0 +1,98 @@
+use std::sync::Arc;
+
+use warp::{http::StatusCode, Filter, Rejection, Reply};
+
+use crate::{
+    channels::ChannelsUIEndpoint,
+    macros::{Macros, MACRO_COMMAND_PREFIX},
+    metrics::METRICS,
+    xpc_types::UICommand,
+};
+
+/// Routes for listing the macros and for running them in a connection.
+pub fn macro_routes<C>(
+    connection: C,
+    macros: Arc<Macros>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
+where
+    C: Filter<Extract = (ChannelsUIEndpoint,), Error = Rejection> + Clone + Send + Sync + 'static,
+{
+    let list = {
+        let macros = macros.clone();
+        warp::path!("macros")
+            .and(warp::get())
+            .map(move || warp::reply::json(&macros.names()))
+    };
+
+    let run = connection
+        .and(warp::path!("macros" / String))
+        .and(warp::post())
+        .then(move |endpoint: ChannelsUIEndpoint, name: String| {
+            let macros = macros.clone();
+            async move {
+                if !macros.contains(&name) {
+                    return StatusCode::NOT_FOUND;
+                }
+                METRICS.commands_sent.inc(&["rest"]);
+                let command = UICommand::command(&format!("{}{}", MACRO_COMMAND_PREFIX, name));
+                match endpoint.ui_cmds.send(command).await {
+                    Ok(()) => StatusCode::ACCEPTED,
+                    Err(_) => StatusCode::SERVICE_UNAVAILABLE,
+                }
+            }
+        });
+
+    list.or(run)
+}
+
+#[cfg(test)]
+mod macro_route_tests {
+    use std::sync::Arc;
+
+    use tokio::sync::{mpsc, watch};
+
+    use super::super::with_connection;
+    use super::macro_routes;
+    use crate::{
+        channels::ChannelsUIEndpoint,
+        macros::{MacroConfig, Macros},
+        xpc_types::{ReceivedDatarefs, UICommand},
+    };
+
+    #[tokio::test]
+    async fn run_macro() {
+        let (ui_cmds, mut sent) = mpsc::channel(4);
+        let endpoint = ChannelsUIEndpoint {
+            connection: String::from("default"),
+            data: watch::channel(ReceivedDatarefs::default()).1,
+            ui_cmds,
+            last_packet: watch::channel(None).1,
+        };
+        let macros = Macros::new(vec![MacroConfig {
+            name: String::from("lights-on"),
+            steps: Vec::new(),
+        }]);
+        let routes = macro_routes(with_connection(vec![endpoint]), Arc::new(macros));
+
+        let list = warp::test::request().path("/macros").reply(&routes).await;
+        assert_eq!(list.body(), r#"["lights-on"]"#);
+
+        let run = warp::test::request()
+            .method("POST")
+            .path("/macros/lights-on")
+            .reply(&routes)
+            .await;
+        assert_eq!(run.status(), 202);
+        assert_eq!(
+            sent.try_recv().unwrap(),
+            UICommand::command("macro:lights-on")
+        );
+
+        let unknown = warp::test::request()
+            .method("POST")
+            .path("/connections/default/macros/nothing")
+            .reply(&routes)
+            .await;
+        assert_eq!(unknown.status(), 404);
+    }
+}
@@ -0,0 +1,67 @@
+use std::sync::Arc;
+
+use warp::{http::StatusCode, reply::Response, Filter, Rejection, Reply};
+
+use crate::panels::PanelsConfig;
+
+/// Routes for listing the panels and for loading one panel's layout.
+pub fn panel_routes(
+    panels: Arc<PanelsConfig>,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let list = {
+        let panels = panels.clone();
+        warp::path!("panels")
+            .and(warp::get())
+            .map(move || warp::reply::json(&panels.names()))
+    };
+
+    let panel =
+        warp::path!("panels" / String)
+            .and(warp::get())
+            .map(move |name: String| -> Response {
+                match panels.panel(&name) {
+                    Some(panel) => warp::reply::json(panel).into_response(),
+                    None => StatusCode::NOT_FOUND.into_response(),
+                }
+            });
+
+    list.or(panel)
+}
+
+#[cfg(test)]
+mod panel_route_tests {
+    use std::sync::Arc;
+
+    use super::panel_routes;
+    use crate::panels::PanelsConfig;
+
+    #[tokio::test]
+    async fn load_panel() {
+        let panels: PanelsConfig = serde_json::from_str(
+            r#"{ "panels": [
+                { "name": "gear", "rows": [{ "controls": [{ "type": "gear" }] }] }
+            ] }"#,
+        )
+        .unwrap();
+        let routes = panel_routes(Arc::new(panels));
+
+        let list = warp::test::request().path("/panels").reply(&routes).await;
+        assert_eq!(list.body(), r#"["gear"]"#);
+
+        let panel = warp::test::request()
+            .path("/panels/gear")
+            .reply(&routes)
+            .await;
+        assert_eq!(panel.status(), 200);
+        assert_eq!(
+            panel.body(),
+            r#"{"name":"gear","rows":[{"controls":[{"type":"gear"}]}]}"#
+        );
+
+        let unknown = warp::test::request()
+            .path("/panels/radios")
+            .reply(&routes)
+            .await;
+        assert_eq!(unknown.status(), 404);
+    }
+}
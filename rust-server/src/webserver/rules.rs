@@ -0,0 +1,43 @@
+use std::convert::Infallible;
+
+use futures_util::{stream, Stream};
+use tokio::sync::broadcast::error::RecvError;
+use warp::{filters::sse::Event, Filter, Rejection, Reply};
+
+use crate::rules::RuleEvents;
+
+/// Routes for the active rules, and for streaming the notifications of
+/// the rules with server-sent events.
+pub fn rule_routes(
+    events: RuleEvents,
+) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone {
+    let active = {
+        let events = events.clone();
+        warp::path!("rules" / "active")
+            .and(warp::get())
+            .map(move || warp::reply::json(&*events.active().borrow()))
+    };
+
+    let notifications = warp::path!("notifications").and(warp::get()).map(move || {
+        warp::sse::reply(warp::sse::keep_alive().stream(notification_events(&events)))
+    });
+
+    active.or(notifications)
+}
+
+fn notification_events(events: &RuleEvents) -> impl Stream<Item = Result<Event, Infallible>> {
+    stream::unfold(events.notifications(), |mut notifications| async move {
+        loop {
+            match notifications.recv().await {
+                Ok(notification) => {
+                    let event = Event::default()
+                        .event("notification")
+                        .data(serde_json::to_string(&notification).unwrap());
+                    return Some((Ok(event), notifications));
+                }
+                Err(RecvError::Lagged(_)) => continue,
+                Err(RecvError::Closed) => return None,
+            }
+        }
+    })
+}
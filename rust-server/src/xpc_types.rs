@@ -1,6 +1,12 @@
 use serde::{Deserialize, Serialize};
 
-#[derive(Default, Debug, Clone, Serialize)]
+use crate::{
+    autopilot::{Autopilot, AutopilotCommand},
+    radios::{RadioCommand, Radios},
+    systems::Systems,
+};
+
+#[derive(Default, Debug, Clone, PartialEq, Serialize)]
 pub struct ReceivedDatarefs {
     #[serde(rename = "has-retracting-gear")]
     pub has_retracting_gear: bool,
@@ -92,9 +98,91 @@ pub struct ReceivedDatarefs {
     pub lat: Option<f32>,
 
     pub lon: Option<f32>,
+
+    #[serde(rename = "aircraft-icao")]
+    pub aircraft_icao: String,
+
+    #[serde(rename = "acf-path")]
+    pub acf_path: String,
+
+    /// The profile of the aircraft, set once its strings have settled
+    #[serde(rename = "aircraft-profile")]
+    pub aircraft_profile: Option<String>,
+
+    pub radios: Radios,
+
+    pub autopilot: Autopilot,
+
+    pub systems: Systems,
+}
+
+impl ReceivedDatarefs {
+    /// The JSON sent to the web clients, to look up several keys without
+    /// serializing the datarefs for each of them.
+    pub fn values(&self) -> DatarefValues {
+        DatarefValues(serde_json::to_value(self).unwrap_or_default())
+    }
+
+    /// Returns true if `key` is in the JSON sent to the web clients.
+    pub fn has_key(&self, key: &str) -> bool {
+        self.values().has_key(key)
+    }
+
+    /// Returns true if `key` is in the JSON sent to the web clients with
+    /// a boolean, a number or null, rather than an object or a string.
+    pub fn has_value_key(&self, key: &str) -> bool {
+        self.values().has_value_key(key)
+    }
+
+    /// Returns the value of `key` in the JSON sent to the web clients,
+    /// with the booleans as 0 and 1.
+    pub fn value_of(&self, key: &str) -> Option<f64> {
+        self.values().value_of(key)
+    }
+}
+
+/// The received datarefs as the JSON sent to the web clients.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DatarefValues(serde_json::Value);
+
+impl DatarefValues {
+    pub fn has_key(&self, key: &str) -> bool {
+        self.0.get(key).is_some()
+    }
+
+    pub fn has_value_key(&self, key: &str) -> bool {
+        matches!(
+            self.0.get(key),
+            Some(
+                serde_json::Value::Bool(_) | serde_json::Value::Number(_) | serde_json::Value::Null
+            )
+        )
+    }
+
+    pub fn value_of(&self, key: &str) -> Option<f64> {
+        match self.0.get(key)? {
+            serde_json::Value::Bool(b) => Some(if *b { 1.0 } else { 0.0 }),
+            serde_json::Value::Number(n) => n.as_f64(),
+            _ => None,
+        }
+    }
+}
+
+/// A command from the web UI or the hardware inputs: either an X-Plane
+/// command or a new value for a dataref.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum UICommand {
+    Command { command: String },
+    SetDataref { dataref: String, value: f32 },
+    Radio(RadioCommand),
+    Autopilot(AutopilotCommand),
 }
 
-#[derive(Default, Debug, Clone, Serialize, Deserialize)]
-pub struct UICommand {
-    pub command: String,
+impl UICommand {
+    pub fn command(command: &str) -> UICommand {
+        UICommand::Command {
+            command: command.to_string(),
+        }
+    }
 }
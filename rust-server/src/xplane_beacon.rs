@@ -39,12 +39,11 @@ pub async fn receive_xplane_beacon(channels: ChannelsController) -> io::Result<(
                         beacon.computer_name,
                         beacon.version_number
                     );
-                    channels
-                        .send_control(ControlMessages::XPlaneAddr {
-                            addr,
-                            port: beacon.port,
-                        })
-                        .await;
+                    channels.send_control(ControlMessages::XPlaneAddr {
+                        addr,
+                        port: beacon.port,
+                        computer_name: beacon.computer_name.to_string(),
+                    });
                 }
             }
         }
@@ -130,9 +129,9 @@ mod xplane_beacon_tests {
     fn test_create_and_parse_beacon_data() {
         let mut writer = Cursor::new(Vec::new());
         testbeacon().write(&mut writer).unwrap();
-        let mut bytes = writer.get_mut();
+        let bytes = writer.get_mut();
 
-        let r = decode_xplane_beacon_input(&mut bytes).unwrap();
+        let r = decode_xplane_beacon_input(bytes).unwrap();
         assert_eq!(r.port, 49000);
         assert_eq!(r.computer_name.to_string(), "Just testing");
     }
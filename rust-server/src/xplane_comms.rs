@@ -1,19 +1,40 @@
 pub use crate::xpc_types::ReceivedDatarefs;
 use crate::{
-    channels::ChannelsXPlaneCommEndpoint, control_msgs::ControlMessages, xpc_types::UICommand,
+    autopilot::{AutopilotSource, FlightDirectorMode, ModeState},
+    channels::ChannelsXPlaneCommEndpoint,
+    connections::ConnectionConfig,
+    control_msgs::ControlMessages,
+    macros::Macros,
+    metrics::METRICS,
+    profiles::AircraftProfile,
+    profiles::AircraftProfiles,
+    radios::TransponderMode,
+    systems::{SystemCounts, SystemValues},
+    xpc_types::UICommand,
 };
 use binrw::{binrw, io::Cursor, BinReaderExt, BinResult, BinWrite, NullString};
 use log::{debug, error, info};
 use std::{
-    cmp::min, io::{self}, net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4}, sync::Arc, time::Duration
+    cmp::min,
+    io::{self},
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    sync::Arc,
+    time::Duration,
 };
 use tokio::{
     net::UdpSocket,
-    time::{interval, sleep},
+    sync::mpsc,
+    task::JoinHandle,
+    time::{interval, sleep, Instant},
 };
 
-pub async fn run_xplane_udp(port: u16, channels: ChannelsXPlaneCommEndpoint) -> io::Result<()> {
-    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, port);
+pub async fn run_xplane_udp(
+    connection: &ConnectionConfig,
+    channels: &mut ChannelsXPlaneCommEndpoint,
+    profiles: &AircraftProfiles,
+    macros: &Macros,
+) -> io::Result<()> {
+    let addr = SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, connection.udp_port);
     let sock = UdpSocket::bind(addr).await?;
 
     let receive = Arc::new(sock);
@@ -24,30 +45,73 @@ pub async fn run_xplane_udp(port: u16, channels: ChannelsXPlaneCommEndpoint) ->
     let mut dataref_cache = ReceivedDatarefs {
         ..Default::default()
     };
+    let mut string_bytes = StringBytes::new(profiles.use_acf_file());
+    let mut system_values = SystemValues::default();
+    let mut aircraft = AircraftStrings::default();
+    let mut profile: Option<AircraftProfile> = None;
+    let mut requests = DatarefRequests::default();
 
     let ChannelsXPlaneCommEndpoint {
-        mut control,
+        control,
         datarefs,
-        mut ui_cmds,
+        ui_cmds,
+        last_packet,
     } = channels;
 
+    let (macro_cmds_tx, mut macro_cmds) = mpsc::channel::<UICommand>(20);
+
     let mut dataref_timer = interval(Duration::from_secs(30));
 
-    let mut xp_addr: Option<SocketAddr> = None;
+    let mut xp_addr: Option<SocketAddr> = connection.xplane_address;
 
     loop {
         tokio::select! {
             Ok((len, _)) = receive.recv_from(&mut buf) => {
-                handle_input(&mut buf[..len], &mut dataref_cache).await;
-                datarefs.send(dataref_cache.clone()).await.ok();
+                last_packet.send_replace(Some(Instant::now().into_std()));
+                handle_input(&connection.name, &mut buf[..len], &mut dataref_cache, &mut string_bytes, &mut system_values).await;
+                let settled = aircraft.settled(&dataref_cache, Instant::now()) && string_bytes.complete();
+                let found = profiles.find(&dataref_cache);
+                let wanted = subscription(&string_bytes, &system_values);
+                let mut restart = wanted != *requests.subscription();
+                if settled && found.map(|p| &p.name) != profile.as_ref().map(|p| &p.name) {
+                    info!(
+                        "Aircraft {} loaded in connection {}, using profile {}",
+                        dataref_cache.aircraft_icao,
+                        connection.name,
+                        found.map_or("default", |p| &p.name)
+                    );
+                    profile = found.cloned();
+                    dataref_cache.aircraft_profile = profile.as_ref().map(|p| p.name.clone());
+                    restart = true;
+                }
+                if let Some(addr) = xp_addr.filter(|_| restart) {
+                    requests.start(send.clone(), addr, profile.as_ref(), wanted);
+                }
+                dataref_cache.systems =
+                    system_values.systems(profile.as_ref().map(|p| p.units).unwrap_or_default());
+                datarefs.send_if_modified(|current| {
+                    if *current != dataref_cache {
+                        *current = dataref_cache.clone();
+                        true
+                    } else {
+                        false
+                    }
+                });
             },
             ctrlmsg = control.recv() => {
                 match ctrlmsg {
-                    Some(ControlMessages::XPlaneAddr { addr, port }) => {
+                    Some(ControlMessages::XPlaneAddr { addr, port, computer_name }) => {
+                        if !connection.accepts_beacon_from(&computer_name) {
+                            continue;
+                        }
                         if let Some(new_addr) = update_xp_addr(&xp_addr, addr, port) {
-                            info!("Got new XPlane address {:?}", new_addr.to_string());
+                            info!(
+                                "Got new XPlane address {:?} for connection {}",
+                                new_addr.to_string(),
+                                connection.name
+                            );
                             xp_addr = Some(new_addr);
-                            request_datarefs(send.clone(), &new_addr).await;
+                            requests.start(send.clone(), new_addr, profile.as_ref(), subscription(&string_bytes, &system_values));
                         }
                     },
                     None => { info!("Got nothing from control socket"); },
@@ -55,6 +119,15 @@ pub async fn run_xplane_udp(port: u16, channels: ChannelsXPlaneCommEndpoint) ->
             },
             Some(cmd) = ui_cmds.recv () => {
                 debug!("Received command from UI: {:?}", cmd);
+                if macros.start(&connection.name, &cmd, datarefs.subscribe(), macro_cmds_tx.clone()) {
+                    continue;
+                }
+                if let Some(addr) = xp_addr {
+                    send_cmd(send.clone(), &addr, cmd).await;
+                }
+            },
+            Some(cmd) = macro_cmds.recv() => {
+                debug!("Received command from macro: {:?}", cmd);
                 if let Some(addr) = xp_addr {
                     send_cmd(send.clone(), &addr, cmd).await;
                 }
@@ -62,7 +135,7 @@ pub async fn run_xplane_udp(port: u16, channels: ChannelsXPlaneCommEndpoint) ->
             _ = dataref_timer.tick() =>  {
                 debug!("Dataref timer.");
                 if let Some(addr) = xp_addr {
-                    request_datarefs(send.clone(), &addr).await;
+                    requests.start(send.clone(), addr, profile.as_ref(), subscription(&string_bytes, &system_values));
                 }
             }
         }
@@ -80,41 +153,191 @@ fn update_xp_addr(prev_addr: &Option<SocketAddr>, addr: IpAddr, port: u16) -> Op
     Some(new_addr)
 }
 
-async fn request_datarefs(sock: Arc<UdpSocket>, xp_addr: &SocketAddr) {
-    let ac = xp_addr.clone();
-    tokio::spawn(async move {
-        info!("Requesting datarefs from {}", ac);
-        for (i, dref_identity) in RREF_IDENTITIES.iter().enumerate() {
-            let index = (i + 1) as u32;
-            let request = RrefRequest {
-                freq: 3,
-                index,
-                name: dref_identity.name.into(),
-            };
+/// The task sending the RREF requests of a connection. A new pass
+/// replaces the one in progress. The string datarefs don't change with
+/// the profile, so their bytes are only requested once from each
+/// address.
+#[derive(Default)]
+struct DatarefRequests {
+    /// The pass in progress, and what was requested before it
+    task: Option<(JoinHandle<()>, Subscription)>,
+    addr: Option<SocketAddr>,
+    /// What has been requested from `addr`
+    subscription: Subscription,
+}
 
-            let mut writer = Cursor::new(Vec::new());
-            request.write(&mut writer).unwrap();
-            let bytes = writer.into_inner();
+/// The string bytes and system array elements requested from X-Plane.
+#[derive(Debug, Default, Clone, PartialEq)]
+struct Subscription {
+    /// The number of bytes requested of each string
+    string_lens: Vec<usize>,
+    /// The counts that the system arrays are requested for
+    counts: SystemCounts,
+}
 
-            assert_eq!(bytes.len(), 413);
+/// What to request for the received strings and system counts.
+fn subscription(string_bytes: &StringBytes, system_values: &SystemValues) -> Subscription {
+    Subscription {
+        string_lens: string_bytes.lens(),
+        counts: system_values.counts(),
+    }
+}
 
-            send_to_xp(sock.clone(), &ac, bytes).await;
-            sleep(Duration::from_millis(20)).await;
+impl DatarefRequests {
+    fn start(
+        &mut self,
+        sock: Arc<UdpSocket>,
+        xp_addr: SocketAddr,
+        profile: Option<&AircraftProfile>,
+        subscription: Subscription,
+    ) {
+        if let Some((task, requested)) = self.task.take() {
+            if !task.is_finished() {
+                task.abort();
+                // Some of the system elements may be left requested
+                self.subscription = Subscription {
+                    counts: requested.counts.max(&self.subscription.counts),
+                    ..requested
+                };
+            }
         }
+
+        let requested = match self.addr {
+            Some(addr) if addr == xp_addr => self.subscription.clone(),
+            _ => Subscription::default(),
+        };
+        let requests = rref_requests(profile, &subscription, &requested);
+
+        let task = tokio::spawn(async move {
+            info!("Requesting {} datarefs from {}", requests.len(), xp_addr);
+            for request in requests {
+                let mut writer = Cursor::new(Vec::new());
+                request.write(&mut writer).unwrap();
+                let bytes = writer.into_inner();
+
+                if bytes.len() != RREF_REQUEST_LEN {
+                    error!(
+                        "Not requesting dataref {}, the name is too long",
+                        request.name
+                    );
+                    continue;
+                }
+
+                send_to_xp(sock.clone(), &xp_addr, bytes).await;
+                sleep(Duration::from_millis(20)).await;
+            }
+        });
+        self.task = Some((task, requested));
+        self.addr = Some(xp_addr);
+        self.subscription = subscription;
+    }
+
+    /// What has been requested from the address.
+    fn subscription(&self) -> &Subscription {
+        &self.subscription
+    }
+}
+
+/// The length of an RREF request message.
+const RREF_REQUEST_LEN: usize = 413;
+
+/// The longest dataref name that fits in an RREF request.
+pub const MAX_RREF_NAME_LEN: usize = 399;
+
+/// The RREF requests for the datarefs, with the datarefs replaced by
+/// those of the profile. The system datarefs are requested one array
+/// element at a time after the others, followed by the string bytes
+/// that have not been `requested` yet. The system elements that are
+/// not used any more are stopped.
+fn rref_requests(
+    profile: Option<&AircraftProfile>,
+    subscription: &Subscription,
+    requested: &Subscription,
+) -> Vec<RrefRequest> {
+    let values = RREF_IDENTITIES.iter().enumerate().map(|(i, identity)| {
+        let name = profile.map_or(identity.name, |p| p.dataref(identity.name));
+        (3, i + 1, name.to_string())
     });
+    let systems = subscription
+        .counts
+        .requests(&requested.counts)
+        .into_iter()
+        .map(|(freq, offset, name)| (freq, RREF_IDENTITIES.len() + 1 + offset, name));
+
+    let first_byte = RREF_IDENTITIES.len() + SystemValues::len() + 1;
+    let bytes = STRING_IDENTITIES
+        .iter()
+        .zip(&subscription.string_lens)
+        .enumerate()
+        .scan(first_byte, |first, (i, (identity, len))| {
+            let index = *first;
+            *first += identity.len;
+            let from = requested.string_lens.get(i).copied().unwrap_or_default();
+            Some(
+                (from..*len)
+                    .map(move |i| (STRING_FREQ, index + i, format!("{}[{}]", identity.name, i))),
+            )
+        })
+        .flatten();
+
+    values
+        .chain(systems)
+        .chain(bytes)
+        .map(|(freq, index, name)| RrefRequest {
+            freq,
+            index: index as u32,
+            name: name.into(),
+        })
+        .collect()
+}
+
+/// Returns true if `name` is one of the datarefs read from X-Plane.
+pub fn is_known_dataref(name: &str) -> bool {
+    RREF_IDENTITIES.iter().any(|identity| identity.name == name)
 }
 
 async fn send_cmd(sock: Arc<UdpSocket>, xp_addr: &SocketAddr, command: UICommand) {
-    let xp_cmd = XPlaneCmd {
-        command: command.command.into(),
+    let commands = match &command {
+        UICommand::Radio(radio) => radio.xplane_commands(),
+        UICommand::Autopilot(autopilot) => autopilot.xplane_commands(),
+        _ => Ok(vec![command.clone()]),
+    };
+    let commands = match commands {
+        Ok(commands) => commands,
+        Err(e) => {
+            error!("Ignoring command {:?}: {}", command, e);
+            return;
+        }
     };
-    let mut writer = Cursor::new(Vec::new());
-    xp_cmd.write(&mut writer).unwrap();
-    let bytes = writer.into_inner();
 
-    debug!("Sending command {:#?} to XPlane at {}", xp_cmd, xp_addr);
+    for command in commands {
+        let mut writer = Cursor::new(Vec::new());
+        write_cmd(&mut writer, xp_addr, command);
+        send_to_xp(sock.clone(), xp_addr, writer.into_inner()).await;
+    }
+}
 
-    send_to_xp(sock, xp_addr, bytes).await;
+fn write_cmd(writer: &mut Cursor<Vec<u8>>, xp_addr: &SocketAddr, command: UICommand) {
+    match command {
+        UICommand::Command { command } => {
+            let xp_cmd = XPlaneCmd {
+                command: command.into(),
+            };
+            xp_cmd.write(writer).unwrap();
+            debug!("Sending command {:#?} to XPlane at {}", xp_cmd, xp_addr);
+        }
+        UICommand::SetDataref { dataref, value } => {
+            let xp_dref = XPlaneDref {
+                value,
+                dataref: dataref.into(),
+            };
+            xp_dref.write(writer).unwrap();
+            debug!("Sending dataref {:#?} to XPlane at {}", xp_dref, xp_addr);
+        }
+        UICommand::Radio(_) | UICommand::Autopilot(_) => {
+            error!("Command {:?} was not translated", command)
+        }
+    }
 }
 
 async fn send_to_xp(sock: Arc<UdpSocket>, xp_addr: &SocketAddr, bytes: Vec<u8>) {
@@ -150,12 +373,37 @@ enum RrefIdentifier {
     AutopilotBackCourseMode,
     YawDamper,
     ParkingBrakeRatio,
-    IAS,
-    TAS,
+    Ias,
+    Tas,
     MagHeading,
     Altitude,
     Lat,
     Lon,
+    Com1Frequency,
+    Com1StandbyFrequency,
+    Com2Frequency,
+    Com2StandbyFrequency,
+    Nav1Frequency,
+    Nav1StandbyFrequency,
+    Nav2Frequency,
+    Nav2StandbyFrequency,
+    Adf1Frequency,
+    Adf1StandbyFrequency,
+    Nav1Obs,
+    Nav2Obs,
+    TransponderCode,
+    TransponderMode,
+    AutopilotHeadingDial,
+    AutopilotAltitudeDial,
+    AutopilotVviDial,
+    AutopilotAirspeedDial,
+    AutopilotAirspeedIsMach,
+    AutopilotCourse,
+    AutopilotSource,
+    AutopilotNavStatus,
+    AutopilotApproachStatus,
+    AutopilotGlideslopeStatus,
+    AutopilotVviStatus,
 }
 
 struct DatarefIdentity {
@@ -163,7 +411,7 @@ struct DatarefIdentity {
     name: &'static str,
 }
 
-static RREF_IDENTITIES: &'static [DatarefIdentity] = &[
+static RREF_IDENTITIES: &[DatarefIdentity] = &[
     DatarefIdentity {
         id: RrefIdentifier::HasRetractingGear,
         name: "sim/aircraft/gear/acf_gear_retract",
@@ -265,11 +513,11 @@ static RREF_IDENTITIES: &'static [DatarefIdentity] = &[
         name: "sim/cockpit2/controls/parking_brake_ratio",
     },
     DatarefIdentity {
-        id: RrefIdentifier::IAS,
+        id: RrefIdentifier::Ias,
         name: "sim/cockpit2/gauges/indicators/airspeed_kts_pilot",
     },
     DatarefIdentity {
-        id: RrefIdentifier::TAS,
+        id: RrefIdentifier::Tas,
         name: "sim/cockpit2/gauges/indicators/ground_speed_kt",
     },
     DatarefIdentity {
@@ -288,8 +536,242 @@ static RREF_IDENTITIES: &'static [DatarefIdentity] = &[
         id: RrefIdentifier::Lon,
         name: "sim/flightmodel/position/longitude",
     },
+    DatarefIdentity {
+        id: RrefIdentifier::Com1Frequency,
+        name: "sim/cockpit2/radios/actuators/com1_frequency_hz_833",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Com1StandbyFrequency,
+        name: "sim/cockpit2/radios/actuators/com1_standby_frequency_hz_833",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Com2Frequency,
+        name: "sim/cockpit2/radios/actuators/com2_frequency_hz_833",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Com2StandbyFrequency,
+        name: "sim/cockpit2/radios/actuators/com2_standby_frequency_hz_833",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Nav1Frequency,
+        name: "sim/cockpit2/radios/actuators/nav1_frequency_hz",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Nav1StandbyFrequency,
+        name: "sim/cockpit2/radios/actuators/nav1_standby_frequency_hz",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Nav2Frequency,
+        name: "sim/cockpit2/radios/actuators/nav2_frequency_hz",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Nav2StandbyFrequency,
+        name: "sim/cockpit2/radios/actuators/nav2_standby_frequency_hz",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Adf1Frequency,
+        name: "sim/cockpit2/radios/actuators/adf1_frequency_hz",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Adf1StandbyFrequency,
+        name: "sim/cockpit2/radios/actuators/adf1_standby_frequency_hz",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Nav1Obs,
+        name: "sim/cockpit2/radios/actuators/nav1_obs_deg_mag_pilot",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::Nav2Obs,
+        name: "sim/cockpit2/radios/actuators/nav2_obs_deg_mag_pilot",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::TransponderCode,
+        name: "sim/cockpit2/radios/actuators/transponder_code",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::TransponderMode,
+        name: "sim/cockpit2/radios/actuators/transponder_mode",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotHeadingDial,
+        name: "sim/cockpit2/autopilot/heading_dial_deg_mag_pilot",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotAltitudeDial,
+        name: "sim/cockpit2/autopilot/altitude_dial_ft",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotVviDial,
+        name: "sim/cockpit2/autopilot/vvi_dial_fpm",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotAirspeedDial,
+        name: "sim/cockpit2/autopilot/airspeed_dial_kts_mach",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotAirspeedIsMach,
+        name: "sim/cockpit2/autopilot/airspeed_is_mach",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotCourse,
+        name: "sim/cockpit2/radios/actuators/hsi_obs_deg_mag_pilot",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotSource,
+        name: "sim/cockpit2/autopilot/autopilot_source",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotNavStatus,
+        name: "sim/cockpit2/autopilot/nav_status",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotApproachStatus,
+        name: "sim/cockpit2/autopilot/approach_status",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotGlideslopeStatus,
+        name: "sim/cockpit2/autopilot/glideslope_status",
+    },
+    DatarefIdentity {
+        id: RrefIdentifier::AutopilotVviStatus,
+        name: "sim/cockpit2/autopilot/vvi_status",
+    },
+];
+
+enum StringIdentifier {
+    AircraftIcao,
+    AcfPath,
+}
+
+/// A string dataref. X-Plane sends its bytes as separate values.
+struct StringIdentity {
+    id: StringIdentifier,
+    name: &'static str,
+    len: usize,
+}
+
+static STRING_IDENTITIES: &[StringIdentity] = &[
+    StringIdentity {
+        id: StringIdentifier::AircraftIcao,
+        name: "sim/aircraft/view/acf_ICAO",
+        len: 8,
+    },
+    StringIdentity {
+        id: StringIdentifier::AcfPath,
+        name: "sim/aircraft/view/acf_relative_path",
+        len: 256,
+    },
 ];
 
+/// The string datarefs are sent this many times per second.
+const STRING_FREQ: u32 = 1;
+
+/// The bytes of the aircraft path are requested this many at a time.
+const PATH_CHUNK: usize = 64;
+
+/// The received bytes of the string datarefs, and the number of bytes
+/// requested of each.
+struct StringBytes {
+    strings: Vec<RequestedString>,
+}
+
+#[derive(Debug)]
+struct RequestedString {
+    bytes: Vec<u8>,
+    len: usize,
+    /// The first of the more bytes requested, until it has arrived
+    waiting_for: Option<usize>,
+}
+
+impl StringBytes {
+    /// The aircraft path is only requested `with_path`, as it is needed
+    /// just for matching the `.acf` files of the profiles.
+    fn new(with_path: bool) -> StringBytes {
+        let strings = STRING_IDENTITIES
+            .iter()
+            .map(|identity| RequestedString {
+                bytes: vec![0; identity.len],
+                len: match identity.id {
+                    StringIdentifier::AcfPath if with_path => PATH_CHUNK,
+                    StringIdentifier::AcfPath => 0,
+                    StringIdentifier::AircraftIcao => identity.len,
+                },
+                waiting_for: None,
+            })
+            .collect();
+        StringBytes { strings }
+    }
+
+    /// The number of bytes to request of each string.
+    fn lens(&self) -> Vec<usize> {
+        self.strings.iter().map(|s| s.len).collect()
+    }
+
+    /// Returns false while a string fills all of its requested bytes, or
+    /// more of its bytes have not arrived yet.
+    fn complete(&self) -> bool {
+        STRING_IDENTITIES
+            .iter()
+            .zip(&self.strings)
+            .all(|(identity, s)| {
+                s.waiting_for.is_none() && (s.len == identity.len || s.bytes[..s.len].contains(&0))
+            })
+    }
+
+    /// Sets byte `offset` of all the string bytes, and returns the
+    /// identity and the new value of the string it belongs to. Requests
+    /// the next bytes of a string that fills its requested bytes.
+    fn set(&mut self, offset: usize, value: f32) -> Option<(&StringIdentity, String)> {
+        let mut offset = offset;
+        for (identity, s) in STRING_IDENTITIES.iter().zip(self.strings.iter_mut()) {
+            if offset < identity.len {
+                s.bytes[offset] = value as u8;
+                if s.waiting_for.is_some_and(|first| offset >= first) {
+                    s.waiting_for = None;
+                }
+                let end = s
+                    .bytes
+                    .iter()
+                    .position(|b| *b == 0)
+                    .unwrap_or(s.bytes.len());
+                if end >= s.len && s.len > 0 && s.len < identity.len {
+                    s.waiting_for = Some(s.len);
+                    s.len = min(s.len + PATH_CHUNK, identity.len);
+                }
+                return Some((
+                    identity,
+                    String::from_utf8_lossy(&s.bytes[..end]).into_owned(),
+                ));
+            }
+            offset -= identity.len;
+        }
+        None
+    }
+}
+
+/// Follows the aircraft ICAO and path, which arrive a few bytes at a
+/// time, so that the profile is only switched with complete strings.
+#[derive(Debug, Default)]
+struct AircraftStrings {
+    strings: (String, String),
+    since: Option<Instant>,
+}
+
+impl AircraftStrings {
+    /// Returns true when the strings have not changed for a whole update
+    /// of the string datarefs.
+    fn settled(&mut self, datarefs: &ReceivedDatarefs, now: Instant) -> bool {
+        let strings = (datarefs.aircraft_icao.clone(), datarefs.acf_path.clone());
+        if strings != self.strings || self.since.is_none() {
+            self.strings = strings;
+            self.since = Some(now);
+        }
+
+        self.since
+            .is_some_and(|since| now - since > Duration::from_secs(1) / STRING_FREQ)
+    }
+}
+
 #[derive(Debug)]
 #[binrw]
 #[br(little)]
@@ -331,6 +813,15 @@ struct XPlaneCmd {
     command: NullString,
 }
 
+#[derive(Debug)]
+#[binrw]
+#[brw(little, magic = b"DREF\0")]
+struct XPlaneDref {
+    value: f32,
+    #[brw(pad_size_to = 500)]
+    dataref: NullString,
+}
+
 #[binrw::parser(reader)]
 fn parse_drefvalues() -> BinResult<Vec<DatarefValue>> {
     let mut datarefs = Vec::new();
@@ -346,9 +837,18 @@ fn parse_drefvalues() -> BinResult<Vec<DatarefValue>> {
     Ok(datarefs)
 }
 
-async fn handle_input(buf: &mut [u8], dataref_cache: &mut ReceivedDatarefs) {
+async fn handle_input(
+    connection: &str,
+    buf: &mut [u8],
+    dataref_cache: &mut ReceivedDatarefs,
+    string_bytes: &mut StringBytes,
+    system_values: &mut SystemValues,
+) {
     debug!("Content: {:?}", buf);
 
+    let len = buf.len() as u64;
+    METRICS.xplane_packet_received(connection, message_type(buf), buf.len());
+
     let mut reader = Cursor::new(buf);
 
     loop {
@@ -359,26 +859,66 @@ async fn handle_input(buf: &mut [u8], dataref_cache: &mut ReceivedDatarefs) {
             }
             Ok(IncomingMsg::RrefMsg { values }) => {
                 debug!("Dataref values {:?}", values);
-                handle_datarefs(&values, dataref_cache);
+                handle_datarefs(&values, dataref_cache, string_bytes, system_values);
+            }
+            Err(e) => {
+                if reader.position() < len {
+                    debug!("Parsing message from XPlane failed: {:?}", e);
+                    METRICS.parse_failures.inc();
+                }
+                break;
             }
-            Err(_) => break,
         }
     }
 
     debug!("Dataref cache: {:?}", dataref_cache);
 }
 
-fn handle_datarefs(values: &Vec<DatarefValue>, dataref_cache: &mut ReceivedDatarefs) {
+fn message_type(buf: &[u8]) -> &'static str {
+    match buf.get(..4) {
+        Some(b"RREF") => "RREF",
+        Some(b"DATA") => "DATA",
+        _ => "other",
+    }
+}
+
+fn handle_datarefs(
+    values: &Vec<DatarefValue>,
+    dataref_cache: &mut ReceivedDatarefs,
+    string_bytes: &mut StringBytes,
+    system_values: &mut SystemValues,
+) {
     for v in values {
-        handle_dataref(v.id, v.value, dataref_cache);
+        handle_dataref(v.id, v.value, dataref_cache, string_bytes, system_values);
     }
 }
 
-fn handle_dataref(id: u32, value: f32, datarefs: &mut ReceivedDatarefs) {
+fn handle_dataref(
+    id: u32,
+    value: f32,
+    datarefs: &mut ReceivedDatarefs,
+    string_bytes: &mut StringBytes,
+    system_values: &mut SystemValues,
+) {
     debug!("Got dataref {} = {:?}", id, value);
 
+    if id > RREF_IDENTITIES.len() as u32 {
+        let offset = (id as usize) - RREF_IDENTITIES.len() - 1;
+        if system_values.set(offset, value) {
+            return;
+        }
+        if let Some((identity, s)) = string_bytes.set(offset - SystemValues::len(), value) {
+            match identity.id {
+                StringIdentifier::AircraftIcao => datarefs.aircraft_icao = s,
+                StringIdentifier::AcfPath => datarefs.acf_path = s,
+            }
+            return;
+        }
+    }
+
     if id == 0 || id > RREF_IDENTITIES.len() as u32 {
         error!("Got dataref with id outside the known values: {}", id);
+        METRICS.unknown_dataref_ids.inc();
         return;
     }
 
@@ -405,22 +945,73 @@ fn handle_dataref(id: u32, value: f32, datarefs: &mut ReceivedDatarefs) {
         RrefIdentifier::WindowHeat => datarefs.window_heat = boolv(value),
         RrefIdentifier::AutopilotFlightDirectorMode => parse_flight_director_mode(datarefs, value),
         RrefIdentifier::AutopilotHeadingMode => datarefs.autopilot_heading_mode = boolv(value),
-        RrefIdentifier::AutopilotAltHoldMode => datarefs.autopilot_alt_hold_mode = boolv(value),
+        RrefIdentifier::AutopilotAltHoldMode => {
+            datarefs.autopilot_alt_hold_mode = boolv(value);
+            datarefs.autopilot.modes.alt = ModeState::from_dataref(value);
+        }
         RrefIdentifier::AutopilotApproachMode => datarefs.autopilot_approach_mode = boolv(value),
         RrefIdentifier::AutopilotNavMode => datarefs.autopilot_nav_mode = boolv(value),
         RrefIdentifier::AutopilotBackCourseMode => {
             datarefs.autopilot_back_course_mode = boolv(value)
         }
         RrefIdentifier::YawDamper => datarefs.yaw_damper = boolv(value),
-        RrefIdentifier::IAS => datarefs.ias = value,
-        RrefIdentifier::TAS => datarefs.tas = value,
+        RrefIdentifier::Ias => datarefs.ias = value,
+        RrefIdentifier::Tas => datarefs.tas = value,
         RrefIdentifier::MagHeading => datarefs.mag_heading = value,
         RrefIdentifier::Altitude => datarefs.altitude = value,
         RrefIdentifier::Lat => datarefs.lat = Some(value),
         RrefIdentifier::Lon => datarefs.lon = Some(value),
+        RrefIdentifier::Com1Frequency => datarefs.radios.com1.active_khz = khz(value, 1),
+        RrefIdentifier::Com1StandbyFrequency => datarefs.radios.com1.standby_khz = khz(value, 1),
+        RrefIdentifier::Com2Frequency => datarefs.radios.com2.active_khz = khz(value, 1),
+        RrefIdentifier::Com2StandbyFrequency => datarefs.radios.com2.standby_khz = khz(value, 1),
+        RrefIdentifier::Nav1Frequency => datarefs.radios.nav1.radio.active_khz = khz(value, 10),
+        RrefIdentifier::Nav1StandbyFrequency => {
+            datarefs.radios.nav1.radio.standby_khz = khz(value, 10)
+        }
+        RrefIdentifier::Nav2Frequency => datarefs.radios.nav2.radio.active_khz = khz(value, 10),
+        RrefIdentifier::Nav2StandbyFrequency => {
+            datarefs.radios.nav2.radio.standby_khz = khz(value, 10)
+        }
+        RrefIdentifier::Adf1Frequency => datarefs.radios.adf1.active_khz = khz(value, 1),
+        RrefIdentifier::Adf1StandbyFrequency => datarefs.radios.adf1.standby_khz = khz(value, 1),
+        RrefIdentifier::Nav1Obs => datarefs.radios.nav1.obs = value,
+        RrefIdentifier::Nav2Obs => datarefs.radios.nav2.obs = value,
+        RrefIdentifier::TransponderCode => datarefs.radios.transponder.code = value.round() as u32,
+        RrefIdentifier::TransponderMode => {
+            datarefs.radios.transponder.mode = TransponderMode::from_dataref(value)
+        }
+        RrefIdentifier::AutopilotHeadingDial => datarefs.autopilot.heading = value,
+        RrefIdentifier::AutopilotAltitudeDial => datarefs.autopilot.altitude = value,
+        RrefIdentifier::AutopilotVviDial => datarefs.autopilot.vertical_speed = value,
+        RrefIdentifier::AutopilotAirspeedDial => datarefs.autopilot.airspeed = value,
+        RrefIdentifier::AutopilotAirspeedIsMach => {
+            datarefs.autopilot.airspeed_is_mach = boolv(value)
+        }
+        RrefIdentifier::AutopilotCourse => datarefs.autopilot.course = value,
+        RrefIdentifier::AutopilotSource => {
+            datarefs.autopilot.source = AutopilotSource::from_dataref(value)
+        }
+        RrefIdentifier::AutopilotNavStatus => {
+            datarefs.autopilot.modes.nav = ModeState::from_dataref(value)
+        }
+        RrefIdentifier::AutopilotApproachStatus => {
+            datarefs.autopilot.modes.approach = ModeState::from_dataref(value)
+        }
+        RrefIdentifier::AutopilotGlideslopeStatus => {
+            datarefs.autopilot.modes.glideslope = ModeState::from_dataref(value)
+        }
+        RrefIdentifier::AutopilotVviStatus => {
+            datarefs.autopilot.modes.vs = ModeState::from_dataref(value)
+        }
     }
 }
 
+/// Converts a frequency dataref in units of `unit_khz` to kHz.
+fn khz(value: f32, unit_khz: u32) -> u32 {
+    value.round().max(0.0) as u32 * unit_khz
+}
+
 fn boolv(v: f32) -> bool {
     v != 0f32
 }
@@ -432,14 +1023,336 @@ fn boolv_with_limit(v: f32, limit: f32) -> bool {
 fn parse_flight_director_mode(datarefs: &mut ReceivedDatarefs, value: f32) {
     datarefs.autopilot_flight_director = value > 0f32;
     datarefs.autopilot_engaged = value >= 2f32;
+    datarefs.autopilot.flight_director = FlightDirectorMode::from_dataref(value);
 }
 
 fn parse_flap_position(datarefs: &mut ReceivedDatarefs, value: f32) {
     let slots = datarefs.flap_positions;
     if slots <= 0 {
-        return
+        return;
     }
 
     let position = (value * slots as f32).round() as i32;
     datarefs.current_flap_position = min(position, slots);
 }
+
+#[cfg(test)]
+mod xplane_comms_tests {
+    use binrw::{io::Cursor, BinWrite};
+
+    use std::time::Duration;
+
+    use tokio::time::Instant;
+
+    use super::{
+        handle_dataref, rref_requests, AircraftStrings, StringBytes, Subscription, XPlaneDref,
+        RREF_IDENTITIES,
+    };
+    use crate::{
+        autopilot::{FlightDirectorMode, ModeState},
+        profiles::AircraftProfile,
+        radios::TransponderMode,
+        systems::{SystemCounts, SystemValues},
+        xplane_comms::ReceivedDatarefs,
+    };
+
+    #[test]
+    fn dref_message() {
+        let dref = XPlaneDref {
+            value: 2.0,
+            dataref: "sim/cockpit2/engine/actuators/ignition_key[0]".into(),
+        };
+        let mut writer = Cursor::new(Vec::new());
+        dref.write(&mut writer).unwrap();
+        let bytes = writer.into_inner();
+
+        assert_eq!(bytes.len(), 509);
+        assert_eq!(&bytes[..5], b"DREF\0");
+        assert_eq!(&bytes[5..9], &2.0f32.to_le_bytes());
+        assert_eq!(&bytes[9..16], b"sim/coc");
+        assert_eq!(bytes[508], 0);
+    }
+
+    #[test]
+    fn profile_datarefs() {
+        let profile = AircraftProfile {
+            name: String::from("zibo"),
+            datarefs: [(
+                String::from("sim/cockpit2/switches/beacon_on"),
+                String::from("laminar/B738/toggle_switch/beacon"),
+            )]
+            .into(),
+            ..Default::default()
+        };
+        let name = |requests: &[super::RrefRequest], name: &str| {
+            requests.iter().position(|r| r.name.to_string() == name)
+        };
+
+        let none = Subscription::default();
+        let default = rref_requests(None, &none, &none);
+        let zibo = rref_requests(Some(&profile), &none, &none);
+        assert_eq!(default.len(), zibo.len());
+        assert!(name(&default, "sim/cockpit2/switches/beacon_on").is_some());
+        assert_eq!(
+            name(&zibo, "laminar/B738/toggle_switch/beacon"),
+            name(&default, "sim/cockpit2/switches/beacon_on")
+        );
+        assert!(name(&zibo, "sim/cockpit2/switches/beacon_on").is_none());
+    }
+
+    #[test]
+    fn string_requests() {
+        let none = Subscription::default();
+        let values = rref_requests(None, &none, &none);
+        let subscription = |string_lens: Vec<usize>| Subscription {
+            string_lens,
+            ..Default::default()
+        };
+        let requests = rref_requests(
+            None,
+            &subscription(vec![8, 128]),
+            &subscription(vec![0, 64]),
+        );
+        let strings = &requests[values.len()..];
+
+        assert_eq!(strings.len(), 8 + 64);
+        assert_eq!(strings[0].name.to_string(), "sim/aircraft/view/acf_ICAO[0]");
+        assert_eq!(
+            strings[8].name.to_string(),
+            "sim/aircraft/view/acf_relative_path[64]"
+        );
+        assert_eq!(strings[8].index, strings[0].index + 8 + 64);
+        assert!(values
+            .iter()
+            .all(|r| !r.name.to_string().starts_with("sim/aircraft/view/acf_")));
+    }
+
+    #[test]
+    fn string_datarefs() {
+        let mut datarefs = ReceivedDatarefs::default();
+        let mut bytes = StringBytes::new(true);
+        let mut systems = SystemValues::default();
+        let icao = (RREF_IDENTITIES.len() + SystemValues::len()) as u32 + 1;
+        let path = icao + 8;
+
+        for (i, b) in "B738".bytes().enumerate() {
+            handle_dataref(
+                icao + i as u32,
+                b as f32,
+                &mut datarefs,
+                &mut bytes,
+                &mut systems,
+            );
+        }
+        for (i, b) in "b738.acf".bytes().enumerate() {
+            handle_dataref(
+                path + i as u32,
+                b as f32,
+                &mut datarefs,
+                &mut bytes,
+                &mut systems,
+            );
+        }
+        assert_eq!(datarefs.aircraft_icao, "B738");
+        assert_eq!(datarefs.acf_path, "b738.acf");
+        assert!(bytes.complete());
+
+        handle_dataref(icao + 3, 0.0, &mut datarefs, &mut bytes, &mut systems);
+        assert_eq!(datarefs.aircraft_icao, "B73");
+    }
+
+    #[test]
+    fn longer_path_requested() {
+        let mut datarefs = ReceivedDatarefs::default();
+        let mut bytes = StringBytes::new(true);
+        let mut systems = SystemValues::default();
+        let path = (RREF_IDENTITIES.len() + SystemValues::len()) as u32 + 8 + 1;
+        assert_eq!(bytes.lens(), [8, 64]);
+
+        for i in 0..64 {
+            handle_dataref(
+                path + i,
+                b'a' as f32,
+                &mut datarefs,
+                &mut bytes,
+                &mut systems,
+            );
+        }
+        assert_eq!(bytes.lens(), [8, 128]);
+        assert!(!bytes.complete());
+
+        handle_dataref(
+            path + 64,
+            b'b' as f32,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        assert!(bytes.complete());
+        assert_eq!(datarefs.acf_path.len(), 65);
+
+        assert_eq!(StringBytes::new(false).lens(), [8, 0]);
+    }
+
+    #[test]
+    fn settled_aircraft_strings() {
+        let start = Instant::now();
+        let at = |ms: u64| start + Duration::from_millis(ms);
+        let mut aircraft = AircraftStrings::default();
+        let mut datarefs = ReceivedDatarefs::default();
+
+        assert!(!aircraft.settled(&datarefs, at(0)));
+        assert!(aircraft.settled(&datarefs, at(1100)));
+
+        datarefs.aircraft_icao = String::from("C172");
+        datarefs.acf_path = String::from("Aircraft/Laminar Research/b738.acf");
+        assert!(!aircraft.settled(&datarefs, at(1200)));
+        datarefs.acf_path = String::from("Aircraft/Laminar Research/c172.acf");
+        assert!(!aircraft.settled(&datarefs, at(2000)));
+        assert!(!aircraft.settled(&datarefs, at(2900)));
+        assert!(aircraft.settled(&datarefs, at(3100)));
+    }
+
+    #[test]
+    fn system_datarefs() {
+        let mut datarefs = ReceivedDatarefs::default();
+        let mut bytes = StringBytes::new(true);
+        let mut systems = SystemValues::default();
+        let twin = Subscription {
+            counts: SystemCounts {
+                engines: 2,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let requests = rref_requests(None, &twin, &Subscription::default());
+        let id = |name: &str| {
+            requests
+                .iter()
+                .find(|r| r.name.to_string() == name)
+                .unwrap()
+                .index
+        };
+
+        handle_dataref(
+            id("sim/aircraft/engine/acf_num_engines"),
+            2.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("sim/cockpit2/engine/indicators/N1_percent[1]"),
+            92.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        assert_eq!(datarefs.aircraft_icao, "");
+
+        let engines = systems.systems(Default::default()).engines;
+        assert_eq!(engines.len(), 2);
+        assert_eq!(engines[1].n1, 92.0);
+    }
+
+    #[test]
+    fn radio_datarefs() {
+        let mut datarefs = ReceivedDatarefs::default();
+        let mut bytes = StringBytes::new(true);
+        let mut systems = SystemValues::default();
+        let id = |name: &str| {
+            RREF_IDENTITIES
+                .iter()
+                .position(|identity| identity.name.ends_with(name))
+                .unwrap() as u32
+                + 1
+        };
+
+        handle_dataref(
+            id("com1_frequency_hz_833"),
+            121_500.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("nav2_standby_frequency_hz"),
+            11_390.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("transponder_code"),
+            7000.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("transponder_mode"),
+            3.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+
+        assert_eq!(datarefs.radios.com1.active_khz, 121_500);
+        assert_eq!(datarefs.radios.nav2.radio.standby_khz, 113_900);
+        assert_eq!(datarefs.radios.transponder.code, 7000);
+        assert_eq!(datarefs.radios.transponder.mode, TransponderMode::Alt);
+    }
+
+    #[test]
+    fn autopilot_datarefs() {
+        let mut datarefs = ReceivedDatarefs::default();
+        let mut bytes = StringBytes::new(true);
+        let mut systems = SystemValues::default();
+        let id = |name: &str| {
+            RREF_IDENTITIES
+                .iter()
+                .position(|identity| identity.name == format!("sim/cockpit2/autopilot/{}", name))
+                .unwrap() as u32
+                + 1
+        };
+
+        handle_dataref(
+            id("flight_director_mode"),
+            2.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("altitude_dial_ft"),
+            8000.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("nav_status"),
+            1.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+        handle_dataref(
+            id("altitude_hold_status"),
+            2.0,
+            &mut datarefs,
+            &mut bytes,
+            &mut systems,
+        );
+
+        assert!(datarefs.autopilot_engaged);
+        assert_eq!(
+            datarefs.autopilot.flight_director,
+            FlightDirectorMode::Autopilot
+        );
+        assert_eq!(datarefs.autopilot.altitude, 8000.0);
+        assert_eq!(datarefs.autopilot.modes.nav, ModeState::Armed);
+        assert!(datarefs.autopilot_alt_hold_mode);
+        assert_eq!(datarefs.autopilot.modes.alt, ModeState::Captured);
+    }
+}
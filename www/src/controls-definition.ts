@@ -36,8 +36,17 @@ export type CommandValue = (typeof commands)[CommandName]
 
 export type ButtonDefinition = {
     type: "button"
-    incomingDataKey: string
-    outgoingToggleCommand: CommandValue
+    incomingDataKey?: string
+    outgoingToggleCommand: string
+    text: string
+}
+
+export type EncoderDefinition = {
+    type: "encoder"
+    incomingDataKey?: string
+    rightCommand: string
+    leftCommand: string
+    pushCommand?: string
     text: string
 }
 
@@ -51,6 +60,7 @@ export type FlapsControlDefinition = {
 
 export type ControlDefinition =
     | ButtonDefinition
+    | EncoderDefinition
     | GearControlDefinition
     | FlapsControlDefinition
 
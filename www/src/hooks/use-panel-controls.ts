@@ -0,0 +1,33 @@
+import { useEffect, useState } from "react"
+import { ControlsDefinition, toggleButtons } from "../controls-definition"
+import { loadPanel, panelName } from "../panels"
+
+// The controls of the selected panel, or the built-in controls if no
+// panel is selected or it cannot be loaded
+export function usePanelControls(): ControlsDefinition {
+    const [controls, setControls] = useState<ControlsDefinition>(toggleButtons)
+
+    useEffect(() => {
+        const name = panelName()
+        if (!name) {
+            return
+        }
+
+        let cancelled = false
+        loadPanel(name)
+            .then((panel) => {
+                if (!cancelled) {
+                    setControls(panel)
+                }
+            })
+            .catch((e) => {
+                console.log("Using the built-in controls", e)
+            })
+
+        return () => {
+            cancelled = true
+        }
+    }, [])
+
+    return controls
+}
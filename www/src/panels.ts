@@ -0,0 +1,73 @@
+import Cookies from "js-cookie"
+import { ControlDefinition, ControlsDefinition } from "./controls-definition"
+
+const cookieName = "panel"
+
+// The panel of this tablet: ?panel=<name>, which is remembered, or the
+// panel remembered earlier
+export function panelName(): string | undefined {
+    const fromQuery = new URLSearchParams(location.search).get("panel")
+    if (fromQuery) {
+        Cookies.set(cookieName, fromQuery, { expires: 365 })
+        return fromQuery
+    }
+
+    return Cookies.get(cookieName) || undefined
+}
+
+type PanelControl =
+    | { type: "button"; text: string; data_key?: string; command: string }
+    | {
+          type: "encoder"
+          text: string
+          data_key?: string
+          cmd_right: string
+          cmd_left: string
+          cmd_push?: string
+      }
+    | { type: "gear" }
+    | { type: "flaps" }
+
+type Panel = {
+    name: string
+    rows: { row_id?: string; controls: PanelControl[] }[]
+}
+
+// Loads the layout of the panel from the server
+export async function loadPanel(name: string): Promise<ControlsDefinition> {
+    const response = await fetch(`panels/${encodeURIComponent(name)}`)
+    if (!response.ok) {
+        throw new Error(`Loading panel ${name} failed: ${response.status}`)
+    }
+
+    const panel = (await response.json()) as Panel
+    return panel.rows.map((row) => ({
+        rowId: row.row_id,
+        controls: row.controls.map(toControlDefinition),
+    }))
+}
+
+function toControlDefinition(control: PanelControl): ControlDefinition {
+    switch (control.type) {
+        case "button":
+            return {
+                type: "button",
+                incomingDataKey: control.data_key,
+                outgoingToggleCommand: control.command,
+                text: control.text,
+            }
+        case "encoder":
+            return {
+                type: "encoder",
+                incomingDataKey: control.data_key,
+                rightCommand: control.cmd_right,
+                leftCommand: control.cmd_left,
+                pushCommand: control.cmd_push,
+                text: control.text,
+            }
+        case "gear":
+            return { type: "gear" }
+        case "flaps":
+            return { type: "flaps" }
+    }
+}
@@ -1,5 +1,7 @@
-import { ControlDefinition, toggleButtons } from "../controls-definition"
+import { ControlDefinition } from "../controls-definition"
 import { FlightDataValues } from "../hooks/use-flight-data"
+import { usePanelControls } from "../hooks/use-panel-controls"
+import { EncoderControl } from "./encoder-control"
 import { FlapsControl } from "./flaps-control"
 import { FlightDataToggleButton } from "./flight-data-toggle-button"
 import { GearControl } from "./gear-control"
@@ -9,9 +11,11 @@ export function ControlButtons({
 }: {
     flightData: FlightDataValues
 }) {
+    const controls = usePanelControls()
+
     return (
         <div className="controls">
-            {toggleButtons.map((row, rowIndex) => (
+            {controls.map((row, rowIndex) => (
                 <div key={`controls-${rowIndex}`} className="controls-row">
                     {row.controls.map((control, colIndex) => (
                         <Control
@@ -43,6 +47,8 @@ function Control({
                     flightData={flightData}
                 />
             )
+        case "encoder":
+            return <EncoderControl control={control} flightData={flightData} />
         case "gear":
             return <GearControl flightData={flightData} />
         case "flaps":
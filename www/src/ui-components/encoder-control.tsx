@@ -0,0 +1,46 @@
+import { EncoderDefinition } from "../controls-definition"
+import { FlightDataValues } from "../hooks/use-flight-data"
+import { sendSocket } from "../websocket"
+import { AnnuniciatorContainer } from "./annunciator-container"
+import { ToggleButton } from "./toggle-button"
+
+export function EncoderControl({
+    control,
+    flightData,
+}: {
+    control: EncoderDefinition
+    flightData: FlightDataValues
+}) {
+    const value =
+        control.incomingDataKey !== undefined
+            ? flightData[control.incomingDataKey]
+            : undefined
+    const pushCommand = control.pushCommand
+
+    return (
+        <div className="control-container">
+            <ToggleButton
+                isOn={false}
+                buttonText="◀"
+                onClick={() => sendSocket({ command: control.leftCommand })}
+            />
+            <AnnuniciatorContainer title={control.text}>
+                <div
+                    className="control-annunciator-value"
+                    onClick={
+                        pushCommand
+                            ? () => sendSocket({ command: pushCommand })
+                            : undefined
+                    }
+                >
+                    {value === undefined ? "—" : String(value)}
+                </div>
+            </AnnuniciatorContainer>
+            <ToggleButton
+                isOn={false}
+                buttonText="▶"
+                onClick={() => sendSocket({ command: control.rightCommand })}
+            />
+        </div>
+    )
+}
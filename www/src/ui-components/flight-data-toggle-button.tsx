@@ -1,15 +1,16 @@
-import { CommandValue } from "../controls-definition"
 import { FlightDataValues } from "../hooks/use-flight-data"
 import { sendSocket } from "../websocket"
 import { ToggleButton } from "./toggle-button"
 
 export function FlightDataToggleButton(props: {
-    incomingDataKey: string
-    outgoingToggleCommand: CommandValue
+    incomingDataKey?: string
+    outgoingToggleCommand: string
     buttonText: string
     flightData: FlightDataValues
 }) {
-    const isOn = !!props.flightData[props.incomingDataKey]
+    const isOn =
+        props.incomingDataKey !== undefined &&
+        !!props.flightData[props.incomingDataKey]
 
     function handleClick() {
         sendSocket({ command: props.outgoingToggleCommand })
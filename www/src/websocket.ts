@@ -5,7 +5,11 @@ let reconnectTimer: number | null = null
 
 export function startWebsocket() {
     function connect() {
-        const ws = new WebSocket(`ws://${location.host}/websocket`)
+        // Relative to the page, so that a page under /connections/<name>/
+        // gets the data of that connection
+        const url = new URL("websocket", location.href)
+        url.protocol = url.protocol === "https:" ? "wss:" : "ws:"
+        const ws = new WebSocket(url)
 
         ws.addEventListener("open", () => {
             console.log("Websocket opened")